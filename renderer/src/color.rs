@@ -0,0 +1,169 @@
+//
+// CSS-style color string parsing
+//
+
+use phf::phf_map;
+use regex_macro::regex;
+use tiny_skia::Color;
+
+static NAMED_COLORS: phf::Map<&'static str, &'static str> = phf_map! {
+    "none" => "#0000",
+    "transparent" => "#0000",
+    "black" => "#000",
+    "white" => "#fff",
+    "red" => "#f00",
+    "green" => "#008000",
+    "blue" => "#00f",
+    "purple" => "#800080",
+    "darkturquoise" => "#00ced1",
+    "tomato" => "#ff6347",
+    "lawngreen" => "#7cfc00",
+    "orange" => "#ffa500",
+    "brown" => "#a52a2a",
+    "lightgreen" => "#90ee90",
+};
+
+pub(crate) fn unparse_color(c: &Color) -> String {
+    return format!(
+        "#{:02x}{:02x}{:02x}{:02x}",
+        (c.red() * 255.0) as u8,
+        (c.green() * 255.0) as u8,
+        (c.blue() * 255.0) as u8,
+        (c.alpha() * 255.0) as u8
+    );
+}
+
+/// Parse CSS-style color string into Color
+///
+/// Unknown formats give black.
+pub fn parse_color(text: &str) -> Option<Color> {
+    let mut current_text = text;
+    // First do named color substitution
+    if let Some(new_text) = NAMED_COLORS.get(text) {
+        current_text = new_text;
+    }
+    // Failure to compile any regex expression is legitimate bug, use unwrap()
+    // Any failures in hex parsing propagate to None return value
+    if let Some(captures) = regex!(r"^#(.)(.)(.)$").captures(current_text) {
+        let r = u8::from_str_radix(&captures[1], 16).ok()? * 17;
+        let g = u8::from_str_radix(&captures[2], 16).ok()? * 17;
+        let b = u8::from_str_radix(&captures[3], 16).ok()? * 17;
+        return Color::from_rgba(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0);
+    }
+    if let Some(captures) = regex!(r"^#(.)(.)(.)(.)$").captures(current_text) {
+        let r = u8::from_str_radix(&captures[1], 16).ok()? * 17;
+        let g = u8::from_str_radix(&captures[2], 16).ok()? * 17;
+        let b = u8::from_str_radix(&captures[3], 16).ok()? * 17;
+        let a = u8::from_str_radix(&captures[4], 16).ok()? * 17;
+        return Color::from_rgba(
+            r as f32 / 255.0,
+            g as f32 / 255.0,
+            b as f32 / 255.0,
+            a as f32 / 255.0,
+        );
+    }
+    if let Some(captures) = regex!(r"^#(..)(..)(..)$").captures(current_text) {
+        let r = u8::from_str_radix(&captures[1], 16).ok()?;
+        let g = u8::from_str_radix(&captures[2], 16).ok()?;
+        let b = u8::from_str_radix(&captures[3], 16).ok()?;
+        return Color::from_rgba(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0);
+    }
+    if let Some(captures) = regex!(r"^#(..)(..)(..)(..)$").captures(current_text) {
+        let r = u8::from_str_radix(&captures[1], 16).ok()?;
+        let g = u8::from_str_radix(&captures[2], 16).ok()?;
+        let b = u8::from_str_radix(&captures[3], 16).ok()?;
+        let a = u8::from_str_radix(&captures[4], 16).ok()?;
+        return Color::from_rgba(
+            r as f32 / 255.0,
+            g as f32 / 255.0,
+            b as f32 / 255.0,
+            a as f32 / 255.0,
+        );
+    }
+    if let Some(captures) =
+        regex!(r"^rgb\(\s*(\d+)\s*,\s*(\d+)\s*,\s*(\d+)\s*\)$").captures(current_text)
+    {
+        // Note change to radix 10
+        let r = u8::from_str_radix(&captures[1], 10).ok()?;
+        let g = u8::from_str_radix(&captures[2], 10).ok()?;
+        let b = u8::from_str_radix(&captures[3], 10).ok()?;
+        return Color::from_rgba(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0);
+    }
+    if let Some(captures) =
+        regex!(r"^rgba\(\s*(\d+)\s*,\s*(\d+)\s*,\s*(\d+)\s*,\s*(\d*(\.\d*)?)\s*\)$")
+            .captures(current_text)
+    {
+        // Note change to radix 10
+        let r = u8::from_str_radix(&captures[1], 10).ok()?;
+        let g = u8::from_str_radix(&captures[2], 10).ok()?;
+        let b = u8::from_str_radix(&captures[3], 10).ok()?;
+        let a: f32 = captures[4].parse().ok()?;
+        return Color::from_rgba(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, a);
+    }
+    return Color::from_rgba(0.0, 0.0, 0.0, 1.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("black"), Color::from_rgba(0.0, 0.0, 0.0, 1.0));
+        assert_eq!(parse_color("blue"), Color::from_rgba(0.0, 0.0, 1.0, 1.0));
+        assert_eq!(parse_color("#f00"), Color::from_rgba(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(parse_color("#0f0"), Color::from_rgba(0.0, 1.0, 0.0, 1.0));
+        assert_eq!(parse_color("#00f"), Color::from_rgba(0.0, 0.0, 1.0, 1.0));
+        assert_eq!(parse_color("#f000"), Color::from_rgba(1.0, 0.0, 0.0, 0.0));
+        assert_eq!(parse_color("#0f00"), Color::from_rgba(0.0, 1.0, 0.0, 0.0));
+        assert_eq!(parse_color("#00f0"), Color::from_rgba(0.0, 0.0, 1.0, 0.0));
+        assert_eq!(parse_color("#000f"), Color::from_rgba(0.0, 0.0, 0.0, 1.0));
+        assert_eq!(parse_color("#ff0000"), Color::from_rgba(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(parse_color("#00ff00"), Color::from_rgba(0.0, 1.0, 0.0, 1.0));
+        assert_eq!(parse_color("#0000ff"), Color::from_rgba(0.0, 0.0, 1.0, 1.0));
+        assert_eq!(
+            parse_color("#ff000000"),
+            Color::from_rgba(1.0, 0.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            parse_color("#00ff0000"),
+            Color::from_rgba(0.0, 1.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            parse_color("#0000ff00"),
+            Color::from_rgba(0.0, 0.0, 1.0, 0.0)
+        );
+        assert_eq!(
+            parse_color("#000000ff"),
+            Color::from_rgba(0.0, 0.0, 0.0, 1.0)
+        );
+        assert_eq!(
+            parse_color("#800000"),
+            Color::from_rgba((8.0 * 16.0 / 255.0) as f32, 0.0, 0.0, 1.0)
+        );
+        assert_eq!(
+            parse_color("rgb(255,0,0)"),
+            Color::from_rgba(1.0, 0.0, 0.0, 1.0)
+        );
+        assert_eq!(
+            parse_color("rgba(0,255,0,0.5)"),
+            Color::from_rgba(0.0, 1.0, 0.0, 0.5)
+        );
+        assert_eq!(
+            parse_color("rgba(0,255,0,.5)"),
+            Color::from_rgba(0.0, 1.0, 0.0, 0.5)
+        );
+    }
+
+    #[test]
+    fn test_unparse_color() {
+        assert_eq!(
+            unparse_color(&Color::from_rgba(0.0, 0.0, 0.0, 1.0).unwrap()),
+            "#000000ff"
+        );
+        assert_eq!(
+            unparse_color(&Color::from_rgba(80.0 / 255.0, 0.0, 0.0, 80.0 / 255.0).unwrap()),
+            "#50000050"
+        );
+    }
+}
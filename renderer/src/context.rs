@@ -0,0 +1,1738 @@
+//
+// Drawing context that rasterizes CanvasRenderingContext2D calls
+//
+
+use crate::color::{parse_color, unparse_color};
+use crate::display_list::{
+    blend_mode_name, line_cap_name, line_join_name, parse_blend_mode, parse_fill_rule,
+    parse_line_cap, parse_line_join, ClipPath, Compositing, DisplayList, DrawCommand, Shadow,
+    StrokeStyle, TextGlyph,
+};
+use crate::filter::{parse_filter, Filter};
+use crate::font::{
+    direction_name, parse_direction, parse_font, parse_text_align, parse_text_baseline,
+    text_align_name, text_baseline_name, text_offset, unparse_font, Direction, FontInfo,
+    FontLibrary, FontMetrics, TextAlign, TextBaseline,
+};
+use crate::image::{
+    image_data_size, pixmap_rgba, png_data_url, rgba_length, ImageData, ImageSource,
+};
+use crate::paint::{
+    parse_repetition, unparse_paint, CanvasGradient, CanvasPattern, Gradient, GradientShape,
+    PaintStyle, Pattern, Repetition, StyleValue,
+};
+use crate::path::{
+    path_contains, push_arc, push_arc_to, push_ellipse, push_rect, push_round_rect, CornerRadii,
+    PathArg, PathOr,
+};
+use crate::raster::{clip_mask, draw_clipped};
+use crate::trace::{path_arg, Trace, TraceCall, TraceState};
+use ab_glyph::ScaleFont;
+use rquickjs::class::Trace as JsTrace;
+use rquickjs::function::{Opt, Rest};
+use rquickjs::{Class, Ctx, FromJs};
+use serde_json::{json, Value};
+use std::rc::Rc;
+use tiny_skia::{
+    BlendMode, Color, IntRect, LineCap, LineJoin, Mask, PathBuilder, Pixmap, Rect, Transform,
+};
+
+/// Drawing state is part of the context
+#[derive(Clone, Debug)]
+pub struct DrawState {
+    line_width: f64,
+    /// Dash lengths alternating drawn and skipped, empty for solid lines
+    line_dash: Vec<f64>,
+    line_dash_offset: f64,
+    line_cap: LineCap,
+    line_join: LineJoin,
+    miter_limit: f64,
+    fill_style: PaintStyle,
+    stroke_style: PaintStyle,
+    /// Opacity from 0 to 1 multiplied into everything drawn
+    global_alpha: f64,
+    /// Blend mode from globalCompositeOperation
+    blend_mode: BlendMode,
+    shadow_color: Color,
+    /// Shadow blur and offsets are in CSS pixels, not affected by transform
+    shadow_blur: f64,
+    shadow_offset_x: f64,
+    shadow_offset_y: f64,
+    /// CSS filter string as set, for reading back
+    filter: String,
+    /// Filter functions of filter string, lengths in CSS pixels
+    filters: Vec<Filter>,
+    clear_style: Color,
+    font: FontInfo,
+    text_align: TextAlign,
+    text_baseline: TextBaseline,
+    direction: Direction,
+    transform: Transform,
+    /// Paths set with clip(), drawing is clipped to all of them
+    clip: Vec<ClipPath>,
+    /// Mask for surface covering the clip paths, shared by saved states
+    clip_mask: Option<Rc<Mask>>,
+}
+
+/// A drawing context exposed to JS for rendering.
+///
+/// Owns its own surface with pixel data.
+#[derive(JsTrace)]
+#[rquickjs::class]
+pub(crate) struct DrawContext {
+    /// Width in pixels of surface
+    width: u32,
+    /// Height in pixels of surface
+    height: u32,
+    /// Pixel data for image
+    #[qjs(skip_trace)]
+    surface: Pixmap,
+    /// Current path being constructed with drawing commands
+    #[qjs(skip_trace)]
+    path: Option<PathBuilder>,
+    /// Font library for resolving codepoints
+    #[qjs(skip_trace)]
+    font_library: FontLibrary,
+    /// Drawing state
+    #[qjs(skip_trace)]
+    draw_state: DrawState,
+    /// Save/Restore stack
+    #[qjs(skip_trace)]
+    stack: Vec<DrawState>,
+    /// Every drawing operation done so far
+    #[qjs(skip_trace)]
+    display_list: DisplayList,
+    /// Calls made from JavaScript, when tracing
+    #[qjs(skip_trace)]
+    trace: Option<Trace>,
+}
+
+/// Convert rect xywh coordinates to have positive width and height
+fn normalized_rect(x: f64, y: f64, width: f64, height: f64) -> Rect {
+    let xx = if width < 0.0 { x + width } else { x };
+    let yy = if height < 0.0 { y + height } else { y };
+    return Rect::from_xywh(
+        xx as f32,
+        yy as f32,
+        width.abs() as f32,
+        height.abs() as f32,
+    )
+    .unwrap();
+}
+
+/// Path and fill rule from arguments of fill() or clip(), which are either
+/// (fillRule) or (path, fillRule) with fillRule defaulting to "nonzero"
+fn path_and_fill_rule(
+    first: Opt<PathOr<String>>,
+    fill_rule: Opt<String>,
+) -> (Option<PathBuilder>, String) {
+    let (path, fill_rule) = match first.0 {
+        Some(PathOr::Path(path)) => (Some(path), fill_rule.0),
+        Some(PathOr::Other(fill_rule)) => (None, Some(fill_rule)),
+        None => (None, None),
+    };
+    return (path, fill_rule.unwrap_or_else(|| "nonzero".to_string()));
+}
+
+/// Trace arguments for a call with optional path and fill rule
+fn path_call_args(path: &Option<PathBuilder>, fill_rule: &str) -> Value {
+    return match path {
+        Some(path) => json!([path_arg(path), fill_rule]),
+        None => json!([fill_rule]),
+    };
+}
+
+impl DrawContext {
+    /// Same as new(), but with an error for bad colors or sizes instead of a panic
+    pub(crate) fn try_new(
+        width: u32,
+        height: u32,
+        zoom: f64,
+        background: String,
+        foreground: String,
+    ) -> Result<Self, String> {
+        let fill_style = parse_color(&foreground)
+            .ok_or_else(|| format!("Invalid foreground color '{}'", foreground))?;
+        let stroke_style = fill_style;
+        let clear_style = parse_color(&background)
+            .ok_or_else(|| format!("Invalid background color '{}'", background))?;
+        let surface = Pixmap::new((width as f64 * zoom) as u32, (height as f64 * zoom) as u32)
+            .ok_or_else(|| format!("Could not create surface of size {}x{}", width, height))?;
+        let transform = Transform::identity().post_scale(zoom as f32, zoom as f32);
+        // // Optional subpixel translation to make staff lines sharper (but still 2 pixels wide)
+        // .post_translate(0.0 as f32, 0.3 as f32);
+        let mut context = DrawContext {
+            width,
+            height,
+            surface,
+            path: None,
+            font_library: FontLibrary::new(),
+            draw_state: DrawState {
+                line_width: 1.0,
+                line_dash: vec![],
+                line_dash_offset: 0.0,
+                line_cap: LineCap::Butt,
+                line_join: LineJoin::Miter,
+                miter_limit: 10.0,
+                fill_style: PaintStyle::Color(fill_style),
+                stroke_style: PaintStyle::Color(stroke_style),
+                global_alpha: 1.0,
+                blend_mode: BlendMode::SourceOver,
+                shadow_color: Color::TRANSPARENT,
+                shadow_blur: 0.0,
+                shadow_offset_x: 0.0,
+                shadow_offset_y: 0.0,
+                filter: "none".to_string(),
+                filters: vec![],
+                clear_style,
+                font: FontInfo {
+                    family: vec![],
+                    size: 7.0,
+                    bold: false,
+                    italic: false,
+                },
+                text_align: TextAlign::Start,
+                text_baseline: TextBaseline::Alphabetic,
+                direction: Direction::Inherit,
+                transform,
+                clip: vec![],
+                clip_mask: None,
+            },
+            stack: vec![],
+            display_list: DisplayList::new(width, height, zoom),
+            trace: None,
+        };
+        context.execute(DrawCommand::Clear { color: clear_style });
+        return Ok(context);
+    }
+
+    /// Pixel data drawn so far
+    pub fn surface(&self) -> &Pixmap {
+        return &self.surface;
+    }
+
+    /// Remap codepoints to fixup some issues
+    fn remap_codepoint(codepoint: u32) -> u32 {
+        match codepoint {
+            // Map "White Up-Pointing Triangle" to SMuFL "csymMajorSeventh"
+            0x25b3 => 0xe873,
+            // Map "Latin Small Letter O with Stroke" to SMuFL "csymHalfDiminished"
+            0x00f8 => 0xe871,
+            // Map "White Circle" to SMuFL "csymDiminished"
+            0x25cb => 0xe870,
+            // Map missing SMuFL codepoints to space to avoid warnings for known ones
+            0xe31a => 0x20,
+            0xe31b => 0x20,
+            0xe3de => 0x20,
+            0xe3df => 0x20,
+            _ => codepoint,
+        }
+    }
+
+    /// Every drawing operation done so far, for output in other formats
+    pub fn display_list(&self) -> &DisplayList {
+        return &self.display_list;
+    }
+
+    /// Calls made so far, if tracing was started
+    pub fn trace(&self) -> Option<&Trace> {
+        return self.trace.as_ref();
+    }
+
+    /// Copy of surface for use as an image, covering the size without zoom
+    pub(crate) fn image_source(&self) -> ImageSource {
+        return ImageSource {
+            pixmap: self.surface.clone(),
+            width: self.width as f32,
+            height: self.height as f32,
+        };
+    }
+
+    /// Whether there is a current path, which beginPath() starts
+    pub(crate) fn has_path(&self) -> bool {
+        return self.path.is_some();
+    }
+
+    /// Path given to a drawing method, or else the current path
+    fn path_or_current(&self, path: Option<PathBuilder>) -> PathBuilder {
+        return path.unwrap_or_else(|| self.path.clone().expect("path must be created"));
+    }
+
+    /// Record call in trace along with the current drawing state.
+    fn trace_call(&mut self, method: &str, args: Value) {
+        let Some(trace) = &mut self.trace else {
+            return;
+        };
+        let state = &self.draw_state;
+        let t = &state.transform;
+        trace.calls.push(TraceCall {
+            method: method.to_string(),
+            args: match args {
+                Value::Array(args) => args,
+                arg => vec![arg],
+            },
+            state: TraceState {
+                line_width: state.line_width,
+                line_dash: state.line_dash.clone(),
+                line_dash_offset: state.line_dash_offset,
+                line_cap: line_cap_name(state.line_cap).to_string(),
+                line_join: line_join_name(state.line_join).to_string(),
+                miter_limit: state.miter_limit,
+                fill_style: unparse_paint(&state.fill_style),
+                stroke_style: unparse_paint(&state.stroke_style),
+                global_alpha: state.global_alpha,
+                global_composite_operation: blend_mode_name(state.blend_mode).to_string(),
+                shadow_color: unparse_color(&state.shadow_color),
+                shadow_blur: state.shadow_blur,
+                shadow_offset_x: state.shadow_offset_x,
+                shadow_offset_y: state.shadow_offset_y,
+                filter: state.filter.clone(),
+                font: unparse_font(&state.font),
+                text_align: text_align_name(state.text_align).to_string(),
+                text_baseline: text_baseline_name(state.text_baseline).to_string(),
+                direction: direction_name(state.direction).to_string(),
+                transform: vec![t.sx, t.kx, t.ky, t.sy, t.tx, t.ty]
+                    .into_iter()
+                    .map(f64::from)
+                    .collect(),
+                stack_depth: self.stack.len(),
+            },
+        });
+    }
+
+    /// Compositing settings of drawing state, for drawing commands
+    ///
+    /// Like in Canvas, shadows are only drawn when they are visible and
+    /// blurred or offset. Shadow and filter lengths are scaled by zoom.
+    fn compositing(&self) -> Compositing {
+        let state = &self.draw_state;
+        let zoom = self.display_list.zoom;
+        let visible = state.shadow_color.alpha() > 0.0
+            && (state.shadow_blur > 0.0
+                || state.shadow_offset_x != 0.0
+                || state.shadow_offset_y != 0.0);
+        return Compositing {
+            alpha: state.global_alpha as f32,
+            blend_mode: state.blend_mode,
+            shadow: visible.then_some(Shadow {
+                color: state.shadow_color,
+                blur: (state.shadow_blur * zoom) as f32,
+                offset_x: (state.shadow_offset_x * zoom) as f32,
+                offset_y: (state.shadow_offset_y * zoom) as f32,
+            }),
+            filter: state.filters.iter().map(|f| f.scaled(zoom as f32)).collect(),
+        };
+    }
+
+    /// Line settings of drawing state, for stroke commands
+    fn stroke_style(&self) -> StrokeStyle {
+        let state = &self.draw_state;
+        return StrokeStyle {
+            width: state.line_width as f32,
+            dash: state.line_dash.iter().map(|d| *d as f32).collect(),
+            dash_offset: state.line_dash_offset as f32,
+            line_cap: state.line_cap,
+            line_join: state.line_join,
+            miter_limit: state.miter_limit as f32,
+        };
+    }
+
+    /// Offset from x, y to the start of the baseline of text in font, for
+    /// textAlign, textBaseline and direction.
+    fn text_offset(&self, width: f32, font: &str) -> (f32, f32) {
+        let state = &self.draw_state;
+        let (ascent, descent) = self
+            .font_library
+            .vertical_metrics(font, state.font.size as f32);
+        return text_offset(
+            state.text_align,
+            state.text_baseline,
+            state.direction,
+            width,
+            ascent,
+            descent,
+        );
+    }
+
+    /// Glyphs of text in current font, aligned to x, y.
+    fn text_glyphs(&mut self, txt: &str, x: f64, y: f64) -> Vec<TextGlyph> {
+        let font = self.draw_state.font.clone();
+        let mut x_pos = x as f32;
+        let mut glyphs = vec![];
+        for ch in txt.chars() {
+            let codepoint = Self::remap_codepoint(ch as u32);
+            let glyph =
+                self.font_library
+                    .resolve_glyph(codepoint, font.size as f32, font.italic, font.bold);
+            if !glyph.has_outline && codepoint != 0x20 {
+                println!(r"*** Codepoint \u{:x}, no glyph found", codepoint);
+            }
+            glyphs.push(TextGlyph {
+                ch: char::from_u32(codepoint).unwrap_or(ch),
+                font: glyph.font.to_string(),
+                id: glyph.id,
+                advance: glyph.advance,
+                x: x_pos,
+                y: y as f32,
+            });
+            x_pos += glyph.width;
+        }
+        if let Some(first) = glyphs.first() {
+            let (dx, dy) = self.text_offset(x_pos - x as f32, &first.font);
+            for glyph in &mut glyphs {
+                glyph.x += dx;
+                glyph.y += dy;
+            }
+        }
+        return glyphs;
+    }
+
+    /// Measure text string from the left end of its baseline.
+    fn measure_string(&mut self, string: &str) -> FontMetrics {
+        let mut string_iter = string.chars();
+        // Get first character metrics
+        if let Some(first) = string_iter.next() {
+            let codepoint = first as u32;
+            let mut metrics = self.measure_char(codepoint);
+            // Keep going, just updating fields that might change with more chars
+            for ch in string_iter {
+                let extra_codepoint = ch as u32;
+                let extra_metrics = self.measure_char(extra_codepoint);
+                // Right bounding box is always related to the most recently added glyph.
+                metrics.actual_bounding_box_right =
+                    metrics.width + extra_metrics.actual_bounding_box_right;
+                // When sequencing multiple glyphs, we advance by width of each glyph, so just add it
+                metrics.width += extra_metrics.width;
+                // Ascent and Descent box grows to contain the text.
+                metrics.actual_bounding_box_ascent = f64::max(
+                    metrics.actual_bounding_box_ascent,
+                    extra_metrics.actual_bounding_box_ascent,
+                );
+                metrics.actual_bounding_box_descent = f64::max(
+                    metrics.actual_bounding_box_descent,
+                    extra_metrics.actual_bounding_box_descent,
+                );
+            }
+            return metrics;
+        }
+        // If we get here, we could not get first character
+        // Assume we want to measure null character
+        return self.measure_char(0);
+    }
+
+    /// Replace surface pixels at x, y with pixmap, recorded as putImageData.
+    ///
+    /// Position is in surface pixels. Drawn as a copy of a pattern, which only
+    /// replaces pixels under the rect, and not clipped like in Canvas.
+    pub(crate) fn put_pixmap(&mut self, pixmap: Pixmap, x: f64, y: f64) {
+        // Images are only encoded when there is a trace to keep them in
+        if self.trace.is_some() {
+            self.trace_call("putImageData", json!([png_data_url(&pixmap), x, y]));
+        }
+        let (width, height) = (pixmap.width() as f32, pixmap.height() as f32);
+        let Some(rect) = Rect::from_xywh(x as f32, y as f32, width, height) else {
+            return;
+        };
+        let command = DrawCommand::FillRect {
+            rect,
+            paint: PaintStyle::Pattern(Pattern {
+                image: pixmap,
+                width,
+                height,
+                repetition: Repetition::NoRepeat,
+                transform: Transform::from_translate(x as f32, y as f32),
+            }),
+            transform: Transform::identity(),
+            clip: vec![],
+            compositing: Compositing {
+                blend_mode: BlendMode::Source,
+                ..Compositing::default()
+            },
+        };
+        draw_clipped(&mut self.surface, &command, &self.font_library, None);
+        self.display_list.commands.push(command);
+    }
+
+    /// Draw command to surface and record it.
+    fn execute(&mut self, command: DrawCommand) {
+        draw_clipped(
+            &mut self.surface,
+            &command,
+            &self.font_library,
+            self.draw_state.clip_mask.as_deref(),
+        );
+        self.display_list.commands.push(command);
+    }
+}
+
+#[rquickjs::methods(rename_all = "camelCase")]
+impl DrawContext {
+    /// Create new image with zoom factor.
+    ///
+    /// Size of actual image is zoom factor multiplied by given width and
+    /// height. Example:
+    ///
+    /// ```text
+    /// DrawContext::new(100, 100, 2.0, "#fff", "#000")
+    /// ```
+    ///
+    /// The above creates an image of size 200x200.
+    ///
+    #[qjs(constructor)]
+    pub fn new(width: u32, height: u32, zoom: f64, background: String, foreground: String) -> Self {
+        return Self::try_new(width, height, zoom, background, foreground)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// Start recording calls made on this context.
+    ///
+    /// Should be called before anything else, so that replaying the trace
+    /// starts from the same state.
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Trace::new(
+            self.width,
+            self.height,
+            self.display_list.zoom,
+            unparse_color(&self.draw_state.clear_style),
+            unparse_paint(&self.draw_state.fill_style),
+        ));
+    }
+
+    /// Set fill to CSS color or gradient, invalid colors are ignored.
+    #[qjs(set, rename = "fillStyle")]
+    pub fn set_fill_style(&mut self, style: StyleValue) {
+        self.trace_call("fillStyle", json!([style]));
+        if let Some(paint) = style.to_paint() {
+            self.draw_state.fill_style = paint;
+        }
+    }
+
+    #[qjs(get, rename = "fillStyle")]
+    pub fn get_fill_style(&self) -> StyleValue {
+        return self.draw_state.fill_style.to_style_value();
+    }
+
+    /// Set stroke to CSS color or gradient, invalid colors are ignored.
+    #[qjs(set, rename = "strokeStyle")]
+    pub fn set_stroke_style(&mut self, style: StyleValue) {
+        self.trace_call("strokeStyle", json!([style]));
+        if let Some(paint) = style.to_paint() {
+            self.draw_state.stroke_style = paint;
+        }
+    }
+
+    #[qjs(get, rename = "strokeStyle")]
+    pub fn get_stroke_style(&self) -> StyleValue {
+        return self.draw_state.stroke_style.to_style_value();
+    }
+
+    /// Set opacity of everything drawn, values outside 0 to 1 are ignored like in Canvas
+    #[qjs(set, rename = "globalAlpha")]
+    pub fn set_global_alpha(&mut self, alpha: f64) {
+        self.trace_call("globalAlpha", json!([alpha]));
+        if (0.0..=1.0).contains(&alpha) {
+            self.draw_state.global_alpha = alpha;
+        }
+    }
+
+    #[qjs(get, rename = "globalAlpha")]
+    pub fn get_global_alpha(&self) -> f64 {
+        return self.draw_state.global_alpha;
+    }
+
+    /// Set how drawing combines with what is there, unknown names are ignored like in Canvas
+    #[qjs(set, rename = "globalCompositeOperation")]
+    pub fn set_global_composite_operation(&mut self, operation: String) {
+        self.trace_call("globalCompositeOperation", json!([operation]));
+        if let Some(mode) = parse_blend_mode(&operation) {
+            self.draw_state.blend_mode = mode;
+        }
+    }
+
+    #[qjs(get, rename = "globalCompositeOperation")]
+    pub fn get_global_composite_operation(&self) -> String {
+        return blend_mode_name(self.draw_state.blend_mode).to_string();
+    }
+
+    /// Set shadow to CSS color, invalid colors are ignored.
+    #[qjs(set, rename = "shadowColor")]
+    pub fn set_shadow_color(&mut self, color: String) {
+        self.trace_call("shadowColor", json!([color]));
+        if let Some(color) = parse_color(&color) {
+            self.draw_state.shadow_color = color;
+        }
+    }
+
+    #[qjs(get, rename = "shadowColor")]
+    pub fn get_shadow_color(&self) -> String {
+        return unparse_color(&self.draw_state.shadow_color);
+    }
+
+    /// Set how far shadows are blurred, negative values are ignored like in Canvas
+    #[qjs(set, rename = "shadowBlur")]
+    pub fn set_shadow_blur(&mut self, blur: f64) {
+        self.trace_call("shadowBlur", json!([blur]));
+        if blur >= 0.0 && blur.is_finite() {
+            self.draw_state.shadow_blur = blur;
+        }
+    }
+
+    #[qjs(get, rename = "shadowBlur")]
+    pub fn get_shadow_blur(&self) -> f64 {
+        return self.draw_state.shadow_blur;
+    }
+
+    #[qjs(set, rename = "shadowOffsetX")]
+    pub fn set_shadow_offset_x(&mut self, offset: f64) {
+        self.trace_call("shadowOffsetX", json!([offset]));
+        if offset.is_finite() {
+            self.draw_state.shadow_offset_x = offset;
+        }
+    }
+
+    #[qjs(get, rename = "shadowOffsetX")]
+    pub fn get_shadow_offset_x(&self) -> f64 {
+        return self.draw_state.shadow_offset_x;
+    }
+
+    #[qjs(set, rename = "shadowOffsetY")]
+    pub fn set_shadow_offset_y(&mut self, offset: f64) {
+        self.trace_call("shadowOffsetY", json!([offset]));
+        if offset.is_finite() {
+            self.draw_state.shadow_offset_y = offset;
+        }
+    }
+
+    #[qjs(get, rename = "shadowOffsetY")]
+    pub fn get_shadow_offset_y(&self) -> f64 {
+        return self.draw_state.shadow_offset_y;
+    }
+
+    /// Set CSS filter applied to everything drawn, invalid filters are ignored like in Canvas
+    #[qjs(set, rename = "filter")]
+    pub fn set_filter(&mut self, filter: String) {
+        self.trace_call("filter", json!([filter]));
+        if let Some(filters) = parse_filter(&filter) {
+            self.draw_state.filter = filter;
+            self.draw_state.filters = filters;
+        }
+    }
+
+    #[qjs(get, rename = "filter")]
+    pub fn get_filter(&self) -> String {
+        return self.draw_state.filter.clone();
+    }
+
+    /// Create gradient along line from (x0, y0) to (x1, y1).
+    pub fn create_linear_gradient(&self, x0: f64, y0: f64, x1: f64, y1: f64) -> CanvasGradient {
+        return CanvasGradient {
+            gradient: Gradient::new(GradientShape::Linear {
+                x0: x0 as f32,
+                y0: y0 as f32,
+                x1: x1 as f32,
+                y1: y1 as f32,
+            }),
+        };
+    }
+
+    /// Create gradient between two circles.
+    ///
+    /// Negative radii are ignored and give a gradient that draws nothing.
+    pub fn create_radial_gradient(
+        &self,
+        x0: f64,
+        y0: f64,
+        r0: f64,
+        x1: f64,
+        y1: f64,
+        r1: f64,
+    ) -> CanvasGradient {
+        if r0 < 0.0 || r1 < 0.0 {
+            println!("Negative radial gradient radius {} {}, ignoring", r0, r1);
+        }
+        return CanvasGradient {
+            gradient: Gradient::new(GradientShape::Radial {
+                x0: x0 as f32,
+                y0: y0 as f32,
+                r0: r0 as f32,
+                x1: x1 as f32,
+                y1: y1 as f32,
+                r1: r1 as f32,
+            }),
+        };
+    }
+
+    /// Create gradient sweeping clockwise around (x, y), starting at angle in radians.
+    pub fn create_conic_gradient(&self, angle: f64, x: f64, y: f64) -> CanvasGradient {
+        return CanvasGradient {
+            gradient: Gradient::new(GradientShape::Conic {
+                angle: angle as f32,
+                x: x as f32,
+                y: y as f32,
+            }),
+        };
+    }
+
+    /// Create pattern repeating image, or null for unknown repetition.
+    ///
+    /// Image can be an Image, a Canvas or a DrawContext, which is copied as
+    /// it is now.
+    pub fn create_pattern(
+        &self,
+        image: ImageSource,
+        repetition: Opt<Option<String>>,
+    ) -> Option<CanvasPattern> {
+        let repetition = repetition.0.flatten().unwrap_or_default();
+        let Some(repetition) = parse_repetition(&repetition) else {
+            println!("Unknown pattern repetition '{}', ignoring", repetition);
+            return None;
+        };
+        return Some(CanvasPattern {
+            pattern: Pattern {
+                image: image.pixmap,
+                width: image.width,
+                height: image.height,
+                repetition,
+                transform: Transform::identity(),
+            },
+        });
+    }
+
+    #[qjs(set, rename = "lineWidth")]
+    pub fn set_line_width(&mut self, width: f64) {
+        self.trace_call("lineWidth", json!([width]));
+        self.draw_state.line_width = width;
+    }
+
+    #[qjs(get, rename = "lineWidth")]
+    pub fn get_line_width(&self) -> f64 {
+        return self.draw_state.line_width;
+    }
+
+    /// Set shape of ends of stroked lines, invalid names are ignored like in Canvas
+    #[qjs(set, rename = "lineCap")]
+    pub fn set_line_cap(&mut self, cap: String) {
+        self.trace_call("lineCap", json!([cap]));
+        if let Some(cap) = parse_line_cap(&cap) {
+            self.draw_state.line_cap = cap;
+        }
+    }
+
+    #[qjs(get, rename = "lineCap")]
+    pub fn get_line_cap(&self) -> String {
+        return line_cap_name(self.draw_state.line_cap).to_string();
+    }
+
+    /// Set shape of corners of stroked lines, invalid names are ignored like in Canvas
+    #[qjs(set, rename = "lineJoin")]
+    pub fn set_line_join(&mut self, join: String) {
+        self.trace_call("lineJoin", json!([join]));
+        if let Some(join) = parse_line_join(&join) {
+            self.draw_state.line_join = join;
+        }
+    }
+
+    #[qjs(get, rename = "lineJoin")]
+    pub fn get_line_join(&self) -> String {
+        return line_join_name(self.draw_state.line_join).to_string();
+    }
+
+    /// Set miter limit, values that are not positive are ignored like in Canvas
+    #[qjs(set, rename = "miterLimit")]
+    pub fn set_miter_limit(&mut self, limit: f64) {
+        self.trace_call("miterLimit", json!([limit]));
+        if limit.is_finite() && limit > 0.0 {
+            self.draw_state.miter_limit = limit;
+        }
+    }
+
+    #[qjs(get, rename = "miterLimit")]
+    pub fn get_miter_limit(&self) -> f64 {
+        return self.draw_state.miter_limit;
+    }
+
+    #[qjs(set, rename = "font")]
+    pub fn set_font(&mut self, font: String) {
+        self.trace_call("font", json!([font]));
+        if let Some(font_info) = parse_font(&font) {
+            self.draw_state.font = font_info;
+        }
+    }
+
+    #[qjs(get, rename = "font")]
+    pub fn get_font(&self) -> String {
+        return "30pt Bravura,Academico".to_string();
+    }
+
+    /// Set horizontal alignment of text, invalid names are ignored like in Canvas
+    #[qjs(set, rename = "textAlign")]
+    pub fn set_text_align(&mut self, align: String) {
+        self.trace_call("textAlign", json!([align]));
+        if let Some(align) = parse_text_align(&align) {
+            self.draw_state.text_align = align;
+        }
+    }
+
+    #[qjs(get, rename = "textAlign")]
+    pub fn get_text_align(&self) -> String {
+        return text_align_name(self.draw_state.text_align).to_string();
+    }
+
+    /// Set vertical alignment of text, invalid names are ignored like in Canvas
+    #[qjs(set, rename = "textBaseline")]
+    pub fn set_text_baseline(&mut self, baseline: String) {
+        self.trace_call("textBaseline", json!([baseline]));
+        if let Some(baseline) = parse_text_baseline(&baseline) {
+            self.draw_state.text_baseline = baseline;
+        }
+    }
+
+    #[qjs(get, rename = "textBaseline")]
+    pub fn get_text_baseline(&self) -> String {
+        return text_baseline_name(self.draw_state.text_baseline).to_string();
+    }
+
+    /// Set text direction, inherit is the same as ltr since there is no document
+    #[qjs(set, rename = "direction")]
+    pub fn set_direction(&mut self, direction: String) {
+        self.trace_call("direction", json!([direction]));
+        if let Some(direction) = parse_direction(&direction) {
+            self.draw_state.direction = direction;
+        }
+    }
+
+    #[qjs(get, rename = "direction")]
+    pub fn get_direction(&self) -> String {
+        return direction_name(self.draw_state.direction).to_string();
+    }
+
+    /// Get the current graphical transform.
+    ///
+    /// Format is vector: [sx, kx, ky, sy, tx, ty]
+    ///
+    /// Matrix is:
+    ///
+    /// ```text
+    /// sx ky tx
+    /// kx sy ty
+    /// ```
+    ///
+    pub fn get_transform(&mut self) -> Vec<f64> {
+        return vec![
+            self.draw_state.transform.sx as f64,
+            self.draw_state.transform.kx as f64,
+            self.draw_state.transform.ky as f64,
+            self.draw_state.transform.sy as f64,
+            self.draw_state.transform.tx as f64,
+            self.draw_state.transform.ty as f64,
+        ];
+    }
+
+    /// Set the current graphical transform.
+    ///
+    /// Format is vector: [sx, kx, ky, sy, tx, ty]
+    pub fn set_transform(&mut self, t: Vec<f64>) {
+        self.trace_call("setTransform", json!([t]));
+        self.draw_state.transform = Transform {
+            sx: t[0] as f32,
+            kx: t[1] as f32,
+            ky: t[2] as f32,
+            sy: t[3] as f32,
+            tx: t[4] as f32,
+            ty: t[5] as f32,
+        }
+    }
+
+    /// Apply a scale to the current transformation
+    pub fn scale(&mut self, sx: f64, sy: f64) {
+        self.trace_call("scale", json!([sx, sy]));
+        self.draw_state.transform = self.draw_state.transform.post_scale(sx as f32, sy as f32);
+    }
+
+    /// Add a translation to the current transformation
+    pub fn translate(&mut self, x: f64, y: f64) {
+        self.trace_call("translate", json!([x, y]));
+        self.draw_state.transform = self
+            .draw_state
+            .transform
+            .post_translate(-x as f32, -y as f32);
+    }
+
+    /// Add a rotation to the current transformation
+    /// Angle is specified in radians.
+    pub fn rotate(&mut self, angle: f64) {
+        self.trace_call("rotate", json!([angle]));
+        self.draw_state.transform = self
+            .draw_state
+            .transform
+            .post_rotate(angle.to_degrees() as f32);
+    }
+
+    /// Measure a single glyph from a codepoint.
+    ///
+    /// Return value is scaled to screen pixel units.
+    pub fn measure_char(&mut self, codepoint: u32) -> FontMetrics {
+        let mapped_codepoint = Self::remap_codepoint(codepoint);
+        let (scaled_font, glyph) = self.font_library.lookup_glyph(
+            mapped_codepoint,
+            self.draw_state.font.size as f32,
+            self.draw_state.font.italic,
+            self.draw_state.font.bold,
+            0.0,
+            0.0,
+        );
+        let ascent = scaled_font.ascent();
+        let descent = scaled_font.descent();
+        let h_advance = scaled_font.h_advance(glyph.id);
+        // If it has a path, get bounds.
+        if let Some(g) = scaled_font.outline_glyph(glyph) {
+            let bounds = g.px_bounds();
+            // bounds from px_bounds() are negative to positive
+            // Just store positive part in FontMetrics.
+            return FontMetrics {
+                width: h_advance as f64,
+                font_bounding_box_ascent: -ascent as f64,
+                font_bounding_box_descent: descent as f64,
+                actual_bounding_box_ascent: -bounds.min.y as f64,
+                actual_bounding_box_descent: bounds.max.y as f64,
+                actual_bounding_box_left: -bounds.min.x as f64,
+                actual_bounding_box_right: bounds.max.x as f64,
+            };
+        }
+        // No path, return what we can from font info.
+        return FontMetrics {
+            width: h_advance as f64,
+            font_bounding_box_ascent: -ascent as f64,
+            font_bounding_box_descent: descent as f64,
+            actual_bounding_box_ascent: 0.0,
+            actual_bounding_box_descent: 0.0,
+            actual_bounding_box_left: 0.0,
+            actual_bounding_box_right: 0.0,
+        };
+    }
+
+    /// Measure text string, relative to the point textAlign and textBaseline
+    /// put at x, y.
+    pub fn measure_text(&mut self, string: String) -> FontMetrics {
+        let mut metrics = self.measure_string(&string);
+        let first = Self::remap_codepoint(string.chars().next().map_or(0, |ch| ch as u32));
+        let font = &self.draw_state.font;
+        let font = FontLibrary::font_name_for(first, font.italic, font.bold);
+        let (dx, dy) = self.text_offset(metrics.width as f32, font);
+        let (dx, dy) = (dx as f64, dy as f64);
+        metrics.actual_bounding_box_left -= dx;
+        metrics.actual_bounding_box_right += dx;
+        metrics.actual_bounding_box_ascent -= dy;
+        metrics.actual_bounding_box_descent += dy;
+        // Font box has always had the signs flipped from TextMetrics, keep them
+        metrics.font_bounding_box_ascent += dy;
+        metrics.font_bounding_box_descent -= dy;
+        return metrics;
+    }
+
+    /// Draw text string at fixed position with given color.
+    pub fn fill_text(&mut self, txt: String, x: f64, y: f64) {
+        self.trace_call("fillText", json!([txt, x, y]));
+        let glyphs = self.text_glyphs(&txt, x, y);
+        self.execute(DrawCommand::FillText {
+            glyphs,
+            size: self.draw_state.font.size as f32,
+            paint: self.draw_state.fill_style.clone(),
+            transform: self.draw_state.transform,
+            clip: self.draw_state.clip.clone(),
+            compositing: self.compositing(),
+        });
+    }
+
+    /// Draw outlines of text glyphs with stroke style and line settings.
+    pub fn stroke_text(&mut self, txt: String, x: f64, y: f64) {
+        self.trace_call("strokeText", json!([txt, x, y]));
+        let glyphs = self.text_glyphs(&txt, x, y);
+        self.execute(DrawCommand::StrokeText {
+            glyphs,
+            size: self.draw_state.font.size as f32,
+            paint: self.draw_state.stroke_style.clone(),
+            stroke: self.stroke_style(),
+            transform: self.draw_state.transform,
+            clip: self.draw_state.clip.clone(),
+            compositing: self.compositing(),
+        });
+    }
+
+    /// Save image to a file.
+    ///
+    /// As a convenience, creates parent directories of file if needed.
+    pub fn save_png(&mut self, filename: String) {
+        let filepath = std::path::Path::new(&filename);
+        if let Some(p) = filepath.parent() {
+            std::fs::create_dir_all(p).expect("Could not create directory");
+        };
+        self.surface.save_png(filename).unwrap();
+    }
+
+    pub fn begin_path(&mut self) {
+        self.trace_call("beginPath", json!([]));
+        self.path = Some(PathBuilder::new());
+    }
+
+    pub fn move_to(&mut self, x: f64, y: f64) {
+        self.trace_call("moveTo", json!([x, y]));
+        assert!(self.path.is_some());
+        self.path
+            .as_mut()
+            .expect("path must be created")
+            .move_to(x as f32, y as f32);
+    }
+
+    pub fn line_to(&mut self, x: f64, y: f64) {
+        self.trace_call("lineTo", json!([x, y]));
+        assert!(self.path.is_some());
+        self.path
+            .as_mut()
+            .expect("path must be created")
+            .line_to(x as f32, y as f32);
+    }
+
+    pub fn close_path(&mut self) {
+        self.trace_call("closePath", json!([]));
+        assert!(self.path.is_some());
+        self.path.as_mut().expect("path must be created").close();
+    }
+
+    pub fn quadratic_curve_to(&mut self, x1: f64, y1: f64, x: f64, y: f64) {
+        self.trace_call("quadraticCurveTo", json!([x1, y1, x, y]));
+        assert!(self.path.is_some());
+        self.path
+            .as_mut()
+            .expect("path must be created")
+            .quad_to(x1 as f32, y1 as f32, x as f32, y as f32);
+    }
+
+    /// Add arc of circle to current path, joined to current point by a line
+    pub fn arc(
+        &mut self,
+        x: f64,
+        y: f64,
+        radius: f64,
+        start_angle: f64,
+        end_angle: f64,
+        counterclockwise: Opt<bool>,
+    ) {
+        let counterclockwise = counterclockwise.0.unwrap_or(false);
+        self.trace_call(
+            "arc",
+            json!([x, y, radius, start_angle, end_angle, counterclockwise]),
+        );
+        assert!(self.path.is_some());
+        if let Err(e) = push_arc(
+            self.path.as_mut().expect("path must be created"),
+            x,
+            y,
+            radius,
+            start_angle,
+            end_angle,
+            counterclockwise,
+        ) {
+            println!("{}, ignoring", e);
+        }
+    }
+
+    /// Add arc of ellipse to current path, joined to current point by a line
+    #[allow(clippy::too_many_arguments)]
+    pub fn ellipse(
+        &mut self,
+        x: f64,
+        y: f64,
+        radius_x: f64,
+        radius_y: f64,
+        rotation: f64,
+        start_angle: f64,
+        end_angle: f64,
+        counterclockwise: Opt<bool>,
+    ) {
+        let counterclockwise = counterclockwise.0.unwrap_or(false);
+        self.trace_call(
+            "ellipse",
+            json!([x, y, radius_x, radius_y, rotation, start_angle, end_angle, counterclockwise]),
+        );
+        assert!(self.path.is_some());
+        if let Err(e) = push_ellipse(
+            self.path.as_mut().expect("path must be created"),
+            x,
+            y,
+            radius_x,
+            radius_y,
+            rotation,
+            start_angle,
+            end_angle,
+            counterclockwise,
+        ) {
+            println!("{}, ignoring", e);
+        }
+    }
+
+    /// Add arc with given radius joining line to (x1, y1) and line from there to (x2, y2)
+    pub fn arc_to(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, radius: f64) {
+        self.trace_call("arcTo", json!([x1, y1, x2, y2, radius]));
+        assert!(self.path.is_some());
+        let path = self.path.as_mut().expect("path must be created");
+        if let Err(e) = push_arc_to(path, x1, y1, x2, y2, radius) {
+            println!("{}, ignoring", e);
+        }
+    }
+
+    /// Add rectangle with rounded corners to current path
+    pub fn round_rect(&mut self, x: f64, y: f64, width: f64, height: f64, radii: Opt<CornerRadii>) {
+        let radii = radii.0.unwrap_or(CornerRadii(vec![(0.0, 0.0)]));
+        self.trace_call("roundRect", json!([x, y, width, height, radii.0]));
+        assert!(self.path.is_some());
+        let path = self.path.as_mut().expect("path must be created");
+        if let Err(e) = push_round_rect(path, x, y, width, height, &radii) {
+            println!("{}, ignoring", e);
+        }
+    }
+
+    /// Add rectangle to current path, width and height may be negative
+    pub fn rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        self.trace_call("rect", json!([x, y, width, height]));
+        assert!(self.path.is_some());
+        push_rect(
+            self.path.as_mut().expect("path must be created"),
+            x,
+            y,
+            width,
+            height,
+        );
+    }
+
+    pub fn bezier_curve_to(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, x: f64, y: f64) {
+        self.trace_call("bezierCurveTo", json!([x1, y1, x2, y2, x, y]));
+        assert!(self.path.is_some());
+        self.path.as_mut().expect("path must be created").cubic_to(
+            x1 as f32, y1 as f32, x2 as f32, y2 as f32, x as f32, y as f32,
+        );
+    }
+
+    /// Stroke current path or the Path2D given
+    pub fn stroke(&mut self, path: Opt<PathArg>) {
+        let path = path.0.map(|p| p.0);
+        let args: Vec<Value> = path.iter().map(path_arg).collect();
+        self.trace_call("stroke", json!(args));
+        let Some(final_path) = self.path_or_current(path).finish() else {
+            return;
+        };
+        self.execute(DrawCommand::StrokePath {
+            path: final_path,
+            paint: self.draw_state.stroke_style.clone(),
+            stroke: self.stroke_style(),
+            transform: self.draw_state.transform,
+            clip: self.draw_state.clip.clone(),
+            compositing: self.compositing(),
+        });
+    }
+
+    /// Fill current path or the Path2D given, with "nonzero" or "evenodd" rule.
+    ///
+    /// Either `fill(fillRule)` or `fill(path, fillRule)`. Unknown fill rules
+    /// are ignored.
+    pub fn fill(&mut self, path: Opt<PathOr<String>>, fill_rule: Opt<String>) {
+        let (path, fill_rule) = path_and_fill_rule(path, fill_rule);
+        self.trace_call("fill", path_call_args(&path, &fill_rule));
+        let Some(rule) = parse_fill_rule(&fill_rule) else {
+            println!("Unknown fill rule '{}', ignoring", fill_rule);
+            return;
+        };
+        let Some(final_path) = self.path_or_current(path).finish() else {
+            return;
+        };
+        self.execute(DrawCommand::FillPath {
+            path: final_path,
+            paint: self.draw_state.fill_style.clone(),
+            fill_rule: rule,
+            transform: self.draw_state.transform,
+            clip: self.draw_state.clip.clone(),
+            compositing: self.compositing(),
+        });
+    }
+
+    /// Clip drawing to current path or the Path2D given as well, filled with
+    /// "nonzero" or "evenodd" rule.
+    ///
+    /// Like fill() the path is closed, and the clip is kept in the drawing
+    /// state so restore() undoes it. Unknown fill rules are ignored.
+    pub fn clip(&mut self, path: Opt<PathOr<String>>, fill_rule: Opt<String>) {
+        let (path, fill_rule) = path_and_fill_rule(path, fill_rule);
+        self.trace_call("clip", path_call_args(&path, &fill_rule));
+        let Some(rule) = parse_fill_rule(&fill_rule) else {
+            println!("Unknown clip fill rule '{}', ignoring", fill_rule);
+            return;
+        };
+        // Clipping to an empty path leaves nothing to draw in
+        let empty = Rect::from_xywh(0.0, 0.0, 0.0, 0.0).unwrap();
+        let path = self
+            .path_or_current(path)
+            .finish()
+            .unwrap_or_else(|| PathBuilder::from_rect(empty));
+        self.draw_state.clip.push(ClipPath {
+            path,
+            fill_rule: rule,
+            transform: self.draw_state.transform,
+        });
+        self.draw_state.clip_mask = clip_mask(
+            self.surface.width(),
+            self.surface.height(),
+            &self.draw_state.clip,
+        )
+        .map(Rc::new);
+    }
+
+    /// Whether point is inside the current path or the Path2D given, filled
+    /// with "nonzero" or "evenodd" rule.
+    ///
+    /// Either `isPointInPath(x, y, fillRule)` or `isPointInPath(path, x, y,
+    /// fillRule)`. The point is in canvas coordinates, unaffected by the
+    /// current transform, while the path is transformed like when drawing.
+    pub fn is_point_in_path<'js>(
+        &self,
+        first: PathOr<f64>,
+        second: f64,
+        third: Opt<rquickjs::Value<'js>>,
+        fourth: Opt<String>,
+    ) -> bool {
+        let third = third.0.filter(|v| !v.is_undefined());
+        let (path, x, y, fill_rule) = match first {
+            PathOr::Path(path) => {
+                let y = third.as_ref().and_then(rquickjs::Value::as_number);
+                (Some(path), second, y.unwrap_or(f64::NAN), fourth.0)
+            }
+            PathOr::Other(x) => {
+                let fill_rule = third.and_then(|v| v.as_string()?.to_string().ok());
+                (None, x, second, fill_rule)
+            }
+        };
+        let fill_rule = fill_rule.unwrap_or_else(|| "nonzero".to_string());
+        let Some(rule) = parse_fill_rule(&fill_rule) else {
+            println!("Unknown isPointInPath fill rule '{}', ignoring", fill_rule);
+            return false;
+        };
+        if path.is_none() && self.path.is_none() {
+            return false;
+        }
+        let Some(path) = self
+            .path_or_current(path)
+            .finish()
+            .and_then(|p| p.transform(self.draw_state.transform))
+        else {
+            return false;
+        };
+        let zoom = self.display_list.zoom;
+        return path_contains(&path, rule, (x * zoom) as f32, (y * zoom) as f32);
+    }
+
+    /// Draw filled rectangle over image
+    pub fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        self.trace_call("fillRect", json!([x, y, width, height]));
+        // Check for negative width/height, normalize
+        self.execute(DrawCommand::FillRect {
+            rect: normalized_rect(x, y, width, height),
+            paint: self.draw_state.fill_style.clone(),
+            transform: self.draw_state.transform,
+            clip: self.draw_state.clip.clone(),
+            compositing: self.compositing(),
+        });
+    }
+
+    /// Draw image, or part of it, into a rect.
+    ///
+    /// Arguments after the image are those of the Canvas forms: dx, dy, or
+    /// dx, dy, dw, dh, or sx, sy, sw, sh, dx, dy, dw, dh. The image is drawn
+    /// as a rect filled with a pattern of it, so it is transformed, clipped
+    /// and composited like any fill.
+    pub fn draw_image(&mut self, image: ImageSource, args: Rest<f64>) {
+        let args = args.0;
+        // Images are only encoded when there is a trace to keep them in
+        if self.trace.is_some() {
+            let mut call = vec![json!(image)];
+            call.extend(args.iter().map(|arg| json!(arg)));
+            self.trace_call("drawImage", Value::Array(call));
+        }
+        // Like in Canvas, calls with infinite or NaN arguments draw nothing
+        if args.iter().any(|arg| !arg.is_finite()) {
+            return;
+        }
+        let args: Vec<f32> = args.into_iter().map(|arg| arg as f32).collect();
+        let whole = [0.0, 0.0, image.width, image.height];
+        let (src, dst) = match args[..] {
+            [dx, dy] => (whole, [dx, dy, image.width, image.height]),
+            [dx, dy, dw, dh] => (whole, [dx, dy, dw, dh]),
+            [sx, sy, sw, sh, dx, dy, dw, dh] => ([sx, sy, sw, sh], [dx, dy, dw, dh]),
+            _ => {
+                println!("drawImage() takes 3, 5 or 9 arguments, ignoring");
+                return;
+            }
+        };
+        let Some((pattern, rect)) = image.draw_pattern(src, dst) else {
+            return;
+        };
+        self.execute(DrawCommand::FillRect {
+            rect,
+            paint: PaintStyle::Pattern(pattern),
+            transform: self.draw_state.transform,
+            clip: self.draw_state.clip.clone(),
+            compositing: self.compositing(),
+        });
+    }
+
+    /// Transparent black image data of given size, or the size of other image data.
+    pub fn create_image_data<'js>(
+        &self,
+        ctx: Ctx<'js>,
+        first: rquickjs::Value<'js>,
+        height: Opt<f64>,
+    ) -> rquickjs::Result<ImageData<'js>> {
+        let (width, height, length) = match Class::<ImageData>::from_js(&ctx, first.clone()) {
+            Ok(other) => {
+                let (width, height) = (other.borrow().width, other.borrow().height);
+                let length = rgba_length(width, height).expect("size is checked");
+                (width, height, length)
+            }
+            Err(_) => {
+                let width = f64::from_js(&ctx, first)?;
+                image_data_size(&ctx, width, height.0.unwrap_or(0.0))?
+            }
+        };
+        let rgba = vec![0; length];
+        return ImageData::from_rgba(&ctx, width, height, rgba);
+    }
+
+    /// Image data of rect of surface, transparent black outside of it.
+    ///
+    /// Coordinates are surface pixels, which are zoom times user units, and
+    /// are not transformed.
+    pub fn get_image_data<'js>(
+        &self,
+        ctx: Ctx<'js>,
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    ) -> rquickjs::Result<ImageData<'js>> {
+        let (w, h, _) = image_data_size(&ctx, width, height)?;
+        let (x, y) = ((x + width.min(0.0)) as i32, (y + height.min(0.0)) as i32);
+        let rgba = pixmap_rgba(&self.surface, x, y, w, h);
+        return ImageData::from_rgba(&ctx, w, h, rgba);
+    }
+
+    /// Put pixels of image data on surface at dx, dy, replacing what is there.
+    ///
+    /// Arguments after dx, dy can give a dirty rect of image data, only that
+    /// part is put. Coordinates are surface pixels like for getImageData(), and
+    /// the transform, clip and compositing settings are ignored.
+    pub fn put_image_data<'js>(
+        &mut self,
+        image_data: Class<'js, ImageData<'js>>,
+        dx: f64,
+        dy: f64,
+        dirty: Rest<f64>,
+    ) -> rquickjs::Result<()> {
+        let image_data = image_data.borrow();
+        let (width, height) = (image_data.width as f64, image_data.height as f64);
+        let [x, y, w, h] = match dirty.0[..] {
+            [] => [0.0, 0.0, width, height],
+            [x, y, w, h] => [x + w.min(0.0), y + h.min(0.0), w.abs(), h.abs()],
+            _ => {
+                println!("putImageData() takes 3 or 7 arguments, ignoring");
+                return Ok(());
+            }
+        };
+        if ![dx, dy, x, y, w, h].iter().all(|arg| arg.is_finite()) {
+            return Ok(());
+        }
+        let Some(rect) = IntRect::from_ltrb(
+            x.max(0.0) as i32,
+            y.max(0.0) as i32,
+            (x + w).min(width) as i32,
+            (y + h).min(height) as i32,
+        ) else {
+            return Ok(());
+        };
+        if let Some(pixmap) = image_data.to_pixmap(rect)? {
+            let (x, y) = (dx.trunc() + rect.x() as f64, dy.trunc() + rect.y() as f64);
+            self.put_pixmap(pixmap, x, y);
+        }
+        return Ok(());
+    }
+
+    /// Set surface to color given, including alpha.
+    /// So this can erase canvas, or set to background color.
+    pub fn clear_rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        self.trace_call("clearRect", json!([x, y, width, height]));
+        self.execute(DrawCommand::ClearRect {
+            rect: Rect::from_xywh(x as f32, y as f32, width as f32, height as f32).unwrap(),
+            color: self.draw_state.clear_style,
+            transform: self.draw_state.transform,
+            clip: self.draw_state.clip.clone(),
+        });
+    }
+
+    /// Clear entire image, set to fixed color
+    pub fn clear(&mut self, r: f64, g: f64, b: f64, a: f64) {
+        self.trace_call("clear", json!([r, g, b, a]));
+        let color = Color::from_rgba(r as f32, g as f32, b as f32, a as f32).unwrap();
+        self.execute(DrawCommand::Clear { color });
+    }
+
+    /// Set dash pattern for strokes, empty list for solid lines.
+    ///
+    /// Like Canvas, a list with odd length is repeated to make it even, and
+    /// lists with negative or non-finite lengths are ignored.
+    pub fn set_line_dash(&mut self, segments: Vec<f64>) {
+        self.trace_call("setLineDash", json!([segments]));
+        if segments.iter().any(|d| !d.is_finite() || *d < 0.0) {
+            return;
+        }
+        let mut dash = segments.clone();
+        if dash.len() % 2 == 1 {
+            dash.extend(segments);
+        }
+        self.draw_state.line_dash = dash;
+    }
+
+    pub fn get_line_dash(&self) -> Vec<f64> {
+        return self.draw_state.line_dash.clone();
+    }
+
+    #[qjs(set, rename = "lineDashOffset")]
+    pub fn set_line_dash_offset(&mut self, offset: f64) {
+        self.trace_call("lineDashOffset", json!([offset]));
+        if offset.is_finite() {
+            self.draw_state.line_dash_offset = offset;
+        }
+    }
+
+    #[qjs(get, rename = "lineDashOffset")]
+    pub fn get_line_dash_offset(&self) -> f64 {
+        return self.draw_state.line_dash_offset;
+    }
+
+    pub fn save(&mut self) {
+        self.trace_call("save", json!([]));
+        self.stack.push(self.draw_state.clone());
+    }
+
+    pub fn restore(&mut self) {
+        self.trace_call("restore", json!([]));
+        if let Some(state) = self.stack.pop() {
+            self.draw_state = state;
+        } else {
+            println!("CanvasContext::restore() called with empty stack");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> DrawContext {
+        return DrawContext::new(20, 10, 1.0, "#fff".to_string(), "#000".to_string());
+    }
+
+    /// Stroke of last command drawn, which must be strokePath
+    fn last_stroke(context: &DrawContext) -> StrokeStyle {
+        let Some(DrawCommand::StrokePath { stroke, .. }) = context.display_list().commands.last()
+        else {
+            panic!("expected strokePath");
+        };
+        return stroke.clone();
+    }
+
+    #[test]
+    fn test_clip() {
+        let mut context = context();
+        context.save();
+        context.begin_path();
+        context.rect(0.0, 0.0, 10.0, 10.0);
+        context.rect(2.0, 2.0, 4.0, 4.0);
+        context.clip(Opt(Some(PathOr::Other("evenodd".to_string()))), Opt(None));
+        context.fill_rect(0.0, 0.0, 20.0, 10.0);
+        context.restore();
+        context.set_fill_style(StyleValue::Css("#f00".to_string()));
+        context.fill_rect(15.0, 0.0, 5.0, 10.0);
+        let pixel = |x, y| {
+            let p = context.surface().pixel(x, y).unwrap();
+            return (p.red(), p.green());
+        };
+        assert_eq!(pixel(1, 1), (0, 0));
+        assert_eq!(pixel(4, 4), (255, 255));
+        assert_eq!(pixel(12, 4), (255, 255));
+        assert_eq!(pixel(17, 4), (255, 0));
+    }
+
+    #[test]
+    fn test_compositing() {
+        let mut context = context();
+        context.set_global_alpha(0.5);
+        context.set_global_alpha(2.0);
+        context.set_global_alpha(f64::NAN);
+        assert_eq!(context.get_global_alpha(), 0.5);
+        context.set_global_composite_operation("destination-out".to_string());
+        context.set_global_composite_operation("bogus".to_string());
+        assert_eq!(context.get_global_composite_operation(), "destination-out");
+        context.fill_rect(0.0, 0.0, 10.0, 10.0);
+        let compositing = context
+            .display_list()
+            .commands
+            .last()
+            .unwrap()
+            .compositing();
+        assert_eq!(compositing.alpha, 0.5);
+        assert_eq!(compositing.blend_mode, BlendMode::DestinationOut);
+        context.save();
+        context.set_global_alpha(1.0);
+        context.set_global_composite_operation("source-over".to_string());
+        context.restore();
+        assert_eq!(context.get_global_alpha(), 0.5);
+        assert_eq!(context.get_global_composite_operation(), "destination-out");
+    }
+
+    #[test]
+    fn test_shadow() {
+        let mut context = DrawContext::new(20, 10, 2.0, "#fff".to_string(), "#000".to_string());
+        let shadow = |context: &DrawContext| {
+            return context
+                .display_list()
+                .commands
+                .last()
+                .unwrap()
+                .compositing()
+                .shadow;
+        };
+        // Shadow color is transparent black by default
+        context.set_shadow_offset_x(5.0);
+        context.fill_rect(0.0, 0.0, 5.0, 5.0);
+        assert_eq!(shadow(&context), None);
+        context.set_shadow_color("#ff0000".to_string());
+        context.set_shadow_color("#xyz".to_string());
+        context.set_shadow_blur(-1.0);
+        context.set_shadow_blur(f64::INFINITY);
+        context.set_shadow_offset_y(f64::NAN);
+        context.fill_rect(0.0, 0.0, 5.0, 5.0);
+        // Shadow is in surface pixels, so it is zoomed
+        let expected = Shadow {
+            color: Color::from_rgba8(255, 0, 0, 255),
+            blur: 0.0,
+            offset_x: 10.0,
+            offset_y: 0.0,
+        };
+        assert_eq!(shadow(&context), Some(expected));
+        context.set_shadow_blur(2.0);
+        context.fill_rect(0.0, 0.0, 5.0, 5.0);
+        assert_eq!(shadow(&context).unwrap().blur, 4.0);
+        assert_eq!(context.get_shadow_color(), "#ff0000ff");
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut context = DrawContext::new(20, 10, 2.0, "#fff".to_string(), "#000".to_string());
+        context.set_filter("grayscale(100%) blur(1px)".to_string());
+        context.set_filter("sepia(1)".to_string());
+        assert_eq!(context.get_filter(), "grayscale(100%) blur(1px)");
+        context.fill_rect(0.0, 0.0, 5.0, 5.0);
+        let compositing = context
+            .display_list()
+            .commands
+            .last()
+            .unwrap()
+            .compositing();
+        // Blur is in surface pixels, so it is zoomed
+        assert_eq!(
+            compositing.filter,
+            vec![Filter::Grayscale(1.0), Filter::Blur(2.0)]
+        );
+        context.set_filter("none".to_string());
+        context.fill_rect(0.0, 0.0, 5.0, 5.0);
+        let compositing = context
+            .display_list()
+            .commands
+            .last()
+            .unwrap()
+            .compositing();
+        assert!(compositing.filter.is_empty());
+    }
+
+    #[test]
+    fn test_stroke_text() {
+        let mut context = context();
+        context.set_font("10pt Bravura".to_string());
+        context.fill_text("\u{e0a4}".to_string(), 2.0, 5.0);
+        let Some(DrawCommand::FillText { glyphs: filled, .. }) =
+            context.display_list().commands.last().cloned()
+        else {
+            panic!("expected fillText");
+        };
+        context.set_line_width(2.0);
+        context.set_stroke_style(StyleValue::Css("#00f".to_string()));
+        context.stroke_text("\u{e0a4}".to_string(), 2.0, 5.0);
+        let Some(DrawCommand::StrokeText {
+            glyphs,
+            paint,
+            stroke,
+            ..
+        }) = context.display_list().commands.last()
+        else {
+            panic!("expected strokeText");
+        };
+        assert_eq!(glyphs, &filled);
+        assert_eq!(paint, &PaintStyle::Color(Color::from_rgba8(0, 0, 255, 255)));
+        assert_eq!(stroke.width, 2.0);
+    }
+
+    #[test]
+    fn test_text_align() {
+        let mut context = DrawContext::new(100, 40, 1.0, "#fff".to_string(), "#000".to_string());
+        context.set_font("20pt Academico".to_string());
+        let plain = context.measure_text("Hello".to_string());
+        assert!(plain.font_bounding_box_ascent < 0.0 && plain.font_bounding_box_descent < 0.0);
+        context.save();
+        context.set_text_align("center".to_string());
+        context.set_text_baseline("middle".to_string());
+        context.set_text_align("middle".to_string());
+        assert_eq!(context.get_text_align(), "center");
+        let centered = context.measure_text("Hello".to_string());
+        assert_eq!(centered.width, plain.width);
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-3;
+        let half = plain.width / 2.0;
+        let left = plain.actual_bounding_box_left + half;
+        assert!(close(centered.actual_bounding_box_left, left));
+        let right = plain.actual_bounding_box_right - half;
+        assert!(close(centered.actual_bounding_box_right, right));
+        let shift = plain.actual_bounding_box_ascent - centered.actual_bounding_box_ascent;
+        assert!(shift > 0.0);
+        // Font box keeps its flipped signs, moving the other way
+        let font_ascent = plain.font_bounding_box_ascent + shift;
+        assert!(close(centered.font_bounding_box_ascent, font_ascent));
+        context.fill_text("Hello".to_string(), 50.0, 20.0);
+        context.restore();
+        assert_eq!(context.get_text_baseline(), "alphabetic");
+        context.set_direction("rtl".to_string());
+        context.stroke_text("Hello".to_string(), 50.0, 20.0);
+
+        let commands = &context.display_list().commands;
+        let DrawCommand::FillText { glyphs, .. } = &commands[commands.len() - 2] else {
+            panic!("expected fillText");
+        };
+        assert!(close(glyphs[0].x as f64, 50.0 - half));
+        assert!(close(glyphs[0].y as f64, 20.0 + shift));
+        let DrawCommand::StrokeText { glyphs, .. } = &commands[commands.len() - 1] else {
+            panic!("expected strokeText");
+        };
+        assert!(close(glyphs[0].x as f64, 50.0 - plain.width));
+        assert_eq!(glyphs[0].y, 20.0);
+    }
+
+    #[test]
+    fn test_draw_image() {
+        let mut image = DrawContext::new(4, 4, 1.0, "#00f".to_string(), "#000".to_string());
+        image.set_fill_style(StyleValue::Css("#f00".to_string()));
+        image.fill_rect(0.0, 0.0, 2.0, 2.0);
+        let mut context = DrawContext::new(20, 20, 1.0, "#fff".to_string(), "#000".to_string());
+        context.draw_image(image.image_source(), Rest(vec![1.0, 1.0]));
+        context.draw_image(image.image_source(), Rest(vec![10.0, 10.0, 8.0, 8.0]));
+        context.draw_image(
+            image.image_source(),
+            Rest(vec![2.0, 0.0, 2.0, 2.0, 6.0, 1.0]),
+        );
+        context.draw_image(image.image_source(), Rest(vec![1.0, f64::NAN]));
+        let pixel = |x, y| {
+            let p = context.surface().pixel(x, y).unwrap();
+            return (p.red(), p.green(), p.blue());
+        };
+        assert_eq!(pixel(1, 1), (255, 0, 0));
+        assert_eq!(pixel(4, 4), (0, 0, 255));
+        assert_eq!(pixel(5, 5), (255, 255, 255));
+        assert_eq!(pixel(11, 11), (255, 0, 0));
+        assert_eq!(pixel(16, 16), (0, 0, 255));
+        assert_eq!(pixel(18, 18), (255, 255, 255));
+        // Wrong number of arguments draws nothing
+        assert_eq!(pixel(6, 1), (255, 255, 255));
+        assert_eq!(context.display_list().commands.len(), 3);
+    }
+
+    #[test]
+    fn test_put_pixmap() {
+        let mut context = DrawContext::new(10, 10, 2.0, "#fff".to_string(), "#000".to_string());
+        context.begin_path();
+        context.rect(0.0, 0.0, 1.0, 1.0);
+        context.clip(Opt(None), Opt(None));
+        context.set_global_alpha(0.5);
+        let mut pixmap = Pixmap::new(4, 2).unwrap();
+        pixmap.fill(Color::from_rgba8(0, 0, 255, 128));
+        context.put_pixmap(pixmap, 5.0, 6.0);
+        let pixel = |x, y| {
+            let p = context.surface().pixel(x, y).unwrap();
+            return (p.red(), p.green(), p.blue(), p.alpha());
+        };
+        // Replaces pixels in surface pixels, ignoring clip, alpha and zoom
+        assert_eq!(pixel(5, 6), (0, 0, 128, 128));
+        assert_eq!(pixel(8, 7), (0, 0, 128, 128));
+        assert_eq!(pixel(9, 7), (255, 255, 255, 255));
+        assert_eq!(pixel(5, 8), (255, 255, 255, 255));
+    }
+
+    #[test]
+    fn test_line_dash() {
+        let mut context = context();
+        context.set_line_dash(vec![1.0, 2.0, 3.0]);
+        assert_eq!(context.get_line_dash(), vec![1.0, 2.0, 3.0, 1.0, 2.0, 3.0]);
+        context.set_line_dash(vec![1.0, -2.0]);
+        context.set_line_dash(vec![f64::NAN]);
+        context.set_line_dash(vec![f64::INFINITY, 1.0]);
+        assert_eq!(context.get_line_dash(), vec![1.0, 2.0, 3.0, 1.0, 2.0, 3.0]);
+        context.set_line_dash(vec![4.0, 1.0]);
+        assert_eq!(context.get_line_dash(), vec![4.0, 1.0]);
+        context.set_line_dash_offset(2.5);
+        context.set_line_dash_offset(f64::NAN);
+        assert_eq!(context.get_line_dash_offset(), 2.5);
+        context.begin_path();
+        context.move_to(0.0, 5.0);
+        context.line_to(20.0, 5.0);
+        context.stroke(Opt(None));
+        let stroke = last_stroke(&context);
+        assert_eq!((stroke.dash, stroke.dash_offset), (vec![4.0, 1.0], 2.5));
+        context.set_line_dash(vec![]);
+        assert!(context.get_line_dash().is_empty());
+    }
+
+    #[test]
+    fn test_line_cap_join() {
+        let mut context = context();
+        context.set_line_cap("round".to_string());
+        context.set_line_cap("pointy".to_string());
+        context.set_line_join("bevel".to_string());
+        context.set_line_join("Round".to_string());
+        context.set_miter_limit(4.0);
+        for bad in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            context.set_miter_limit(bad);
+        }
+        assert_eq!(context.get_line_cap(), "round");
+        assert_eq!(context.get_line_join(), "bevel");
+        assert_eq!(context.get_miter_limit(), 4.0);
+        context.save();
+        context.set_line_cap("square".to_string());
+        context.set_line_join("miter".to_string());
+        context.set_miter_limit(2.0);
+        context.restore();
+        assert_eq!(context.get_line_cap(), "round");
+        assert_eq!(context.get_line_join(), "bevel");
+        assert_eq!(context.get_miter_limit(), 4.0);
+        context.begin_path();
+        context.move_to(0.0, 5.0);
+        context.line_to(20.0, 5.0);
+        context.stroke(Opt(None));
+        let stroke = last_stroke(&context);
+        assert_eq!(stroke.line_cap, LineCap::Round);
+        assert_eq!(stroke.line_join, LineJoin::Bevel);
+        assert_eq!(stroke.miter_limit, 4.0);
+    }
+}
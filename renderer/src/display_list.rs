@@ -0,0 +1,921 @@
+//
+// Display list of drawing operations
+//
+// DrawContext records every drawing operation as a DrawCommand with all state
+// resolved: colors, transforms, strokes, and text as glyphs of specific fonts.
+// A display list can be replayed into any Backend, so one run of VexFlow can
+// produce PNG, SVG and PDF output at any zoom. It can also be saved as JSON
+// and loaded again.
+//
+
+use crate::filter::Filter;
+use crate::font::FontLibrary;
+use crate::paint::PaintStyle;
+use crate::pdf::PdfDocument;
+use crate::svg::{SvgDocument, SvgTextMode};
+use serde::{Deserialize, Serialize};
+use tiny_skia::{
+    BlendMode, Color, FillRule, LineCap, LineJoin, Path, Pixmap, Rect, Stroke, StrokeDash,
+    Transform,
+};
+
+/// Something that drawing commands can be replayed into
+pub trait Backend {
+    /// Draw one command, fonts are needed to draw text glyphs
+    fn draw(&mut self, command: &DrawCommand, fonts: &FontLibrary);
+}
+
+/// One glyph of a text run, resolved to a built-in font
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextGlyph {
+    /// Character drawn, used by backends that keep text as text
+    pub ch: char,
+    /// PostScript name of font in FontLibrary
+    pub font: String,
+    /// Glyph id within the font
+    pub id: u16,
+    /// Advance width in thousandths of an em
+    pub advance: f32,
+    /// Glyph origin (left end of baseline) in user units
+    pub x: f32,
+    pub y: f32,
+}
+
+/// How paths are stroked
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StrokeStyle {
+    pub width: f32,
+    #[serde(default, with = "line_cap_serde")]
+    pub line_cap: LineCap,
+    #[serde(default, with = "line_join_serde")]
+    pub line_join: LineJoin,
+    /// Longest miter join allowed, as a multiple of width, before it is beveled
+    #[serde(default = "default_miter_limit")]
+    pub miter_limit: f32,
+    /// Dash lengths alternating drawn and skipped, with even length, empty for solid lines
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dash: Vec<f32>,
+    /// Distance into the dash pattern at start of path
+    #[serde(default)]
+    pub dash_offset: f32,
+}
+
+fn default_miter_limit() -> f32 {
+    return 10.0;
+}
+
+impl Default for StrokeStyle {
+    /// Canvas defaults
+    fn default() -> Self {
+        StrokeStyle {
+            width: 1.0,
+            line_cap: LineCap::Butt,
+            line_join: LineJoin::Miter,
+            miter_limit: default_miter_limit(),
+            dash: vec![],
+            dash_offset: 0.0,
+        }
+    }
+}
+
+/// Path that drawing is clipped to, with the transform it was set with
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipPath {
+    #[serde(with = "path_serde")]
+    pub path: Path,
+    #[serde(with = "fill_rule_serde")]
+    pub fill_rule: FillRule,
+    #[serde(with = "transform_serde")]
+    pub transform: Transform,
+}
+
+/// How a drawing operation is combined with what was drawn before
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Compositing {
+    /// Opacity multiplied into everything drawn, from globalAlpha
+    #[serde(default = "default_alpha", skip_serializing_if = "is_opaque")]
+    pub alpha: f32,
+    /// From globalCompositeOperation
+    #[serde(
+        default,
+        skip_serializing_if = "is_source_over",
+        with = "blend_mode_serde"
+    )]
+    pub blend_mode: BlendMode,
+    /// Drawn under the operation, None when shadows are off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shadow: Option<Shadow>,
+    /// Applied in order to the operation, before its shadow is made
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filter: Vec<Filter>,
+}
+
+/// Blurred copy of a drawing operation in one color, drawn under it
+///
+/// Blur and offsets are in pixels of the surface, not affected by the transform.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Shadow {
+    #[serde(with = "color_serde")]
+    pub color: Color,
+    /// From shadowBlur, twice the standard deviation of the Gaussian blur
+    pub blur: f32,
+    pub offset_x: f32,
+    pub offset_y: f32,
+}
+
+fn default_alpha() -> f32 {
+    return 1.0;
+}
+
+fn is_opaque(alpha: &f32) -> bool {
+    return *alpha == 1.0;
+}
+
+fn is_source_over(mode: &BlendMode) -> bool {
+    return *mode == BlendMode::SourceOver;
+}
+
+impl Default for Compositing {
+    /// Canvas defaults, draw opaque over what is there
+    fn default() -> Self {
+        Compositing {
+            alpha: default_alpha(),
+            blend_mode: BlendMode::SourceOver,
+            shadow: None,
+            filter: vec![],
+        }
+    }
+}
+
+impl Compositing {
+    /// Same compositing with shadow and filter lengths multiplied by scale
+    pub fn scaled(&self, scale: f32) -> Compositing {
+        return Compositing {
+            shadow: self.shadow.map(|shadow| shadow.scaled(scale)),
+            filter: self.filter.iter().map(|f| f.scaled(scale)).collect(),
+            ..self.clone()
+        };
+    }
+}
+
+impl Shadow {
+    /// Same shadow with blur and offsets multiplied by scale
+    pub fn scaled(&self, scale: f32) -> Shadow {
+        return Shadow {
+            color: self.color,
+            blur: self.blur * scale,
+            offset_x: self.offset_x * scale,
+            offset_y: self.offset_y * scale,
+        };
+    }
+}
+
+/// Canvas name of blend mode, from globalCompositeOperation
+pub(crate) fn blend_mode_name(mode: BlendMode) -> &'static str {
+    return match mode {
+        BlendMode::Clear => "clear",
+        BlendMode::Source => "copy",
+        BlendMode::Destination => "destination",
+        BlendMode::SourceOver => "source-over",
+        BlendMode::DestinationOver => "destination-over",
+        BlendMode::SourceIn => "source-in",
+        BlendMode::DestinationIn => "destination-in",
+        BlendMode::SourceOut => "source-out",
+        BlendMode::DestinationOut => "destination-out",
+        BlendMode::SourceAtop => "source-atop",
+        BlendMode::DestinationAtop => "destination-atop",
+        BlendMode::Xor => "xor",
+        BlendMode::Plus => "lighter",
+        BlendMode::Modulate => "modulate",
+        BlendMode::Screen => "screen",
+        BlendMode::Overlay => "overlay",
+        BlendMode::Darken => "darken",
+        BlendMode::Lighten => "lighten",
+        BlendMode::ColorDodge => "color-dodge",
+        BlendMode::ColorBurn => "color-burn",
+        BlendMode::HardLight => "hard-light",
+        BlendMode::SoftLight => "soft-light",
+        BlendMode::Difference => "difference",
+        BlendMode::Exclusion => "exclusion",
+        BlendMode::Multiply => "multiply",
+        BlendMode::Hue => "hue",
+        BlendMode::Saturation => "saturation",
+        BlendMode::Color => "color",
+        BlendMode::Luminosity => "luminosity",
+    };
+}
+
+/// Blend mode from Canvas globalCompositeOperation name
+pub(crate) fn parse_blend_mode(name: &str) -> Option<BlendMode> {
+    return match name {
+        "source-over" => Some(BlendMode::SourceOver),
+        "source-in" => Some(BlendMode::SourceIn),
+        "source-out" => Some(BlendMode::SourceOut),
+        "source-atop" => Some(BlendMode::SourceAtop),
+        "destination-over" => Some(BlendMode::DestinationOver),
+        "destination-in" => Some(BlendMode::DestinationIn),
+        "destination-out" => Some(BlendMode::DestinationOut),
+        "destination-atop" => Some(BlendMode::DestinationAtop),
+        "lighter" => Some(BlendMode::Plus),
+        "copy" => Some(BlendMode::Source),
+        "xor" => Some(BlendMode::Xor),
+        "multiply" => Some(BlendMode::Multiply),
+        "screen" => Some(BlendMode::Screen),
+        "overlay" => Some(BlendMode::Overlay),
+        "darken" => Some(BlendMode::Darken),
+        "lighten" => Some(BlendMode::Lighten),
+        "color-dodge" => Some(BlendMode::ColorDodge),
+        "color-burn" => Some(BlendMode::ColorBurn),
+        "hard-light" => Some(BlendMode::HardLight),
+        "soft-light" => Some(BlendMode::SoftLight),
+        "difference" => Some(BlendMode::Difference),
+        "exclusion" => Some(BlendMode::Exclusion),
+        "hue" => Some(BlendMode::Hue),
+        "saturation" => Some(BlendMode::Saturation),
+        "color" => Some(BlendMode::Color),
+        "luminosity" => Some(BlendMode::Luminosity),
+        _ => None,
+    };
+}
+
+/// Canvas name of fill rule
+pub(crate) fn fill_rule_name(rule: FillRule) -> &'static str {
+    return match rule {
+        FillRule::Winding => "nonzero",
+        FillRule::EvenOdd => "evenodd",
+    };
+}
+
+/// Fill rule from Canvas name
+pub(crate) fn parse_fill_rule(name: &str) -> Option<FillRule> {
+    return match name {
+        "nonzero" => Some(FillRule::Winding),
+        "evenodd" => Some(FillRule::EvenOdd),
+        _ => None,
+    };
+}
+
+/// Canvas name of line cap
+pub(crate) fn line_cap_name(cap: LineCap) -> &'static str {
+    return match cap {
+        LineCap::Butt => "butt",
+        LineCap::Round => "round",
+        LineCap::Square => "square",
+    };
+}
+
+/// Line cap from Canvas name
+pub(crate) fn parse_line_cap(name: &str) -> Option<LineCap> {
+    return match name {
+        "butt" => Some(LineCap::Butt),
+        "round" => Some(LineCap::Round),
+        "square" => Some(LineCap::Square),
+        _ => None,
+    };
+}
+
+/// Canvas name of line join
+pub(crate) fn line_join_name(join: LineJoin) -> &'static str {
+    return match join {
+        LineJoin::Miter | LineJoin::MiterClip => "miter",
+        LineJoin::Round => "round",
+        LineJoin::Bevel => "bevel",
+    };
+}
+
+/// Line join from Canvas name
+pub(crate) fn parse_line_join(name: &str) -> Option<LineJoin> {
+    return match name {
+        "miter" => Some(LineJoin::Miter),
+        "round" => Some(LineJoin::Round),
+        "bevel" => Some(LineJoin::Bevel),
+        _ => None,
+    };
+}
+
+/// Rough bounds of text drawn at font size, generous enough to cover every glyph
+pub(crate) fn text_bounds(glyphs: &[TextGlyph], size: f32) -> Option<Rect> {
+    let left = glyphs.iter().map(|g| g.x).fold(f32::INFINITY, f32::min);
+    let right = glyphs.iter().map(|g| g.x).fold(f32::NEG_INFINITY, f32::max);
+    let top = glyphs.iter().map(|g| g.y).fold(f32::INFINITY, f32::min);
+    let bottom = glyphs.iter().map(|g| g.y).fold(f32::NEG_INFINITY, f32::max);
+    return Rect::from_ltrb(
+        left - size,
+        top - 2.0 * size,
+        right + 2.0 * size,
+        bottom + size,
+    );
+}
+
+impl StrokeStyle {
+    /// Stroke settings for tiny-skia
+    pub fn to_stroke(&self) -> Stroke {
+        return Stroke {
+            width: self.width,
+            line_cap: self.line_cap,
+            line_join: self.line_join,
+            miter_limit: self.miter_limit,
+            // None when dash lengths are all zero, which means a solid line
+            dash: StrokeDash::new(self.dash.clone(), self.dash_offset),
+        };
+    }
+
+    /// Whether stroke has a dash pattern that skips anything
+    pub fn is_dashed(&self) -> bool {
+        return StrokeDash::new(self.dash.clone(), self.dash_offset).is_some();
+    }
+}
+
+/// One drawing operation with all drawing state resolved
+///
+/// Except for Clear, commands are clipped to the intersection of the paths
+/// in clip, which is empty when there is no clipping.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub(crate) enum DrawCommand {
+    /// Fill whole surface with color, replacing everything drawn
+    Clear {
+        #[serde(with = "color_serde")]
+        color: Color,
+    },
+    /// Set area to color, replacing what was drawn there
+    ClearRect {
+        #[serde(with = "rect_serde")]
+        rect: Rect,
+        #[serde(with = "color_serde")]
+        color: Color,
+        #[serde(with = "transform_serde")]
+        transform: Transform,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        clip: Vec<ClipPath>,
+    },
+    FillRect {
+        #[serde(with = "rect_serde")]
+        rect: Rect,
+        paint: PaintStyle,
+        #[serde(with = "transform_serde")]
+        transform: Transform,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        clip: Vec<ClipPath>,
+        #[serde(flatten)]
+        compositing: Compositing,
+    },
+    FillPath {
+        #[serde(with = "path_serde")]
+        path: Path,
+        paint: PaintStyle,
+        #[serde(with = "fill_rule_serde")]
+        fill_rule: FillRule,
+        #[serde(with = "transform_serde")]
+        transform: Transform,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        clip: Vec<ClipPath>,
+        #[serde(flatten)]
+        compositing: Compositing,
+    },
+    StrokePath {
+        #[serde(with = "path_serde")]
+        path: Path,
+        paint: PaintStyle,
+        stroke: StrokeStyle,
+        #[serde(with = "transform_serde")]
+        transform: Transform,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        clip: Vec<ClipPath>,
+        #[serde(flatten)]
+        compositing: Compositing,
+    },
+    /// Run of glyphs, size is font size in pt
+    FillText {
+        glyphs: Vec<TextGlyph>,
+        size: f32,
+        paint: PaintStyle,
+        #[serde(with = "transform_serde")]
+        transform: Transform,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        clip: Vec<ClipPath>,
+        #[serde(flatten)]
+        compositing: Compositing,
+    },
+    /// Outlines of run of glyphs, size is font size in pt
+    StrokeText {
+        glyphs: Vec<TextGlyph>,
+        size: f32,
+        paint: PaintStyle,
+        stroke: StrokeStyle,
+        #[serde(with = "transform_serde")]
+        transform: Transform,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        clip: Vec<ClipPath>,
+        #[serde(flatten)]
+        compositing: Compositing,
+    },
+}
+
+impl DrawCommand {
+    /// Same command drawn with an extra scale applied after its transform
+    ///
+    /// Shadows and filters are scaled as well.
+    pub fn scaled(&self, scale: f32) -> DrawCommand {
+        let mut command = self.map_transforms(|t| t.post_scale(scale, scale));
+        if let Some(compositing) = command.compositing_mut() {
+            *compositing = compositing.scaled(scale);
+        }
+        return command;
+    }
+
+    /// Same command drawn with an extra translation applied after its transform
+    pub fn translated(&self, dx: f32, dy: f32) -> DrawCommand {
+        return self.map_transforms(|t| t.post_translate(dx, dy));
+    }
+
+    /// Rough bounds of what command draws in device pixels, ignoring clip
+    ///
+    /// None when command clears everything or draws no glyphs.
+    pub fn bounds(&self) -> Option<Rect> {
+        let (bounds, transform) = match self {
+            DrawCommand::Clear { .. } => return None,
+            DrawCommand::ClearRect {
+                rect, transform, ..
+            }
+            | DrawCommand::FillRect {
+                rect, transform, ..
+            } => (*rect, transform),
+            DrawCommand::FillPath {
+                path, transform, ..
+            } => (path.bounds(), transform),
+            DrawCommand::StrokePath {
+                path,
+                stroke,
+                transform,
+                ..
+            } => (
+                path.stroke(&stroke.to_stroke(), 1.0)
+                    .map_or(path.bounds(), |outline| outline.bounds()),
+                transform,
+            ),
+            DrawCommand::FillText {
+                glyphs,
+                size,
+                transform,
+                ..
+            } => (text_bounds(glyphs, *size)?, transform),
+            DrawCommand::StrokeText {
+                glyphs,
+                size,
+                stroke,
+                transform,
+                ..
+            } => (
+                text_bounds(glyphs, *size)?.outset(stroke.width, stroke.width)?,
+                transform,
+            ),
+        };
+        return bounds.transform(*transform);
+    }
+
+    /// Same command with f applied to its transform and those of its clip paths
+    fn map_transforms(&self, f: impl Fn(Transform) -> Transform) -> DrawCommand {
+        let mut command = self.clone();
+        match &mut command {
+            DrawCommand::Clear { .. } => {}
+            DrawCommand::ClearRect {
+                transform, clip, ..
+            }
+            | DrawCommand::FillRect {
+                transform, clip, ..
+            }
+            | DrawCommand::FillPath {
+                transform, clip, ..
+            }
+            | DrawCommand::StrokePath {
+                transform, clip, ..
+            }
+            | DrawCommand::FillText {
+                transform, clip, ..
+            }
+            | DrawCommand::StrokeText {
+                transform, clip, ..
+            } => {
+                *transform = f(*transform);
+                for path in clip {
+                    path.transform = f(path.transform);
+                }
+            }
+        }
+        return command;
+    }
+
+    /// Same command drawn with other compositing, clearing ignores it
+    pub fn with_compositing(&self, compositing: Compositing) -> DrawCommand {
+        let mut command = self.clone();
+        if let Some(c) = command.compositing_mut() {
+            *c = compositing;
+        }
+        return command;
+    }
+
+    /// Paths command is clipped to, all of them apply
+    pub fn clip(&self) -> &[ClipPath] {
+        return match self {
+            DrawCommand::Clear { .. } => &[],
+            DrawCommand::ClearRect { clip, .. }
+            | DrawCommand::FillRect { clip, .. }
+            | DrawCommand::FillPath { clip, .. }
+            | DrawCommand::StrokePath { clip, .. }
+            | DrawCommand::FillText { clip, .. }
+            | DrawCommand::StrokeText { clip, .. } => clip,
+        };
+    }
+
+    /// How command is combined with what was drawn before, clearing ignores it
+    pub fn compositing(&self) -> Compositing {
+        return match self {
+            DrawCommand::Clear { .. } | DrawCommand::ClearRect { .. } => Compositing::default(),
+            DrawCommand::FillRect { compositing, .. }
+            | DrawCommand::FillPath { compositing, .. }
+            | DrawCommand::StrokePath { compositing, .. }
+            | DrawCommand::FillText { compositing, .. }
+            | DrawCommand::StrokeText { compositing, .. } => compositing.clone(),
+        };
+    }
+
+    /// Same command with paint changed by f, clearing has no paint
+    pub fn map_paint(&self, f: impl Fn(&PaintStyle) -> PaintStyle) -> DrawCommand {
+        let mut command = self.clone();
+        match &mut command {
+            DrawCommand::Clear { .. } | DrawCommand::ClearRect { .. } => {}
+            DrawCommand::FillRect { paint, .. }
+            | DrawCommand::FillPath { paint, .. }
+            | DrawCommand::StrokePath { paint, .. }
+            | DrawCommand::FillText { paint, .. }
+            | DrawCommand::StrokeText { paint, .. } => *paint = f(paint),
+        }
+        return command;
+    }
+
+    fn compositing_mut(&mut self) -> Option<&mut Compositing> {
+        return match self {
+            DrawCommand::Clear { .. } | DrawCommand::ClearRect { .. } => None,
+            DrawCommand::FillRect { compositing, .. }
+            | DrawCommand::FillPath { compositing, .. }
+            | DrawCommand::StrokePath { compositing, .. }
+            | DrawCommand::FillText { compositing, .. }
+            | DrawCommand::StrokeText { compositing, .. } => Some(compositing),
+        };
+    }
+}
+
+/// Every drawing operation done on a DrawContext, in order
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DisplayList {
+    /// Width of drawing in pixels, before zoom
+    pub width: u32,
+    /// Height of drawing in pixels, before zoom
+    pub height: u32,
+    /// Zoom factor included in the transforms of commands
+    pub zoom: f64,
+    pub(crate) commands: Vec<DrawCommand>,
+}
+
+impl DisplayList {
+    pub(crate) fn new(width: u32, height: u32, zoom: f64) -> Self {
+        DisplayList {
+            width,
+            height,
+            zoom,
+            commands: vec![],
+        }
+    }
+
+    /// Size in pixels of output at given zoom
+    pub fn size(&self, zoom: f64) -> (u32, u32) {
+        return (
+            (self.width as f64 * zoom) as u32,
+            (self.height as f64 * zoom) as u32,
+        );
+    }
+
+    /// Draw all commands into backend, rescaled to given zoom.
+    pub(crate) fn replay(&self, backend: &mut impl Backend, fonts: &FontLibrary, zoom: f64) {
+        let scale = (zoom / self.zoom) as f32;
+        for command in &self.commands {
+            if scale == 1.0 {
+                backend.draw(command, fonts);
+            } else {
+                backend.draw(&command.scaled(scale), fonts);
+            }
+        }
+    }
+
+    /// Rasterize to new image at given zoom.
+    pub fn to_pixmap(&self, zoom: f64) -> Pixmap {
+        let (width, height) = self.size(zoom);
+        let mut pixmap =
+            Pixmap::new(width, height).expect("Could not create new PixMap of requested size");
+        self.replay(&mut pixmap, &FontLibrary::new(), zoom);
+        return pixmap;
+    }
+
+    /// SVG file contents at given zoom
+    pub fn to_svg(&self, zoom: f64, text_mode: SvgTextMode) -> String {
+        let (width, height) = self.size(zoom);
+        let mut svg = SvgDocument::new(width, height, Color::TRANSPARENT, text_mode);
+        self.replay(&mut svg, &FontLibrary::new(), zoom);
+        return svg.finish();
+    }
+
+    /// PDF file contents at given zoom
+    pub fn to_pdf(&self, zoom: f64) -> Vec<u8> {
+        let (width, height) = self.size(zoom);
+        let fonts = FontLibrary::new();
+        let mut pdf = PdfDocument::new(width, height, Color::TRANSPARENT);
+        self.replay(&mut pdf, &fonts, zoom);
+        return pdf.finish(&fonts);
+    }
+
+    /// Serialize as JSON
+    pub fn to_json(&self) -> String {
+        return serde_json::to_string(self).expect("Display list can always be serialized");
+    }
+
+    /// Load from JSON written by to_json()
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        return serde_json::from_str(json);
+    }
+}
+
+/// Colors as [r, g, b, a] with components from 0 to 1
+pub(crate) mod color_serde {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use tiny_skia::Color;
+
+    pub fn serialize<S: Serializer>(color: &Color, serializer: S) -> Result<S::Ok, S::Error> {
+        return [color.red(), color.green(), color.blue(), color.alpha()].serialize(serializer);
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+        let [r, g, b, a] = <[f32; 4]>::deserialize(deserializer)?;
+        return Color::from_rgba(r, g, b, a)
+            .ok_or_else(|| serde::de::Error::custom("color component out of range"));
+    }
+}
+
+/// Transforms as [sx, ky, kx, sy, tx, ty], same order as SVG and PDF matrices
+pub(crate) mod transform_serde {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use tiny_skia::Transform;
+
+    pub fn serialize<S: Serializer>(t: &Transform, serializer: S) -> Result<S::Ok, S::Error> {
+        return [t.sx, t.ky, t.kx, t.sy, t.tx, t.ty].serialize(serializer);
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Transform, D::Error> {
+        let [sx, ky, kx, sy, tx, ty] = <[f32; 6]>::deserialize(deserializer)?;
+        return Ok(Transform::from_row(sx, ky, kx, sy, tx, ty));
+    }
+}
+
+/// Rectangles as [x, y, width, height]
+mod rect_serde {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use tiny_skia::Rect;
+
+    pub fn serialize<S: Serializer>(rect: &Rect, serializer: S) -> Result<S::Ok, S::Error> {
+        return [rect.x(), rect.y(), rect.width(), rect.height()].serialize(serializer);
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rect, D::Error> {
+        let [x, y, width, height] = <[f32; 4]>::deserialize(deserializer)?;
+        return Rect::from_xywh(x, y, width, height)
+            .ok_or_else(|| serde::de::Error::custom("invalid rectangle"));
+    }
+}
+
+/// Fill rules as "nonzero" or "evenodd", like canvas
+mod fill_rule_serde {
+    use super::{fill_rule_name, parse_fill_rule};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use tiny_skia::FillRule;
+
+    pub fn serialize<S: Serializer>(rule: &FillRule, serializer: S) -> Result<S::Ok, S::Error> {
+        return fill_rule_name(*rule).serialize(serializer);
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<FillRule, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        return parse_fill_rule(&name)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown fill rule '{}'", name)));
+    }
+}
+
+/// Blend modes by Canvas globalCompositeOperation name
+mod blend_mode_serde {
+    use super::{blend_mode_name, parse_blend_mode};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use tiny_skia::BlendMode;
+
+    pub fn serialize<S: Serializer>(mode: &BlendMode, serializer: S) -> Result<S::Ok, S::Error> {
+        return blend_mode_name(*mode).serialize(serializer);
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BlendMode, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        return parse_blend_mode(&name)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown blend mode '{}'", name)));
+    }
+}
+
+/// Line caps by Canvas name
+mod line_cap_serde {
+    use super::{line_cap_name, parse_line_cap};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use tiny_skia::LineCap;
+
+    pub fn serialize<S: Serializer>(cap: &LineCap, serializer: S) -> Result<S::Ok, S::Error> {
+        return line_cap_name(*cap).serialize(serializer);
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LineCap, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        return parse_line_cap(&name)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown line cap '{}'", name)));
+    }
+}
+
+/// Line joins by Canvas name
+mod line_join_serde {
+    use super::{line_join_name, parse_line_join};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use tiny_skia::LineJoin;
+
+    pub fn serialize<S: Serializer>(join: &LineJoin, serializer: S) -> Result<S::Ok, S::Error> {
+        return line_join_name(*join).serialize(serializer);
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LineJoin, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        return parse_line_join(&name)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown line join '{}'", name)));
+    }
+}
+
+/// Paths as list of segments, each segment is a letter like SVG path data
+/// followed by coordinates
+pub(crate) mod path_serde {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use tiny_skia::{Path, PathBuilder, PathSegment};
+
+    #[derive(Serialize, Deserialize)]
+    enum Segment {
+        M(f32, f32),
+        L(f32, f32),
+        Q(f32, f32, f32, f32),
+        C(f32, f32, f32, f32, f32, f32),
+        Z,
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        let segments: Vec<Segment> = path
+            .segments()
+            .map(|segment| match segment {
+                PathSegment::MoveTo(p) => Segment::M(p.x, p.y),
+                PathSegment::LineTo(p) => Segment::L(p.x, p.y),
+                PathSegment::QuadTo(p1, p) => Segment::Q(p1.x, p1.y, p.x, p.y),
+                PathSegment::CubicTo(p1, p2, p) => Segment::C(p1.x, p1.y, p2.x, p2.y, p.x, p.y),
+                PathSegment::Close => Segment::Z,
+            })
+            .collect();
+        return segments.serialize(serializer);
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Path, D::Error> {
+        let segments = Vec::<Segment>::deserialize(deserializer)?;
+        let mut builder = PathBuilder::new();
+        for segment in segments {
+            match segment {
+                Segment::M(x, y) => builder.move_to(x, y),
+                Segment::L(x, y) => builder.line_to(x, y),
+                Segment::Q(x1, y1, x, y) => builder.quad_to(x1, y1, x, y),
+                Segment::C(x1, y1, x2, y2, x, y) => builder.cubic_to(x1, y1, x2, y2, x, y),
+                Segment::Z => builder.close(),
+            }
+        }
+        return builder
+            .finish()
+            .ok_or_else(|| serde::de::Error::custom("empty path"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_skia::PathBuilder;
+
+    fn example() -> DisplayList {
+        let mut pb = PathBuilder::new();
+        pb.move_to(1.0, 2.0);
+        pb.quad_to(3.0, 4.0, 5.0, 6.0);
+        pb.close();
+        let mut list = DisplayList::new(10, 5, 2.0);
+        list.commands.push(DrawCommand::Clear {
+            color: Color::WHITE,
+        });
+        list.commands.push(DrawCommand::StrokePath {
+            path: pb.finish().unwrap(),
+            paint: PaintStyle::Color(Color::from_rgba(1.0, 0.0, 0.0, 0.5).unwrap()),
+            stroke: StrokeStyle {
+                width: 1.5,
+                line_cap: LineCap::Round,
+                dash: vec![2.0, 1.0],
+                dash_offset: 0.5,
+                ..StrokeStyle::default()
+            },
+            transform: Transform::from_scale(2.0, 2.0),
+            clip: vec![],
+            compositing: Compositing {
+                alpha: 0.5,
+                blend_mode: BlendMode::Multiply,
+                shadow: Some(Shadow {
+                    color: Color::BLACK,
+                    blur: 4.0,
+                    offset_x: 2.0,
+                    offset_y: -1.0,
+                }),
+                filter: vec![Filter::Blur(1.0), Filter::Invert(1.0)],
+            },
+        });
+        list.commands.push(DrawCommand::FillText {
+            glyphs: vec![TextGlyph {
+                ch: 'a',
+                font: "Academico-Regular".to_string(),
+                id: 7,
+                advance: 500.0,
+                x: 1.0,
+                y: 4.0,
+            }],
+            size: 7.0,
+            paint: PaintStyle::Color(Color::BLACK),
+            transform: Transform::from_scale(2.0, 2.0),
+            clip: vec![ClipPath {
+                path: PathBuilder::from_rect(Rect::from_xywh(0.0, 0.0, 3.0, 3.0).unwrap()),
+                fill_rule: FillRule::EvenOdd,
+                transform: Transform::from_scale(2.0, 2.0),
+            }],
+            compositing: Compositing::default(),
+        });
+        return list;
+    }
+
+    #[test]
+    fn test_line_cap_join_names() {
+        for cap in [LineCap::Butt, LineCap::Round, LineCap::Square] {
+            assert_eq!(parse_line_cap(line_cap_name(cap)), Some(cap));
+        }
+        for join in [LineJoin::Miter, LineJoin::Round, LineJoin::Bevel] {
+            assert_eq!(parse_line_join(line_join_name(join)), Some(join));
+        }
+        assert_eq!(line_join_name(LineJoin::MiterClip), "miter");
+        assert_eq!(parse_line_cap("Round"), None);
+        assert_eq!(parse_line_join("clip"), None);
+        let bad = r#"{"width":1.0,"lineJoin":"pointy"}"#;
+        assert!(serde_json::from_str::<StrokeStyle>(bad).is_err());
+    }
+
+    #[test]
+    fn test_json() {
+        let list = example();
+        let json = list.to_json();
+        assert!(json.contains(r#""op":"strokePath","path":[{"M":[1.0,2.0]},{"Q":[3.0,4.0,5.0,6.0]},"Z"]"#));
+        assert!(json.contains(r#""transform":[2.0,0.0,0.0,2.0,0.0,0.0]"#));
+        assert!(json.contains(r#""stroke":{"width":1.5,"lineCap":"round","lineJoin":"miter","miterLimit":10.0,"dash":[2.0,1.0],"dashOffset":0.5},"transform":[2.0,0.0,0.0,2.0,0.0,0.0],"alpha":0.5,"blendMode":"multiply","shadow":{"color":[0.0,0.0,0.0,1.0],"blur":4.0,"offsetX":2.0,"offsetY":-1.0},"filter":[{"blur":1.0},{"invert":1.0}]}"#));
+        assert!(json.contains(r#""fillRule":"evenodd","transform":[2.0,0.0,0.0,2.0,0.0,0.0]}]}"#));
+        assert_eq!(DisplayList::from_json(&json).unwrap(), list);
+        assert!(DisplayList::from_json(r#"{"width":1}"#).is_err());
+        let stroke: StrokeStyle = serde_json::from_str(r#"{"width":2.0}"#).unwrap();
+        assert_eq!(stroke, StrokeStyle { width: 2.0, ..StrokeStyle::default() });
+    }
+
+    #[test]
+    fn test_replay() {
+        let list = example();
+        let pixmap = list.to_pixmap(2.0);
+        assert_eq!((pixmap.width(), pixmap.height()), (20, 10));
+        assert_eq!(pixmap.pixel(19, 9).unwrap().red(), 255);
+        let pixmap = list.to_pixmap(1.0);
+        assert_eq!((pixmap.width(), pixmap.height()), (10, 5));
+        let scaled = list.commands[1].scaled(0.5);
+        assert!(matches!(scaled, DrawCommand::StrokePath { transform, .. } if transform == Transform::from_scale(1.0, 1.0)));
+        let shadow = scaled.compositing().shadow.unwrap();
+        assert_eq!((shadow.blur, shadow.offset_x, shadow.offset_y), (2.0, 1.0, -0.5));
+        assert_eq!(scaled.compositing().filter[0], Filter::Blur(0.5));
+        let scaled = list.commands[2].scaled(0.5);
+        assert_eq!(scaled.clip()[0].transform, Transform::from_scale(1.0, 1.0));
+        assert!(list.to_svg(2.0, SvgTextMode::Text).contains(">a</text>"));
+        assert!(list.to_pdf(2.0).starts_with(b"%PDF-"));
+    }
+}
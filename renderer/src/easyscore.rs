@@ -0,0 +1,150 @@
+//
+// Rendering short EasyScore strings without writing a score script
+//
+// The score is turned into a small JavaScript score script, in the same
+// style as `test/easyscore.js`, and rendered like any other script.
+//
+
+/// One stave in an EasyScore system
+#[derive(Clone, Debug, PartialEq)]
+pub struct EasyScoreStave {
+    /// EasyScore note strings, one per voice, like `"C#5/q, B4, A4, G#4"`
+    pub voices: Vec<String>,
+    /// Clef name like `treble` or `bass`
+    pub clef: String,
+}
+
+impl EasyScoreStave {
+    /// Create stave with given clef and voices.
+    pub fn new(clef: &str, voices: &[&str]) -> Self {
+        EasyScoreStave {
+            voices: voices.iter().map(|voice| voice.to_string()).collect(),
+            clef: clef.to_string(),
+        }
+    }
+}
+
+/// A system of one or more staves written in EasyScore notation
+#[derive(Clone, Debug, PartialEq)]
+pub struct EasyScore {
+    /// Staves from top to bottom
+    pub staves: Vec<EasyScoreStave>,
+    /// Time signature like `4/4`, shared by all staves
+    pub time: String,
+    /// Key signature like `D` or `Bbm`, shared by all staves
+    pub key: Option<String>,
+}
+
+impl EasyScore {
+    /// Create score of a single treble stave in 4/4 with given voices.
+    pub fn new(voices: &[&str]) -> Self {
+        EasyScore {
+            staves: vec![EasyScoreStave::new("treble", voices)],
+            time: "4/4".to_string(),
+            key: None,
+        }
+    }
+
+    /// Generate score script that draws this score in a system of given width.
+    ///
+    /// When a stave has more than one voice, stems of the first voice go up
+    /// and stems of the second go down.
+    pub fn to_script(&self, width: u32) -> String {
+        let mut script = String::new();
+        script.push_str("const score = vf.EasyScore();\n");
+        script.push_str(&format!("score.set({{ time: {} }});\n", js_string(&self.time)));
+        script.push_str(&format!(
+            "const system = vf.System({{ x: 10, y: 10, width: {} }});\n",
+            width.saturating_sub(20)
+        ));
+        for stave in &self.staves {
+            let multi_voice = stave.voices.len() > 1;
+            let voices: Vec<String> = stave
+                .voices
+                .iter()
+                .enumerate()
+                .map(|(i, notes)| {
+                    let stem = if !multi_voice {
+                        "".to_string()
+                    } else if i % 2 == 0 {
+                        ", stem: 'up'".to_string()
+                    } else {
+                        ", stem: 'down'".to_string()
+                    };
+                    format!(
+                        "    score.voice(score.notes({}, {{ clef: {}{} }})),\n",
+                        js_string(notes),
+                        js_string(&stave.clef),
+                        stem
+                    )
+                })
+                .collect();
+            script.push_str(&format!(
+                "system\n  .addStave({{\n  voices: [\n{}  ],\n  }})\n  .addClef({})\n  .addTimeSignature({})",
+                voices.concat(),
+                js_string(&stave.clef),
+                js_string(&self.time)
+            ));
+            if let Some(key) = &self.key {
+                script.push_str(&format!("\n  .addKeySignature({})", js_string(key)));
+            }
+            script.push_str(";\n");
+        }
+        if self.staves.len() > 1 {
+            script.push_str("system.addConnector('singleLeft');\n");
+        }
+        script.push_str("vf.draw();\n");
+        return script;
+    }
+}
+
+/// Quote string as JavaScript string literal
+fn js_string(s: &str) -> String {
+    let mut result = String::from("'");
+    for ch in s.chars() {
+        match ch {
+            '\'' => result.push_str("\\'"),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('\'');
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_js_string() {
+        assert_eq!(js_string("C#5/q, B4"), "'C#5/q, B4'");
+        assert_eq!(js_string("it's"), r"'it\'s'");
+        assert_eq!(js_string("a\\b\nc"), r"'a\\b\nc'");
+        assert_eq!(js_string("\t"), r"'\u0009'");
+    }
+
+    #[test]
+    fn test_to_script() {
+        let score = EasyScore {
+            staves: vec![
+                EasyScoreStave::new("treble", &["C#5/q, B4, A4, G#4", "C#4/h, C#4"]),
+                EasyScoreStave::new("bass", &["C#3/w"]),
+            ],
+            time: "4/4".to_string(),
+            key: Some("E".to_string()),
+        };
+        let script = score.to_script(500);
+        assert!(script.contains("vf.System({ x: 10, y: 10, width: 480 })"));
+        assert!(script.contains("score.notes('C#5/q, B4, A4, G#4', { clef: 'treble', stem: 'up' })"));
+        assert!(script.contains("score.notes('C#4/h, C#4', { clef: 'treble', stem: 'down' })"));
+        assert!(script.contains("score.notes('C#3/w', { clef: 'bass' })"));
+        assert!(script.contains(".addClef('bass')"));
+        assert_eq!(script.matches(".addKeySignature('E')").count(), 2);
+        assert!(script.contains("system.addConnector('singleLeft');"));
+        assert!(script.ends_with("vf.draw();\n"));
+    }
+}
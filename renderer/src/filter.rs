@@ -0,0 +1,269 @@
+//
+// CSS filters for the Canvas filter property
+//
+// A filter string is a list of filter functions, like `blur(2px) opacity(50%)`,
+// applied in order to each drawing operation before it is composited. Lengths
+// are in CSS pixels, not affected by the transform. Color filters change each
+// unpremultiplied color on its own, so backends without per-pixel filtering can
+// apply them to paint colors instead.
+//
+
+use crate::color::parse_color;
+use crate::display_list::Shadow;
+use serde::{Deserialize, Serialize};
+use tiny_skia::Color;
+
+/// One filter function
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Filter {
+    /// Gaussian blur with standard deviation in pixels
+    Blur(f32),
+    /// How much color is removed, from 0 to 1
+    Grayscale(f32),
+    /// Factor from 0 to 1 multiplied into alpha
+    Opacity(f32),
+    /// Factor multiplied into color components
+    Brightness(f32),
+    /// How much colors are inverted, from 0 to 1
+    Invert(f32),
+    /// Blurred copy in one color drawn under what is filtered
+    DropShadow(Shadow),
+}
+
+impl Filter {
+    /// Same filter with lengths multiplied by scale
+    pub fn scaled(&self, scale: f32) -> Filter {
+        return match self {
+            Filter::Blur(deviation) => Filter::Blur(deviation * scale),
+            Filter::DropShadow(shadow) => Filter::DropShadow(shadow.scaled(scale)),
+            filter => *filter,
+        };
+    }
+
+    /// Whether filter changes each color on its own, as apply_to_color does
+    pub fn is_color(&self) -> bool {
+        return !matches!(self, Filter::Blur(_) | Filter::DropShadow(_));
+    }
+
+    /// Color after filtering, blur and drop shadow leave it as it is
+    pub fn apply_to_color(&self, color: Color) -> Color {
+        let (r, g, b, a) = (color.red(), color.green(), color.blue(), color.alpha());
+        let (r, g, b, a) = match *self {
+            Filter::Blur(_) | Filter::DropShadow(_) => return color,
+            // Matrix from the Filter Effects spec
+            Filter::Grayscale(amount) => {
+                let s = 1.0 - amount;
+                (
+                    (0.2126 + 0.7874 * s) * r
+                        + (0.7152 - 0.7152 * s) * g
+                        + (0.0722 - 0.0722 * s) * b,
+                    (0.2126 - 0.2126 * s) * r
+                        + (0.7152 + 0.2848 * s) * g
+                        + (0.0722 - 0.0722 * s) * b,
+                    (0.2126 - 0.2126 * s) * r
+                        + (0.7152 - 0.7152 * s) * g
+                        + (0.0722 + 0.9278 * s) * b,
+                    a,
+                )
+            }
+            Filter::Opacity(amount) => (r, g, b, a * amount),
+            Filter::Brightness(amount) => (r * amount, g * amount, b * amount, a),
+            Filter::Invert(amount) => {
+                let invert = |c: f32| amount + c * (1.0 - 2.0 * amount);
+                (invert(r), invert(g), invert(b), a)
+            }
+        };
+        let clamp = |c: f32| c.clamp(0.0, 1.0);
+        return Color::from_rgba(clamp(r), clamp(g), clamp(b), clamp(a)).unwrap_or(color);
+    }
+}
+
+/// Index of parenthesis closing one already open, None if there is none
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    return None;
+}
+
+/// Split at whitespace outside of parentheses
+fn split_args(text: &str) -> Vec<&str> {
+    let mut args = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            c if c.is_whitespace() && depth == 0 => {
+                if start < i {
+                    args.push(&text[start..i]);
+                }
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    if start < text.len() {
+        args.push(&text[start..]);
+    }
+    return args;
+}
+
+/// Length in px, unitless 0 is allowed too
+fn parse_length(text: &str) -> Option<f32> {
+    if text == "0" {
+        return Some(0.0);
+    }
+    let value: f32 = text.strip_suffix("px")?.parse().ok()?;
+    return value.is_finite().then_some(value);
+}
+
+/// Amount as number or percentage, 1 if missing, negative amounts are not valid
+fn parse_amount(text: &str) -> Option<f32> {
+    if text.is_empty() {
+        return Some(1.0);
+    }
+    let value = match text.strip_suffix('%') {
+        Some(percent) => percent.parse::<f32>().ok()? / 100.0,
+        None => text.parse::<f32>().ok()?,
+    };
+    return (value >= 0.0 && value.is_finite()).then_some(value);
+}
+
+/// Arguments of drop-shadow(): optional color before or after 2 or 3 lengths
+fn parse_drop_shadow(text: &str) -> Option<Shadow> {
+    let args = split_args(text);
+    let lengths: Vec<f32> = args.iter().filter_map(|arg| parse_length(arg)).collect();
+    let colors: Vec<&str> = args
+        .iter()
+        .copied()
+        .filter(|arg| parse_length(arg).is_none())
+        .collect();
+    let color = match colors[..] {
+        [] => Color::BLACK,
+        [color] if args[0] == color || args[args.len() - 1] == color => parse_color(color)?,
+        _ => return None,
+    };
+    let (offset_x, offset_y, blur) = match lengths[..] {
+        [x, y] => (x, y, 0.0),
+        [x, y, blur] if blur >= 0.0 => (x, y, blur),
+        _ => return None,
+    };
+    return Some(Shadow {
+        color,
+        blur,
+        offset_x,
+        offset_y,
+    });
+}
+
+/// One filter function from its name and the text between its parentheses
+fn parse_function(name: &str, args: &str) -> Option<Filter> {
+    return match name {
+        "blur" if args.is_empty() => Some(Filter::Blur(0.0)),
+        "blur" => parse_length(args)
+            .filter(|deviation| *deviation >= 0.0)
+            .map(Filter::Blur),
+        "grayscale" => Some(Filter::Grayscale(parse_amount(args)?.min(1.0))),
+        "opacity" => Some(Filter::Opacity(parse_amount(args)?.min(1.0))),
+        "brightness" => Some(Filter::Brightness(parse_amount(args)?)),
+        "invert" => Some(Filter::Invert(parse_amount(args)?.min(1.0))),
+        "drop-shadow" => parse_drop_shadow(args).map(Filter::DropShadow),
+        _ => None,
+    };
+}
+
+/// Parse CSS filter string into filter functions, None if it is not valid
+///
+/// `none` gives no filters. Only px lengths are understood.
+pub fn parse_filter(text: &str) -> Option<Vec<Filter>> {
+    let mut rest = text.trim();
+    if rest == "none" {
+        return Some(vec![]);
+    }
+    let mut filters = vec![];
+    while !rest.is_empty() {
+        let (name, after) = rest.split_once('(')?;
+        let close = closing_paren(after)?;
+        filters.push(parse_function(name, after[..close].trim())?);
+        rest = after[close + 1..].trim_start();
+    }
+    if filters.is_empty() {
+        return None;
+    }
+    return Some(filters);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!(parse_filter("none"), Some(vec![]));
+        assert_eq!(
+            parse_filter(" blur(2px) grayscale(50%)opacity() brightness(1.5) invert(2)"),
+            Some(vec![
+                Filter::Blur(2.0),
+                Filter::Grayscale(0.5),
+                Filter::Opacity(1.0),
+                Filter::Brightness(1.5),
+                Filter::Invert(1.0),
+            ])
+        );
+        assert_eq!(
+            parse_filter("drop-shadow(rgba(255, 0, 0, 0.5) 1px -2px 3px)"),
+            Some(vec![Filter::DropShadow(Shadow {
+                color: Color::from_rgba(1.0, 0.0, 0.0, 0.5).unwrap(),
+                blur: 3.0,
+                offset_x: 1.0,
+                offset_y: -2.0,
+            })])
+        );
+        assert_eq!(
+            parse_filter("drop-shadow(0 4px)"),
+            Some(vec![Filter::DropShadow(Shadow {
+                color: Color::BLACK,
+                blur: 0.0,
+                offset_x: 0.0,
+                offset_y: 4.0,
+            })])
+        );
+        for bad in [
+            "",
+            "blur(2)",
+            "blur(-1px)",
+            "grayscale(-10%)",
+            "sepia(1)",
+            "blur(2px",
+            "drop-shadow(1px)",
+            "drop-shadow(1px red 2px)",
+        ] {
+            assert_eq!(parse_filter(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_apply_to_color() {
+        let color = Color::from_rgba(1.0, 0.5, 0.0, 0.8).unwrap();
+        let gray = Filter::Grayscale(1.0).apply_to_color(color);
+        assert!((gray.red() - 0.5702).abs() < 1e-4);
+        assert_eq!(gray.red(), gray.blue());
+        assert_eq!(Filter::Grayscale(0.0).apply_to_color(color), color);
+        assert_eq!(Filter::Opacity(0.5).apply_to_color(color).alpha(), 0.4);
+        assert_eq!(Filter::Brightness(2.0).apply_to_color(color).green(), 1.0);
+        let inverted = Filter::Invert(1.0).apply_to_color(color);
+        assert_eq!((inverted.red(), inverted.blue()), (0.0, 1.0));
+        assert_eq!(Filter::Blur(2.0).apply_to_color(color), color);
+        assert!(Filter::Invert(1.0).is_color());
+        assert!(!Filter::Blur(2.0).is_color());
+    }
+}
@@ -0,0 +1,652 @@
+//
+// Built-in fonts, font metrics and font string parsing
+//
+// All glyphs are drawn from fonts bundled at compile time. The font family
+// requested by VexFlow is ignored, FontLibrary chooses the font per codepoint.
+//
+
+use ab_glyph::{point, Font, FontVec, Glyph, GlyphId, OutlineCurve, PxScaleFont, ScaleFont};
+use regex_macro::regex;
+use rquickjs::class::Trace;
+use tiny_skia::{Path, PathBuilder};
+
+/// A library of fonts that are ready to use
+pub struct FontLibrary {
+    /// Owned font Bravura for musical glyphs
+    bravura_font: FontVec,
+    /// Owned font for regular text (used for many things, e.g. fingering numbers)
+    regular_font: FontVec,
+    /// Owned font for italic text (often used, e.g. 8va annotation)
+    italic_font: FontVec,
+    /// Owned font for bold text (used for some things, e.g. certain types of tab fingerings)
+    bold_font: FontVec,
+    /// Owned font for bold italic text (mostly for completeness)
+    bold_italic_font: FontVec,
+}
+
+impl FontLibrary {
+    /// Creates a filled font library with build-in fonts.
+    ///
+    pub fn new() -> Self {
+        FontLibrary {
+            bravura_font: FontVec::try_from_vec(include_bytes!("../fonts/Bravura.otf").to_vec())
+                .expect("Failed to load Bravura.otf embedded font"),
+            regular_font: FontVec::try_from_vec(
+                //include_bytes!("../fonts/EBGaramond-VariableFont_wght.ttf").to_vec(),
+                include_bytes!("../fonts/AcademicoRegular.otf").to_vec(),
+            )
+            .expect("Failed to load AcademicoRegular.otf embedded font"),
+            italic_font: FontVec::try_from_vec(
+                include_bytes!("../fonts/AcademicoItalic.otf").to_vec(),
+            )
+            .expect("Failed to load AcademicoItalic.otf embedded font"),
+            bold_font: FontVec::try_from_vec(include_bytes!("../fonts/AcademicoBold.otf").to_vec())
+                .expect("Failed to load AcademicoBold.otf embedded font"),
+            bold_italic_font: FontVec::try_from_vec(
+                include_bytes!("../fonts/AcademicoBoldItalic.otf").to_vec(),
+            )
+            .expect("Failed to load AcademicoBoldItalic.otf embedded font"),
+        }
+    }
+
+    /// Decide if a codepoint is in SMuFL
+    fn is_in_smufl(codepoint: u32) -> bool {
+        // Values comes from:
+        // https://www.w3.org/2021/03/smufl14/about/recommended-chars-optional-glyphs.html
+        return codepoint >= 0xe000 && codepoint <= 0xf8ff;
+    }
+
+    /// Given a specific codepoint, compute outline glyph
+    ///
+    /// No font family is given here. The FontLibrary takes care of choosing the
+    /// font to use.
+    ///
+    /// Resolution order:
+    /// 1) Musical glyphs
+    /// 2) Text font with correct combination of bold/italic
+    ///
+    /// The position x, y is needed to account for differences in rendering
+    /// based on subpixel aliasing. The x,y position passed should be fractions
+    /// of pixel units.
+    ///
+    pub fn lookup_glyph(
+        &mut self,
+        codepoint: u32,
+        size: f32,
+        italic: bool,
+        bold: bool,
+        x: f32,
+        y: f32,
+    ) -> (PxScaleFont<&FontVec>, Glyph) {
+        let ch = char::from_u32(codepoint).expect("Illegal codepoint, is not a char");
+        // For SMUFL codepoints, use Bravura
+        if Self::is_in_smufl(codepoint) {
+            let chosen_font = &self.bravura_font;
+            let scale = chosen_font.pt_to_px_scale(size).expect("Illegal font size");
+            let glyph = chosen_font
+                .glyph_id(ch)
+                .with_scale_and_position(scale, point(x, y));
+            // See if we have a glyph in Bravura, return it if so
+            return (chosen_font.as_scaled(scale), glyph);
+        }
+        // For non-SMUFL, lookup right font based on italic/bold
+        let chosen_font = if italic {
+            if bold {
+                &self.bold_italic_font
+            } else {
+                &self.italic_font
+            }
+        } else {
+            if bold {
+                &self.bold_font
+            } else {
+                &self.regular_font
+            }
+        };
+        let scale = chosen_font.pt_to_px_scale(size).expect("Illegal font size");
+        let glyph = chosen_font
+            .glyph_id(ch)
+            .with_scale_and_position(scale, point(x, y));
+        return (chosen_font.as_scaled(scale), glyph);
+    }
+
+    /// PostScript name of the built-in font used to draw a codepoint
+    pub fn font_name_for(codepoint: u32, italic: bool, bold: bool) -> &'static str {
+        if Self::is_in_smufl(codepoint) {
+            return "Bravura";
+        }
+        return match (italic, bold) {
+            (false, false) => "Academico-Regular",
+            (true, false) => "Academico-Italic",
+            (false, true) => "Academico-Bold",
+            (true, true) => "Academico-BoldItalic",
+        };
+    }
+
+    /// Font family name and style of a built-in font
+    ///
+    /// Returns (family, italic, bold) for describing the font to other
+    /// programs, for example in SVG output.
+    pub fn font_style(name: &str) -> (&'static str, bool, bool) {
+        return match name {
+            "Academico-Regular" => ("Academico", false, false),
+            "Academico-Italic" => ("Academico", true, false),
+            "Academico-Bold" => ("Academico", false, true),
+            "Academico-BoldItalic" => ("Academico", true, true),
+            _ => ("Bravura", false, false),
+        };
+    }
+
+    /// Built-in font with given PostScript name
+    pub fn font_named(&self, name: &str) -> Option<&FontVec> {
+        return match name {
+            "Bravura" => Some(&self.bravura_font),
+            "Academico-Regular" => Some(&self.regular_font),
+            "Academico-Italic" => Some(&self.italic_font),
+            "Academico-Bold" => Some(&self.bold_font),
+            "Academico-BoldItalic" => Some(&self.bold_italic_font),
+            _ => None,
+        };
+    }
+
+    /// Built-in font with given PostScript name, scaled to font size in pt
+    pub fn scaled_font(&self, name: &str, size: f32) -> Option<PxScaleFont<&FontVec>> {
+        let font = self.font_named(name)?;
+        let scale = font.pt_to_px_scale(size).expect("Illegal font size");
+        return Some(font.as_scaled(scale));
+    }
+
+    /// Size of the em square in user units for font size in pt
+    pub fn em_size(&self, name: &str, size: f32) -> f32 {
+        let Some(scaled_font) = self.scaled_font(name, size) else {
+            return size;
+        };
+        return scaled_font.h_scale_factor() * scaled_font.font().units_per_em().unwrap_or(1000.0);
+    }
+
+    /// Ascent above and descent below the baseline in user units, both positive
+    pub fn vertical_metrics(&self, name: &str, size: f32) -> (f32, f32) {
+        let Some(scaled_font) = self.scaled_font(name, size) else {
+            return (0.0, 0.0);
+        };
+        return (scaled_font.ascent(), -scaled_font.descent());
+    }
+
+    /// Resolve codepoint to a glyph of a specific built-in font.
+    ///
+    /// Drawing operations are recorded with resolved glyphs, so backends
+    /// can refer to fonts by name and glyph id.
+    pub fn resolve_glyph(
+        &mut self,
+        codepoint: u32,
+        size: f32,
+        italic: bool,
+        bold: bool,
+    ) -> ResolvedGlyph {
+        let (scaled_font, glyph) = self.lookup_glyph(codepoint, size, italic, bold, 0.0, 0.0);
+        let units_per_em = scaled_font.font().units_per_em().unwrap_or(1000.0);
+        let advance = scaled_font.font().h_advance_unscaled(glyph.id) * 1000.0 / units_per_em;
+        return ResolvedGlyph {
+            font: Self::font_name_for(codepoint, italic, bold),
+            id: glyph.id.0,
+            advance,
+            width: scaled_font.h_advance(glyph.id),
+            has_outline: scaled_font.font().outline(glyph.id).is_some(),
+        };
+    }
+
+    /// Compute outline of glyph as a path.
+    ///
+    /// Path is in user coordinates with the glyph origin (left end of
+    /// baseline) at x, y. Returns None for glyphs with no outline, like space.
+    pub fn glyph_path(&self, font: &str, id: u16, size: f32, x: f32, y: f32) -> Option<Path> {
+        let scaled_font = self.scaled_font(font, size)?;
+        let outline = scaled_font.font().outline(GlyphId(id))?;
+        let h_scale = scaled_font.h_scale_factor();
+        let v_scale = scaled_font.v_scale_factor();
+        // Font units have y going up, flip to go down like the canvas
+        let map = |p: ab_glyph::Point| (x + p.x * h_scale, y - p.y * v_scale);
+        let mut builder = PathBuilder::new();
+        let mut last: Option<ab_glyph::Point> = None;
+        for curve in outline.curves {
+            let start = match curve {
+                OutlineCurve::Line(p0, _) => p0,
+                OutlineCurve::Quad(p0, _, _) => p0,
+                OutlineCurve::Cubic(p0, _, _, _) => p0,
+            };
+            // Curves of one contour are contiguous, a jump means a new contour
+            if last != Some(start) {
+                if last.is_some() {
+                    builder.close();
+                }
+                let (sx, sy) = map(start);
+                builder.move_to(sx, sy);
+            }
+            let end = match curve {
+                OutlineCurve::Line(_, p1) => {
+                    let (x1, y1) = map(p1);
+                    builder.line_to(x1, y1);
+                    p1
+                }
+                OutlineCurve::Quad(_, p1, p2) => {
+                    let (x1, y1) = map(p1);
+                    let (x2, y2) = map(p2);
+                    builder.quad_to(x1, y1, x2, y2);
+                    p2
+                }
+                OutlineCurve::Cubic(_, p1, p2, p3) => {
+                    let (x1, y1) = map(p1);
+                    let (x2, y2) = map(p2);
+                    let (x3, y3) = map(p3);
+                    builder.cubic_to(x1, y1, x2, y2, x3, y3);
+                    p3
+                }
+            };
+            last = Some(end);
+        }
+        builder.close();
+        return builder.finish();
+    }
+}
+
+/// A glyph of one of the built-in fonts
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ResolvedGlyph {
+    /// PostScript name of the font
+    pub font: &'static str,
+    /// Glyph id within the font
+    pub id: u16,
+    /// Advance width in thousandths of an em
+    pub advance: f32,
+    /// Advance width in user units
+    pub width: f32,
+    /// Whether glyph has an outline to draw, space does not
+    pub has_outline: bool,
+}
+
+/// Metrics to describe one or more glyphs
+/// Attempts to be compatible with browser TextMetrics
+#[derive(Trace)]
+#[rquickjs::class(rename_all = "camelCase")]
+pub struct FontMetrics {
+    #[qjs(get, set)]
+    pub width: f64,
+    #[qjs(get, set)]
+    pub font_bounding_box_ascent: f64,
+    #[qjs(get, set)]
+    pub font_bounding_box_descent: f64,
+    #[qjs(get, set)]
+    pub actual_bounding_box_ascent: f64,
+    #[qjs(get, set)]
+    pub actual_bounding_box_descent: f64,
+    #[qjs(get, set)]
+    pub actual_bounding_box_left: f64,
+    #[qjs(get, set)]
+    pub actual_bounding_box_right: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontInfo {
+    pub family: Vec<String>,
+    /// Size is measured in pt (and assumed to be 4/3 px which assumes dpi of 72)
+    pub size: f64,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+/// Horizontal alignment of text relative to its x position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextAlign {
+    Start,
+    End,
+    Left,
+    Right,
+    Center,
+}
+
+/// Vertical position of text relative to its y position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextBaseline {
+    Alphabetic,
+    Top,
+    Hanging,
+    Middle,
+    Ideographic,
+    Bottom,
+}
+
+/// Direction of text, only decides which side start and end alignment are
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Ltr,
+    Rtl,
+    Inherit,
+}
+
+/// Canvas name of text alignment
+pub(crate) fn text_align_name(align: TextAlign) -> &'static str {
+    return match align {
+        TextAlign::Start => "start",
+        TextAlign::End => "end",
+        TextAlign::Left => "left",
+        TextAlign::Right => "right",
+        TextAlign::Center => "center",
+    };
+}
+
+/// Text alignment from Canvas name
+pub(crate) fn parse_text_align(name: &str) -> Option<TextAlign> {
+    return match name {
+        "start" => Some(TextAlign::Start),
+        "end" => Some(TextAlign::End),
+        "left" => Some(TextAlign::Left),
+        "right" => Some(TextAlign::Right),
+        "center" => Some(TextAlign::Center),
+        _ => None,
+    };
+}
+
+/// Canvas name of text baseline
+pub(crate) fn text_baseline_name(baseline: TextBaseline) -> &'static str {
+    return match baseline {
+        TextBaseline::Alphabetic => "alphabetic",
+        TextBaseline::Top => "top",
+        TextBaseline::Hanging => "hanging",
+        TextBaseline::Middle => "middle",
+        TextBaseline::Ideographic => "ideographic",
+        TextBaseline::Bottom => "bottom",
+    };
+}
+
+/// Text baseline from Canvas name
+pub(crate) fn parse_text_baseline(name: &str) -> Option<TextBaseline> {
+    return match name {
+        "alphabetic" => Some(TextBaseline::Alphabetic),
+        "top" => Some(TextBaseline::Top),
+        "hanging" => Some(TextBaseline::Hanging),
+        "middle" => Some(TextBaseline::Middle),
+        "ideographic" => Some(TextBaseline::Ideographic),
+        "bottom" => Some(TextBaseline::Bottom),
+        _ => None,
+    };
+}
+
+/// Canvas name of text direction
+pub(crate) fn direction_name(direction: Direction) -> &'static str {
+    return match direction {
+        Direction::Ltr => "ltr",
+        Direction::Rtl => "rtl",
+        Direction::Inherit => "inherit",
+    };
+}
+
+/// Text direction from Canvas name
+pub(crate) fn parse_direction(name: &str) -> Option<Direction> {
+    return match name {
+        "ltr" => Some(Direction::Ltr),
+        "rtl" => Some(Direction::Rtl),
+        "inherit" => Some(Direction::Inherit),
+        _ => None,
+    };
+}
+
+/// Offset from the x, y given for text to the left end of its baseline
+///
+/// Ascent and descent are of the font, positive. There is no hanging baseline
+/// in the built-in fonts, it is taken to be at 80% of the ascent.
+pub(crate) fn text_offset(
+    align: TextAlign,
+    baseline: TextBaseline,
+    direction: Direction,
+    width: f32,
+    ascent: f32,
+    descent: f32,
+) -> (f32, f32) {
+    let rtl = direction == Direction::Rtl;
+    let dx = match align {
+        TextAlign::Left => 0.0,
+        TextAlign::Right => -width,
+        TextAlign::Center => -width / 2.0,
+        TextAlign::Start if rtl => -width,
+        TextAlign::End if !rtl => -width,
+        TextAlign::Start | TextAlign::End => 0.0,
+    };
+    let dy = match baseline {
+        TextBaseline::Alphabetic => 0.0,
+        TextBaseline::Top => ascent,
+        TextBaseline::Hanging => 0.8 * ascent,
+        TextBaseline::Middle => (ascent - descent) / 2.0,
+        TextBaseline::Ideographic | TextBaseline::Bottom => -descent,
+    };
+    return (dx, dy);
+}
+
+pub(crate) fn unparse_font(info: &FontInfo) -> String {
+    let mut result: String = "".to_string();
+    let mut anything: bool = false;
+    if info.bold {
+        if anything {
+            result.push_str(" ");
+        }
+        result.push_str("bold");
+        anything = true;
+    }
+    if info.italic {
+        if anything {
+            result.push_str(" ");
+        }
+        result.push_str("italic");
+        anything = true;
+    }
+    if anything {
+        result.push_str(" ");
+    }
+    result.push_str(format!("{}pt", info.size).as_str());
+    let mut seen_family = false;
+    if !&info.family.is_empty() {
+        result.push_str(" ");
+    }
+    for fam in &info.family {
+        if seen_family {
+            result.push_str(",");
+        }
+        if fam.as_str().contains(" ") {
+            result.push_str(format!(r#""{}""#, fam).as_str());
+        } else {
+            result.push_str(&fam);
+        }
+        seen_family = true;
+    }
+    return result;
+}
+
+/// A span object that measures fonts
+///
+/// This is how VexFlow does font parsing, makes us do it lol
+#[derive(Trace)]
+#[rquickjs::class]
+pub struct SpanFontParser {
+    #[qjs(skip_trace)]
+    font_info: FontInfo,
+}
+
+/// Parse full fontname like "30pt Bravura,Academico" into FontInfo
+/// This is not full CSS parsing, just enough to get by.
+///
+/// Supports:
+///     family with fallbacks, quotes for spaces in family name
+///     size (pt/px)
+///     bold
+///     italic
+pub(crate) fn parse_font(font: &str) -> Option<FontInfo> {
+    // First split on spaces (but not spaces in quotes)
+    let mut result = FontInfo {
+        family: vec![],
+        size: 30.0,
+        italic: false,
+        bold: false,
+    };
+    let _: Vec<_> = regex!(r#"(?:[^\s"]+|"[^"]*")+"#)
+        .find_iter(font)
+        .map(|m| {
+            let term = m.as_str();
+            if term == "bold" {
+                result.bold = true;
+            } else if term == "italic" {
+                result.italic = true;
+            } else if let Some(captures) = regex!(r"^(\d+(\.\d*)?)pt").captures(term) {
+                // See if it is a "pt" size (allow decimal)
+                if let Some(value) = captures[1].parse::<f64>().ok() {
+                    result.size = value;
+                };
+            } else if let Some(captures) = regex!(r"^(\d+(\.\d*)?)px").captures(term) {
+                // See if it is a "pt" size (allow decimal)
+                if let Some(value) = captures[1].parse::<f64>().ok() {
+                    result.size = value * 3.0 / 4.0;
+                };
+            }
+        })
+        .collect();
+    return Some(result);
+}
+
+#[rquickjs::methods(rename_all = "camelCase")]
+impl SpanFontParser {
+    #[qjs(constructor)]
+    pub fn new() -> Self {
+        SpanFontParser {
+            font_info: FontInfo {
+                family: vec![],
+                size: 30.0,
+                bold: false,
+                italic: false,
+            },
+        }
+    }
+    #[qjs(get, rename = "font")]
+    pub fn get_font(&self) -> String {
+        return unparse_font(&self.font_info);
+    }
+    #[qjs(set, rename = "font")]
+    pub fn set_font(&mut self, font: String) {
+        if let Some(font_info) = parse_font(&font) {
+            self.font_info = font_info;
+            return;
+        }
+        println!("Could not parse font '{}'", &font);
+    }
+    #[qjs(get, rename = "fontSize")]
+    pub fn get_font_size(&self) -> String {
+        return format!("{}pt", self.font_info.size);
+    }
+    #[qjs(set, rename = "fontSize")]
+    pub fn set_font_size(&mut self, size: f64) {
+        self.font_info.size = size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_font() {
+        assert_eq!(
+            parse_font("9pt Academico"),
+            Some(FontInfo {
+                family: vec![],
+                size: 9.0,
+                bold: false,
+                italic: false,
+            })
+        );
+        assert_eq!(
+            parse_font("italic 10.72pt Academico"),
+            Some(FontInfo {
+                family: vec![],
+                size: 10.72,
+                bold: false,
+                italic: true,
+            })
+        );
+        assert_eq!(
+            parse_font("bold 24pt Bravura"),
+            Some(FontInfo {
+                family: vec![],
+                size: 24.0,
+                bold: true,
+                italic: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_unparse_font() {
+        assert_eq!(
+            unparse_font(&FontInfo {
+                family: vec![],
+                size: 20.0,
+                bold: false,
+                italic: false
+            }),
+            "20pt",
+        );
+        assert_eq!(
+            unparse_font(&FontInfo {
+                family: vec![],
+                size: 20.0,
+                bold: false,
+                italic: true
+            }),
+            "italic 20pt",
+        );
+        assert_eq!(
+            unparse_font(&FontInfo {
+                family: vec![],
+                size: 20.0,
+                bold: true,
+                italic: true
+            }),
+            "bold italic 20pt",
+        );
+        assert_eq!(
+            unparse_font(&FontInfo {
+                family: vec!["Bravura".to_string()],
+                size: 20.0,
+                bold: false,
+                italic: false
+            }),
+            "20pt Bravura",
+        );
+        assert_eq!(
+            unparse_font(&FontInfo {
+                family: vec!["Bravura".to_string(), "Lato Light".to_string()],
+                size: 20.5,
+                bold: false,
+                italic: true
+            }),
+            "italic 20.5pt Bravura,\"Lato Light\"",
+        );
+    }
+
+    #[test]
+    fn test_text_offset() {
+        let offset = |align, baseline, direction| {
+            return text_offset(align, baseline, direction, 40.0, 8.0, 2.0);
+        };
+        use Direction::*;
+        use TextAlign::*;
+        use TextBaseline::*;
+        assert_eq!(offset(Start, Alphabetic, Inherit), (0.0, 0.0));
+        assert_eq!(offset(Start, Top, Rtl), (-40.0, 8.0));
+        assert_eq!(offset(End, Bottom, Ltr), (-40.0, -2.0));
+        assert_eq!(offset(End, Ideographic, Rtl), (0.0, -2.0));
+        assert_eq!(offset(Center, Middle, Ltr), (-20.0, 3.0));
+        assert_eq!(offset(Right, Hanging, Ltr), (-40.0, 6.4));
+        assert_eq!(offset(Left, Alphabetic, Rtl), (0.0, 0.0));
+        assert_eq!(parse_text_align("center"), Some(Center));
+        assert_eq!(parse_text_baseline("middle"), Some(Middle));
+        assert_eq!(parse_direction("rtl"), Some(Rtl));
+        assert_eq!(parse_text_align("middle"), None);
+    }
+}
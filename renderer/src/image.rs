@@ -0,0 +1,508 @@
+//
+// Bitmap images for patterns, drawImage() and ImageData
+//
+// Images come from PNG files loaded with an Image object, like HTML img
+// elements, or from the surface of another DrawContext. In display lists and
+// traces they are stored as PNG data URLs, which SVG output can use as is.
+// ImageData gives JS the pixels themselves, unpremultiplied like in Canvas.
+//
+
+use crate::context::DrawContext;
+use crate::paint::{Pattern, Repetition};
+use rquickjs::class::Trace;
+use rquickjs::function::{Constructor, Opt, This};
+use rquickjs::{ArrayBuffer, Class, Ctx, Exception, FromJs, Function, Object, Value};
+use serde::{Deserialize, Serialize};
+use tiny_skia::{ColorU8, IntRect, Pixmap, Rect, Transform};
+
+const DATA_URL_PREFIX: &str = "data:image/png;base64,";
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Image loaded from a PNG file, standing in for HTMLImageElement
+///
+/// Loading is synchronous, the image can be used as soon as `src` is set.
+#[derive(Trace)]
+#[rquickjs::class]
+pub struct Image {
+    #[qjs(skip_trace)]
+    src: String,
+    #[qjs(skip_trace)]
+    pixmap: Option<Pixmap>,
+}
+
+#[rquickjs::methods(rename_all = "camelCase")]
+impl Image {
+    #[qjs(constructor)]
+    pub fn new() -> Self {
+        return Image {
+            src: String::new(),
+            pixmap: None,
+        };
+    }
+
+    #[qjs(get, rename = "src")]
+    pub fn get_src(&self) -> String {
+        return self.src.clone();
+    }
+
+    /// Load PNG file, relative paths are from the current directory.
+    #[qjs(set, rename = "src")]
+    pub fn set_src(&mut self, src: String) {
+        self.pixmap = match Pixmap::load_png(&src) {
+            Ok(pixmap) => Some(pixmap),
+            Err(e) => {
+                println!("Could not load image '{}': {}", src, e);
+                None
+            }
+        };
+        self.src = src;
+    }
+
+    /// Width in pixels, 0 until an image is loaded
+    #[qjs(get, rename = "width")]
+    pub fn get_width(&self) -> u32 {
+        return self.pixmap.as_ref().map_or(0, Pixmap::width);
+    }
+
+    /// Height in pixels, 0 until an image is loaded
+    #[qjs(get, rename = "height")]
+    pub fn get_height(&self) -> u32 {
+        return self.pixmap.as_ref().map_or(0, Pixmap::height);
+    }
+}
+
+impl Default for Image {
+    fn default() -> Self {
+        return Image::new();
+    }
+}
+
+/// Pixels of an image as RGBA bytes, standing in for Canvas ImageData
+///
+/// `data` is a Uint8ClampedArray, changes JS makes to it show up when the
+/// image data is put on a DrawContext.
+#[derive(Trace)]
+#[rquickjs::class]
+pub struct ImageData<'js> {
+    #[qjs(get, skip_trace)]
+    pub(crate) width: u32,
+    #[qjs(get, skip_trace)]
+    pub(crate) height: u32,
+    #[qjs(get)]
+    data: Object<'js>,
+}
+
+impl<'js> ImageData<'js> {
+    /// Image data of given size with RGBA bytes in a new Uint8ClampedArray
+    pub(crate) fn from_rgba(
+        ctx: &Ctx<'js>,
+        width: u32,
+        height: u32,
+        rgba: Vec<u8>,
+    ) -> rquickjs::Result<Self> {
+        let buffer = ArrayBuffer::new(ctx.clone(), rgba)?;
+        let constructor: Constructor = ctx.globals().get("Uint8ClampedArray")?;
+        return Ok(ImageData {
+            width,
+            height,
+            data: constructor.construct((buffer,))?,
+        });
+    }
+
+    /// Bytes of data as they are now
+    fn rgba(&self) -> rquickjs::Result<Vec<u8>> {
+        return typed_array_bytes(self.data.ctx(), &self.data);
+    }
+
+    /// Pixels of the part of image data in rect, None if rect is empty
+    pub(crate) fn to_pixmap(&self, rect: IntRect) -> rquickjs::Result<Option<Pixmap>> {
+        let Some(pixmap) = rgba_pixmap(&self.rgba()?, self.width, self.height) else {
+            return Ok(None);
+        };
+        return Ok(pixmap.clone_rect(rect));
+    }
+}
+
+#[rquickjs::methods(rename_all = "camelCase")]
+impl<'js> ImageData<'js> {
+    /// Either `new ImageData(width, height)`, transparent black, or
+    /// `new ImageData(data, width, height)` using the Uint8ClampedArray given,
+    /// where height can be left out.
+    #[qjs(constructor)]
+    pub fn new(
+        ctx: Ctx<'js>,
+        first: Value<'js>,
+        second: u32,
+        third: Opt<u32>,
+    ) -> rquickjs::Result<Self> {
+        if let Some(width) = first.as_number() {
+            let (width, height, length) = image_data_size(&ctx, width, second as f64)?;
+            let rgba = vec![0; length];
+            return ImageData::from_rgba(&ctx, width, height, rgba);
+        }
+        let data = Object::from_js(&ctx, first)?;
+        let length = typed_array_bytes(&ctx, &data)?.len();
+        let width = second;
+        let height = match third.0 {
+            Some(height) => height,
+            None if width > 0 => u32::try_from(length / 4 / width as usize).unwrap_or(0),
+            None => 0,
+        };
+        if width == 0 || height == 0 || rgba_length(width, height) != Some(length) {
+            return Err(Exception::throw_range(
+                &ctx,
+                "ImageData data length does not match width and height",
+            ));
+        }
+        return Ok(ImageData {
+            width,
+            height,
+            data,
+        });
+    }
+}
+
+/// Largest RGBA data in bytes, the most tiny-skia can make a pixmap of
+const MAX_RGBA_LENGTH: usize = i32::MAX as usize;
+
+/// Length in bytes of RGBA data of width by height pixels, None if too large
+pub(crate) fn rgba_length(width: u32, height: u32) -> Option<usize> {
+    return (width as usize)
+        .checked_mul(height as usize)?
+        .checked_mul(4)
+        .filter(|&length| length <= MAX_RGBA_LENGTH);
+}
+
+/// Width and height of image data as whole pixels, and length of its RGBA
+/// data in bytes. Throws RangeError if zero or too large.
+pub(crate) fn image_data_size(
+    ctx: &Ctx,
+    width: f64,
+    height: f64,
+) -> rquickjs::Result<(u32, u32, usize)> {
+    let (width, height) = (width.abs() as u32, height.abs() as u32);
+    if width == 0 || height == 0 {
+        return Err(Exception::throw_range(
+            ctx,
+            "ImageData width and height must not be 0",
+        ));
+    }
+    let Some(length) = rgba_length(width, height) else {
+        return Err(Exception::throw_range(ctx, "ImageData is too large"));
+    };
+    return Ok((width, height, length));
+}
+
+/// Bytes viewed by a typed array
+fn typed_array_bytes<'js>(ctx: &Ctx<'js>, array: &Object<'js>) -> rquickjs::Result<Vec<u8>> {
+    let buffer: ArrayBuffer = array.get("buffer")?;
+    let offset: usize = array.get("byteOffset")?;
+    let length: usize = array.get("byteLength")?;
+    let Some(bytes) = buffer.as_bytes() else {
+        return Err(Exception::throw_type(ctx, "ImageData buffer is detached"));
+    };
+    return Ok(bytes[offset..offset + length].to_vec());
+}
+
+/// Unpremultiplied RGBA bytes of rect of pixmap, transparent outside of it.
+///
+/// The size must have a valid rgba_length().
+pub(crate) fn pixmap_rgba(pixmap: &Pixmap, x: i32, y: i32, width: u32, height: u32) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(rgba_length(width, height).expect("size is checked"));
+    let (x, y) = (x as i64, y as i64);
+    for row in y..y + height as i64 {
+        for column in x..x + width as i64 {
+            let color = u32::try_from(column)
+                .ok()
+                .zip(u32::try_from(row).ok())
+                .and_then(|(column, row)| pixmap.pixel(column, row))
+                .map_or(ColorU8::from_rgba(0, 0, 0, 0), |pixel| pixel.demultiply());
+            rgba.extend([color.red(), color.green(), color.blue(), color.alpha()]);
+        }
+    }
+    return rgba;
+}
+
+/// Pixmap from unpremultiplied RGBA bytes, None if size is 0 or does not match
+pub(crate) fn rgba_pixmap(rgba: &[u8], width: u32, height: u32) -> Option<Pixmap> {
+    let mut pixmap = Pixmap::new(width, height)?;
+    if rgba.len() != pixmap.pixels().len() * 4 {
+        return None;
+    }
+    for (pixel, c) in pixmap.pixels_mut().iter_mut().zip(rgba.chunks(4)) {
+        *pixel = ColorU8::from_rgba(c[0], c[1], c[2], c[3]).premultiply();
+    }
+    return Some(pixmap);
+}
+
+/// Image argument of createPattern() and drawImage()
+///
+/// Accepts an Image, a DrawContext, or a Canvas from wrap.js.
+#[derive(Serialize, Deserialize)]
+pub struct ImageSource {
+    #[serde(with = "pixmap_serde")]
+    pub pixmap: Pixmap,
+    /// Size in user units, contexts with zoom have more pixels than this
+    pub width: f32,
+    pub height: f32,
+}
+
+impl<'js> FromJs<'js> for ImageSource {
+    fn from_js(ctx: &Ctx<'js>, value: Value<'js>) -> rquickjs::Result<Self> {
+        if let Ok(image) = Class::<Image>::from_js(ctx, value.clone()) {
+            let image = image.borrow();
+            let Some(pixmap) = &image.pixmap else {
+                return Err(rquickjs::Error::new_from_js_message(
+                    "Image",
+                    "ImageSource",
+                    format!("image '{}' is not loaded", image.src),
+                ));
+            };
+            return Ok(ImageSource {
+                pixmap: pixmap.clone(),
+                width: pixmap.width() as f32,
+                height: pixmap.height() as f32,
+            });
+        }
+        let context = match value.as_object() {
+            // Canvas objects hand out their DrawContext
+            Some(canvas) if canvas.contains_key("getContext")? => {
+                let get_context: Function = canvas.get("getContext")?;
+                get_context.call((This(canvas.clone()),))?
+            }
+            _ => Class::<DrawContext>::from_js(ctx, value)?,
+        };
+        return Ok(context.borrow().image_source());
+    }
+}
+
+impl ImageSource {
+    /// Pattern showing source rect of image in destination rect, and the part
+    /// of destination rect to fill with it. None if nothing is drawn.
+    ///
+    /// Rects are x, y, width, height, in user units of image and of drawing.
+    /// Like in Canvas, negative sizes do not mirror, and the source rect is
+    /// trimmed to the image with the destination rect trimmed to match.
+    pub(crate) fn draw_pattern(self, src: [f32; 4], dst: [f32; 4]) -> Option<(Pattern, Rect)> {
+        let normalize = |[x, y, w, h]: [f32; 4]| {
+            return [x + w.min(0.0), y + h.min(0.0), w.abs(), h.abs()];
+        };
+        let [sx, sy, sw, sh] = normalize(src);
+        let [dx, dy, dw, dh] = normalize(dst);
+        if sw == 0.0 || sh == 0.0 || dw == 0.0 || dh == 0.0 {
+            return None;
+        }
+        let (scale_x, scale_y) = (dw / sw, dh / sh);
+        let visible = Rect::from_ltrb(
+            sx.max(0.0),
+            sy.max(0.0),
+            (sx + sw).min(self.width),
+            (sy + sh).min(self.height),
+        )?;
+        if visible.width() == 0.0 || visible.height() == 0.0 {
+            return None;
+        }
+        let rect = Rect::from_xywh(
+            dx + (visible.x() - sx) * scale_x,
+            dy + (visible.y() - sy) * scale_y,
+            visible.width() * scale_x,
+            visible.height() * scale_y,
+        )?;
+        let pattern = Pattern {
+            image: self.pixmap,
+            width: self.width,
+            height: self.height,
+            repetition: Repetition::NoRepeat,
+            transform: Transform::from_row(
+                scale_x,
+                0.0,
+                0.0,
+                scale_y,
+                dx - sx * scale_x,
+                dy - sy * scale_y,
+            ),
+        };
+        return Some((pattern, rect));
+    }
+}
+
+/// Encode bytes as base64 with padding
+fn base64_encode(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+            bits | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64_ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    return text;
+}
+
+/// Decode base64 with optional padding, None if not valid
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=').as_bytes();
+    let mut data = Vec::with_capacity(text.len() * 3 / 4);
+    for chunk in text.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let mut bits = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|a| a == c)? as u32;
+            bits |= value << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            data.push((bits >> (16 - 8 * i)) as u8);
+        }
+    }
+    return Some(data);
+}
+
+/// Image as a `data:image/png;base64,` URL
+pub(crate) fn png_data_url(pixmap: &Pixmap) -> String {
+    let png = pixmap
+        .encode_png()
+        .expect("Pixmap can always be encoded as PNG");
+    return format!("{}{}", DATA_URL_PREFIX, base64_encode(&png));
+}
+
+/// Decode image from PNG data URL written by png_data_url()
+pub(crate) fn parse_png_data_url(url: &str) -> Option<Pixmap> {
+    let png = base64_decode(url.strip_prefix(DATA_URL_PREFIX)?)?;
+    return Pixmap::decode_png(&png).ok();
+}
+
+/// Images as PNG data URLs
+pub(crate) mod pixmap_serde {
+    use super::{parse_png_data_url, png_data_url};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use tiny_skia::Pixmap;
+
+    pub fn serialize<S: Serializer>(pixmap: &Pixmap, serializer: S) -> Result<S::Ok, S::Error> {
+        return png_data_url(pixmap).serialize(serializer);
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pixmap, D::Error> {
+        return parse_png_data_url(&String::deserialize(deserializer)?)
+            .ok_or_else(|| serde::de::Error::custom("image should be a PNG data URL"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_skia::Color;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        for text in ["", "f", "fo", "foo", "foob", "fooba", "foobar"] {
+            assert_eq!(
+                base64_decode(&base64_encode(text.as_bytes())).unwrap(),
+                text.as_bytes()
+            );
+        }
+        assert_eq!(base64_decode("Z"), None);
+        assert_eq!(base64_decode("Zm9v!"), None);
+    }
+
+    #[test]
+    fn test_data_url() {
+        let mut pixmap = Pixmap::new(3, 2).unwrap();
+        pixmap.fill(Color::from_rgba8(10, 20, 30, 255));
+        let url = png_data_url(&pixmap);
+        assert!(url.starts_with("data:image/png;base64,iVBORw0KGgo"));
+        assert_eq!(parse_png_data_url(&url).unwrap(), pixmap);
+        assert!(parse_png_data_url("data:image/jpeg;base64,AAAA").is_none());
+    }
+
+    #[test]
+    fn test_rgba() {
+        let mut pixmap = Pixmap::new(2, 1).unwrap();
+        pixmap.fill(Color::from_rgba8(255, 0, 0, 128));
+        assert_eq!(
+            pixmap_rgba(&pixmap, -1, 0, 3, 1),
+            [0, 0, 0, 0, 255, 0, 0, 128, 255, 0, 0, 128]
+        );
+        let rgba = [0, 255, 0, 255, 10, 20, 30, 0];
+        let pixmap = rgba_pixmap(&rgba, 2, 1).unwrap();
+        assert_eq!(pixmap.pixel(0, 0).unwrap().green(), 255);
+        // Fully transparent pixels lose their color
+        assert_eq!(
+            pixmap_rgba(&pixmap, 0, 0, 2, 1),
+            [0, 255, 0, 255, 0, 0, 0, 0]
+        );
+        assert!(rgba_pixmap(&rgba, 1, 1).is_none());
+        assert!(rgba_pixmap(&[], 0, 0).is_none());
+        // Rows past the largest i32 are outside too
+        assert_eq!(pixmap_rgba(&pixmap, 0, i32::MAX, 1, 2), [0; 8]);
+    }
+
+    #[test]
+    fn test_image_data_size() {
+        assert_eq!(rgba_length(3, 2), Some(24));
+        assert_eq!(rgba_length(70000, 70000), None);
+        assert_eq!(rgba_length(u32::MAX, u32::MAX), None);
+        let runtime = rquickjs::Runtime::new().unwrap();
+        let context = rquickjs::Context::full(&runtime).unwrap();
+        context.with(|ctx| {
+            assert_eq!(image_data_size(&ctx, 3.0, -2.0).unwrap(), (3, 2, 24));
+            Class::<ImageData>::define(&ctx.globals()).unwrap();
+            let error = |script: &str| -> String {
+                let script = format!("try {{ {}; '' }} catch (e) {{ e.name }}", script);
+                return ctx.eval(script).unwrap();
+            };
+            assert_eq!(error("new ImageData(70000, 70000)"), "RangeError");
+            assert_eq!(error("new ImageData(1e10, 1)"), "RangeError");
+            assert_eq!(error("new ImageData(0, 1)"), "RangeError");
+            assert_eq!(
+                error("new ImageData(new Uint8ClampedArray(8), 2, 65536)"),
+                "RangeError"
+            );
+            assert_eq!(error("new ImageData(2, 1)"), "");
+        });
+    }
+
+    #[test]
+    fn test_draw_pattern() {
+        let source = || ImageSource {
+            pixmap: Pixmap::new(20, 10).unwrap(),
+            width: 10.0,
+            height: 5.0,
+        };
+        let (pattern, rect) = source()
+            .draw_pattern([0.0, 0.0, 10.0, 5.0], [3.0, 4.0, 10.0, 5.0])
+            .unwrap();
+        assert_eq!(rect, Rect::from_xywh(3.0, 4.0, 10.0, 5.0).unwrap());
+        assert_eq!(pattern.transform, Transform::from_translate(3.0, 4.0));
+        assert_eq!(pattern.repetition, Repetition::NoRepeat);
+        assert_eq!((pattern.width, pattern.height), (10.0, 5.0));
+
+        // Source sticking out of image on the left, negative sizes
+        let (pattern, rect) = source()
+            .draw_pattern([5.0, 5.0, -10.0, -5.0], [20.0, 20.0, 20.0, 10.0])
+            .unwrap();
+        assert_eq!(rect, Rect::from_xywh(30.0, 20.0, 10.0, 10.0).unwrap());
+        assert_eq!(
+            pattern.transform,
+            Transform::from_row(2.0, 0.0, 0.0, 2.0, 30.0, 20.0)
+        );
+
+        assert!(source()
+            .draw_pattern([10.0, 0.0, 5.0, 5.0], [0.0, 0.0, 5.0, 5.0])
+            .is_none());
+        assert!(source()
+            .draw_pattern([0.0, 0.0, 10.0, 5.0], [0.0, 0.0, 0.0, 5.0])
+            .is_none());
+    }
+}
@@ -0,0 +1,52 @@
+//
+// Lightweight renderer for VexFlow
+//
+// Recreates canvas CanvasContext2D interface with some limitations.
+//
+// SUPPORTED
+// * Rendering text and music glyphs
+// * Antialiased lines and strokes paths
+// * Antialiased rectangles and fills
+// * Sharp text scaling
+// * Solid colors, alpha blending
+// * Transparent background, erasing background
+// * Italic, bold options for text
+// * Arbitrary affine drawing transformation (rotations etc.), including for text
+// * Quadratic and cubic Bezier paths
+// * Arcs, including partial and counterclockwise ones
+// * Dashed lines
+// * Linear, radial and conic gradients, image patterns
+// * Save/Restore drawing state stack
+// * Set font size, fill style, stroke style with CSS style strings
+//
+// NOT SUPPORTED
+// * Only supports fixed font set, built-in to renderer at compile time
+// * Fonts can have italic and bold on/off but not other stuff
+// * Font and color parsing is just enough to work with VexFlow, not general
+// * Probably missing some functions
+//
+
+mod color;
+mod context;
+mod display_list;
+mod easyscore;
+mod filter;
+mod font;
+mod image;
+mod paint;
+mod path;
+mod pdf;
+mod raster;
+mod renderer;
+mod runtime;
+mod suite;
+mod svg;
+mod trace;
+
+pub use display_list::DisplayList;
+pub use easyscore::{EasyScore, EasyScoreStave};
+pub use renderer::{RenderError, RenderOptions, Renderer};
+pub use runtime::{load_vexflow_bundle, DEFAULT_VEXFLOW_BUNDLE};
+pub use suite::{run_suite, SuiteError, SuiteOptions, SuiteSummary};
+pub use svg::SvgTextMode;
+pub use trace::Trace;
@@ -0,0 +1,612 @@
+//
+// Paint styles for fills, strokes and text
+//
+// Canvas fillStyle and strokeStyle can be a CSS color, a CanvasGradient or a
+// CanvasPattern. Gradients and patterns are plain data here: each backend
+// turns them into its own shader, SVG paint server or PDF shading or tiling
+// pattern when drawing.
+//
+
+use crate::color::{parse_color, unparse_color};
+use crate::display_list::{color_serde, transform_serde};
+use crate::image::pixmap_serde;
+use rquickjs::class::Trace;
+use rquickjs::function::Opt;
+use rquickjs::{Class, Ctx, FromJs, IntoJs, Value};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+use tiny_skia::{Color, Path, PathBuilder, Pixmap, Point, Rect, Transform};
+
+/// Geometry of a gradient, in user coordinates at the time of drawing
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum GradientShape {
+    /// Colors change along line from (x0, y0) to (x1, y1)
+    Linear { x0: f32, y0: f32, x1: f32, y1: f32 },
+    /// Colors change going from circle (x0, y0, r0) to circle (x1, y1, r1)
+    Radial {
+        x0: f32,
+        y0: f32,
+        r0: f32,
+        x1: f32,
+        y1: f32,
+        r1: f32,
+    },
+    /// Colors change going clockwise around (x, y), starting at angle in radians
+    Conic { angle: f32, x: f32, y: f32 },
+}
+
+/// Color at offset from 0 to 1 along a gradient
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColorStop {
+    pub offset: f32,
+    #[serde(with = "color_serde")]
+    pub color: Color,
+}
+
+/// A gradient with its color stops
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Gradient {
+    #[serde(flatten)]
+    pub shape: GradientShape,
+    /// Stops in order of offset
+    pub stops: Vec<ColorStop>,
+}
+
+/// Directions in which a pattern image repeats
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Repetition {
+    Repeat,
+    RepeatX,
+    RepeatY,
+    NoRepeat,
+}
+
+/// An image repeated to fill shapes
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pattern {
+    #[serde(with = "pixmap_serde")]
+    pub image: Pixmap,
+    /// Size of one copy of the image in pattern space
+    pub width: f32,
+    pub height: f32,
+    pub repetition: Repetition,
+    /// From pattern space to user space
+    #[serde(with = "transform_serde")]
+    pub transform: Transform,
+}
+
+/// Paint for fills, strokes and text with all state resolved
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PaintStyle {
+    Color(#[serde(with = "color_serde")] Color),
+    Gradient(Gradient),
+    Pattern(Pattern),
+}
+
+/// Value of fillStyle or strokeStyle as set and read by JS
+///
+/// Colors are kept as CSS strings so that traces show what JS wrote.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum StyleValue {
+    Css(String),
+    Gradient(Gradient),
+    Pattern(Pattern),
+}
+
+/// Interpolate between colors without premultiplying, like Canvas
+fn mix(a: Color, b: Color, t: f32) -> Color {
+    let channel = |x: f32, y: f32| x + (y - x) * t;
+    return Color::from_rgba(
+        channel(a.red(), b.red()),
+        channel(a.green(), b.green()),
+        channel(a.blue(), b.blue()),
+        channel(a.alpha(), b.alpha()),
+    )
+    .unwrap_or(b);
+}
+
+impl Gradient {
+    pub fn new(shape: GradientShape) -> Self {
+        Gradient {
+            shape,
+            stops: vec![],
+        }
+    }
+
+    /// Add color stop, after any existing stops at the same offset.
+    pub fn add_color_stop(&mut self, offset: f32, color: Color) {
+        let index = self.stops.partition_point(|stop| stop.offset <= offset);
+        self.stops.insert(index, ColorStop { offset, color });
+    }
+
+    /// Whether gradient paints nothing, because it has no stops or its
+    /// geometry is degenerate
+    pub fn is_empty(&self) -> bool {
+        if self.stops.is_empty() {
+            return true;
+        }
+        return match self.shape {
+            GradientShape::Linear { x0, y0, x1, y1 } => x0 == x1 && y0 == y1,
+            GradientShape::Radial {
+                x0,
+                y0,
+                r0,
+                x1,
+                y1,
+                r1,
+            } => r0 < 0.0 || r1 < 0.0 || (x0 == x1 && y0 == y1 && r0 == r1),
+            GradientShape::Conic { .. } => false,
+        };
+    }
+
+    /// Color at offset t, padded with first and last colors outside the stops
+    pub fn color_at(&self, t: f32) -> Color {
+        let Some(first) = self.stops.first() else {
+            return Color::TRANSPARENT;
+        };
+        if t < first.offset {
+            return first.color;
+        }
+        for pair in self.stops.windows(2) {
+            if t < pair[1].offset {
+                let span = pair[1].offset - pair[0].offset;
+                return mix(pair[0].color, pair[1].color, (t - pair[0].offset) / span);
+            }
+        }
+        return self.stops[self.stops.len() - 1].color;
+    }
+
+    /// Radial gradient as a focal gradient, starting from a point instead of
+    /// a circle: (focal point, center, radius, stops).
+    ///
+    /// The circles of a radial gradient form a cone, so starting from its apex
+    /// and moving the stops gives the same colors. When both radii are equal
+    /// there is no apex and the start circle is treated as a point.
+    pub fn focal_radial(&self) -> Option<(Point, Point, f32, Vec<ColorStop>)> {
+        let GradientShape::Radial {
+            x0,
+            y0,
+            r0,
+            x1,
+            y1,
+            r1,
+        } = self.shape
+        else {
+            return None;
+        };
+        if (r1 - r0).abs() < 1e-6 {
+            return Some((
+                Point::from_xy(x0, y0),
+                Point::from_xy(x1, y1),
+                r1,
+                self.stops.clone(),
+            ));
+        }
+        // Gradient offset t where radius r0 + t (r1 - r0) reaches zero
+        let apex = -r0 / (r1 - r0);
+        let apex_point = Point::from_xy(x0 + apex * (x1 - x0), y0 + apex * (y1 - y0));
+        let (end, center, radius) = if r1 > r0 {
+            (1.0, Point::from_xy(x1, y1), r1)
+        } else {
+            (0.0, Point::from_xy(x0, y0), r0)
+        };
+        let mut stops: Vec<ColorStop> = self
+            .stops
+            .iter()
+            .map(|stop| ColorStop {
+                offset: (stop.offset - apex) / (end - apex),
+                color: stop.color,
+            })
+            .collect();
+        if end == 0.0 {
+            stops.reverse();
+        }
+        return Some((apex_point, center, radius, stops));
+    }
+
+    /// Conic gradient as wedges of solid color around its center, reaching
+    /// past every corner of bounds.
+    ///
+    /// For output formats that have no conic gradients. Wedges overlap a little
+    /// so that no gaps show between them.
+    pub fn conic_wedges(&self, bounds: Rect, count: usize) -> Vec<(Path, Color)> {
+        let GradientShape::Conic { angle, x, y } = self.shape else {
+            return vec![];
+        };
+        let radius = [
+            (bounds.left(), bounds.top()),
+            (bounds.right(), bounds.top()),
+            (bounds.left(), bounds.bottom()),
+            (bounds.right(), bounds.bottom()),
+        ]
+        .iter()
+        .map(|(px, py)| (px - x).hypot(py - y))
+        .fold(0.0, f32::max)
+            * 1.1
+            + 1.0;
+        let step = TAU / count as f32;
+        let mut wedges = vec![];
+        for i in 0..count {
+            let start = angle + step * i as f32;
+            let end = start + step * 1.5;
+            let mut pb = PathBuilder::new();
+            pb.move_to(x, y);
+            pb.line_to(x + radius * start.cos(), y + radius * start.sin());
+            pb.line_to(x + radius * end.cos(), y + radius * end.sin());
+            pb.close();
+            if let Some(path) = pb.finish() {
+                wedges.push((path, self.color_at((i as f32 + 0.5) / count as f32)));
+            }
+        }
+        return wedges;
+    }
+}
+
+/// Parse Canvas repetition, where empty means repeat
+pub(crate) fn parse_repetition(text: &str) -> Option<Repetition> {
+    return match text {
+        "" | "repeat" => Some(Repetition::Repeat),
+        "repeat-x" => Some(Repetition::RepeatX),
+        "repeat-y" => Some(Repetition::RepeatY),
+        "no-repeat" => Some(Repetition::NoRepeat),
+        _ => None,
+    };
+}
+
+impl Pattern {
+    /// Transform from image pixels to user space
+    pub fn image_transform(&self) -> Transform {
+        return self.transform.pre_scale(
+            self.width / self.image.width() as f32,
+            self.height / self.image.height() as f32,
+        );
+    }
+
+    /// Region covered by pattern when it does not repeat both ways, None when it does.
+    ///
+    /// Bounds are what needs covering, in the space transform maps user space
+    /// to, and the region returned is in that space too. Backends clip to it,
+    /// since they only have patterns that repeat in both directions.
+    pub fn coverage(&self, bounds: Rect, transform: Transform) -> Option<Path> {
+        if self.repetition == Repetition::Repeat {
+            return None;
+        }
+        let full = transform.pre_concat(self.transform);
+        // Repeats only need to reach as far as bounds in pattern space
+        let reach = full
+            .invert()
+            .and_then(|inverse| PathBuilder::from_rect(bounds).transform(inverse))
+            .map(|path| path.bounds());
+        let (mut left, mut top): (f32, f32) = (0.0, 0.0);
+        let (mut right, mut bottom) = (self.width, self.height);
+        if let Some(reach) = reach {
+            if self.repetition == Repetition::RepeatX {
+                left = left.min(reach.left());
+                right = right.max(reach.right());
+            }
+            if self.repetition == Repetition::RepeatY {
+                top = top.min(reach.top());
+                bottom = bottom.max(reach.bottom());
+            }
+        }
+        let rect = Rect::from_ltrb(left, top, right, bottom)?;
+        return PathBuilder::from_rect(rect).transform(full);
+    }
+}
+
+impl PaintStyle {
+    /// Value JS sees when reading fillStyle or strokeStyle
+    pub fn to_style_value(&self) -> StyleValue {
+        return match self {
+            PaintStyle::Color(color) => StyleValue::Css(unparse_color(color)),
+            PaintStyle::Gradient(gradient) => StyleValue::Gradient(gradient.clone()),
+            PaintStyle::Pattern(pattern) => StyleValue::Pattern(pattern.clone()),
+        };
+    }
+
+    /// Same paint with every color changed by f, including gradient stops and pattern pixels
+    pub fn map_colors(&self, f: impl Fn(Color) -> Color) -> PaintStyle {
+        return match self {
+            PaintStyle::Color(color) => PaintStyle::Color(f(*color)),
+            PaintStyle::Gradient(gradient) => {
+                let mut gradient = gradient.clone();
+                for stop in &mut gradient.stops {
+                    stop.color = f(stop.color);
+                }
+                PaintStyle::Gradient(gradient)
+            }
+            PaintStyle::Pattern(pattern) => {
+                let mut pattern = pattern.clone();
+                for pixel in pattern.image.pixels_mut() {
+                    let c = pixel.demultiply();
+                    let color = Color::from_rgba8(c.red(), c.green(), c.blue(), c.alpha());
+                    *pixel = f(color).premultiply().to_color_u8();
+                }
+                PaintStyle::Pattern(pattern)
+            }
+        };
+    }
+
+    /// Conic gradient of paint, if any, which vector output draws as wedges
+    pub fn conic(&self) -> Option<&Gradient> {
+        return match self {
+            PaintStyle::Gradient(
+                gradient @ Gradient {
+                    shape: GradientShape::Conic { .. },
+                    ..
+                },
+            ) => Some(gradient),
+            _ => None,
+        };
+    }
+}
+
+/// Describe paint like JS String() would, for traces
+pub(crate) fn unparse_paint(paint: &PaintStyle) -> String {
+    return match paint {
+        PaintStyle::Color(color) => unparse_color(color),
+        PaintStyle::Gradient(_) => "[object CanvasGradient]".to_string(),
+        PaintStyle::Pattern(_) => "[object CanvasPattern]".to_string(),
+    };
+}
+
+impl StyleValue {
+    /// Paint to draw with, None for colors that cannot be parsed
+    pub fn to_paint(&self) -> Option<PaintStyle> {
+        return match self {
+            StyleValue::Css(css) => parse_color(css).map(PaintStyle::Color),
+            StyleValue::Gradient(gradient) => Some(PaintStyle::Gradient(gradient.clone())),
+            StyleValue::Pattern(pattern) => Some(PaintStyle::Pattern(pattern.clone())),
+        };
+    }
+}
+
+/// Gradient object given to JS by createLinearGradient() and friends
+///
+/// Assigning it to fillStyle or strokeStyle copies the gradient, so color
+/// stops added afterwards do not change that style.
+#[derive(Trace)]
+#[rquickjs::class]
+pub struct CanvasGradient {
+    #[qjs(skip_trace)]
+    pub(crate) gradient: Gradient,
+}
+
+#[rquickjs::methods(rename_all = "camelCase")]
+impl CanvasGradient {
+    /// Add color at offset from 0 to 1 along gradient
+    pub fn add_color_stop(&mut self, offset: f64, color: String) {
+        if !(0.0..=1.0).contains(&offset) {
+            println!("Color stop offset {} out of range, ignoring", offset);
+            return;
+        }
+        let Some(color) = parse_color(&color) else {
+            println!("Bad color stop color '{}', ignoring", color);
+            return;
+        };
+        self.gradient.add_color_stop(offset as f32, color);
+    }
+
+    /// Describe gradient the way browsers do
+    #[qjs(rename = "toString")]
+    pub fn to_js_string(&self) -> String {
+        return "[object CanvasGradient]".to_string();
+    }
+}
+
+/// Pattern object given to JS by createPattern()
+///
+/// Like gradients, assigning it to a style copies it, so later calls to
+/// setTransform() do not change that style.
+#[derive(Trace)]
+#[rquickjs::class]
+pub(crate) struct CanvasPattern {
+    #[qjs(skip_trace)]
+    pub(crate) pattern: Pattern,
+}
+
+/// DOMMatrix or plain object with fields a to f, missing fields are identity
+pub(crate) struct MatrixInit(pub Transform);
+
+impl<'js> FromJs<'js> for MatrixInit {
+    fn from_js(ctx: &Ctx<'js>, value: Value<'js>) -> rquickjs::Result<Self> {
+        let object = rquickjs::Object::from_js(ctx, value)?;
+        let field = |name: &str, default: f32| -> rquickjs::Result<f32> {
+            let value: Option<f64> = object.get(name)?;
+            return Ok(value.map_or(default, |v| v as f32));
+        };
+        return Ok(MatrixInit(Transform::from_row(
+            field("a", 1.0)?,
+            field("b", 0.0)?,
+            field("c", 0.0)?,
+            field("d", 1.0)?,
+            field("e", 0.0)?,
+            field("f", 0.0)?,
+        )));
+    }
+}
+
+#[rquickjs::methods(rename_all = "camelCase")]
+impl CanvasPattern {
+    /// Set transform from pattern space to user space, no argument resets it
+    pub fn set_transform(&mut self, matrix: Opt<MatrixInit>) {
+        self.pattern.transform = matrix.0.map_or(Transform::identity(), |m| m.0);
+    }
+
+    /// Describe pattern the way browsers do
+    #[qjs(rename = "toString")]
+    pub fn to_js_string(&self) -> String {
+        return "[object CanvasPattern]".to_string();
+    }
+}
+
+impl<'js> FromJs<'js> for StyleValue {
+    fn from_js(ctx: &Ctx<'js>, value: Value<'js>) -> rquickjs::Result<Self> {
+        if let Ok(gradient) = Class::<CanvasGradient>::from_js(ctx, value.clone()) {
+            return Ok(StyleValue::Gradient(gradient.borrow().gradient.clone()));
+        }
+        if let Ok(pattern) = Class::<CanvasPattern>::from_js(ctx, value.clone()) {
+            return Ok(StyleValue::Pattern(pattern.borrow().pattern.clone()));
+        }
+        return Ok(StyleValue::Css(String::from_js(ctx, value)?));
+    }
+}
+
+impl<'js> IntoJs<'js> for StyleValue {
+    fn into_js(self, ctx: &Ctx<'js>) -> rquickjs::Result<Value<'js>> {
+        return match self {
+            StyleValue::Css(css) => css.into_js(ctx),
+            StyleValue::Gradient(gradient) => {
+                Class::instance(ctx.clone(), CanvasGradient { gradient })?.into_js(ctx)
+            }
+            StyleValue::Pattern(pattern) => {
+                Class::instance(ctx.clone(), CanvasPattern { pattern })?.into_js(ctx)
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> Gradient {
+        let mut gradient = Gradient::new(GradientShape::Linear {
+            x0: 0.0,
+            y0: 0.0,
+            x1: 10.0,
+            y1: 0.0,
+        });
+        gradient.add_color_stop(1.0, Color::WHITE);
+        gradient.add_color_stop(0.0, Color::BLACK);
+        gradient.add_color_stop(0.5, Color::from_rgba8(255, 0, 0, 255));
+        gradient.add_color_stop(0.5, Color::from_rgba8(0, 0, 255, 255));
+        return gradient;
+    }
+
+    #[test]
+    fn test_color_at() {
+        let gradient = gradient();
+        assert_eq!(gradient.stops[0].color, Color::BLACK);
+        assert_eq!(gradient.color_at(-1.0), Color::BLACK);
+        assert_eq!(
+            gradient.color_at(0.25),
+            Color::from_rgba(0.5, 0.0, 0.0, 1.0).unwrap()
+        );
+        // Later stop at same offset wins from there on
+        assert_eq!(gradient.color_at(0.5), Color::from_rgba8(0, 0, 255, 255));
+        assert_eq!(gradient.color_at(2.0), Color::WHITE);
+        assert!(!gradient.is_empty());
+        assert!(Gradient::new(gradient.shape.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_focal_radial() {
+        let mut gradient = Gradient::new(GradientShape::Radial {
+            x0: 5.0,
+            y0: 5.0,
+            r0: 2.0,
+            x1: 5.0,
+            y1: 5.0,
+            r1: 4.0,
+        });
+        gradient.add_color_stop(0.0, Color::BLACK);
+        gradient.add_color_stop(1.0, Color::WHITE);
+        let (focal, center, radius, stops) = gradient.focal_radial().unwrap();
+        assert_eq!(
+            (focal, center, radius),
+            (center, Point::from_xy(5.0, 5.0), 4.0)
+        );
+        assert_eq!((stops[0].offset, stops[1].offset), (0.5, 1.0));
+
+        // Shrinking circles go the other way
+        gradient.shape = GradientShape::Radial {
+            x0: 0.0,
+            y0: 0.0,
+            r0: 4.0,
+            x1: 2.0,
+            y1: 0.0,
+            r1: 2.0,
+        };
+        let (focal, center, radius, stops) = gradient.focal_radial().unwrap();
+        assert_eq!(
+            (focal, center, radius),
+            (Point::from_xy(4.0, 0.0), Point::from_xy(0.0, 0.0), 4.0)
+        );
+        assert_eq!(
+            stops[0],
+            ColorStop {
+                offset: 0.5,
+                color: Color::WHITE
+            }
+        );
+        assert_eq!(
+            stops[1],
+            ColorStop {
+                offset: 1.0,
+                color: Color::BLACK
+            }
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let paint = PaintStyle::Gradient(gradient());
+        let json = serde_json::to_string(&paint).unwrap();
+        assert!(json.starts_with(r#"{"type":"linear","x0":0.0,"y0":0.0,"x1":10.0,"y1":0.0,"stops":[{"offset":0.0,"color":[0.0,0.0,0.0,1.0]}"#));
+        assert_eq!(serde_json::from_str::<PaintStyle>(&json).unwrap(), paint);
+        let red: StyleValue = serde_json::from_str(r#""red""#).unwrap();
+        assert_eq!(
+            red.to_paint(),
+            Some(PaintStyle::Color(Color::from_rgba8(255, 0, 0, 255)))
+        );
+
+        let paint = PaintStyle::Pattern(pattern(Repetition::RepeatX));
+        let json = serde_json::to_string(&paint).unwrap();
+        assert!(json.starts_with(r#"{"image":"data:image/png;base64,"#));
+        assert!(json.ends_with(r#""width":4.0,"height":2.0,"repetition":"repeat-x","transform":[1.0,0.0,0.0,1.0,0.0,0.0]}"#));
+        assert_eq!(serde_json::from_str::<PaintStyle>(&json).unwrap(), paint);
+    }
+
+    fn pattern(repetition: Repetition) -> Pattern {
+        let mut image = Pixmap::new(2, 1).unwrap();
+        image.fill(Color::BLACK);
+        return Pattern {
+            image,
+            width: 4.0,
+            height: 2.0,
+            repetition,
+            transform: Transform::identity(),
+        };
+    }
+
+    #[test]
+    fn test_coverage() {
+        let bounds = Rect::from_ltrb(-10.0, -10.0, 10.0, 10.0).unwrap();
+        let scale = Transform::from_scale(2.0, 2.0);
+        let covered = |repetition| {
+            let pattern = pattern(repetition);
+            pattern.coverage(bounds, scale).map(|path| path.bounds())
+        };
+        assert_eq!(covered(Repetition::Repeat), None);
+        assert_eq!(
+            covered(Repetition::NoRepeat),
+            Rect::from_ltrb(0.0, 0.0, 8.0, 4.0)
+        );
+        assert_eq!(
+            covered(Repetition::RepeatX),
+            Rect::from_ltrb(-10.0, 0.0, 10.0, 4.0)
+        );
+        assert_eq!(
+            covered(Repetition::RepeatY),
+            Rect::from_ltrb(0.0, -10.0, 8.0, 10.0)
+        );
+        assert_eq!(parse_repetition(""), Some(Repetition::Repeat));
+        assert_eq!(parse_repetition("repeat-y"), Some(Repetition::RepeatY));
+        assert_eq!(parse_repetition("round"), None);
+    }
+}
@@ -0,0 +1,1033 @@
+//
+// Path construction helpers for Canvas path methods, and Path2D
+//
+// tiny-skia only has lines and Bezier curves, so curved Canvas shapes are
+// approximated with cubic Bezier segments here. Path2D objects keep a path
+// for reuse and can be made from SVG path data.
+//
+
+use crate::paint::MatrixInit;
+use rquickjs::class::Trace;
+use rquickjs::function::Opt;
+use rquickjs::{Class, Ctx, FromJs, Value};
+use std::f64::consts::{FRAC_PI_2, PI, TAU};
+use tiny_skia::{FillRule, Path, PathBuilder, PathSegment, Transform};
+
+/// Corner radii given to roundRect(), as (x, y) radius of each corner
+///
+/// From JS this is a number, a DOMPointInit like `{x: 5, y: 3}`, or a list of
+/// 1 to 4 of those.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CornerRadii(pub Vec<(f64, f64)>);
+
+/// One corner radius from a number or DOMPointInit
+fn corner_radius<'js>(ctx: &Ctx<'js>, value: Value<'js>) -> rquickjs::Result<(f64, f64)> {
+    if let Some(point) = value.as_object() {
+        let x: Option<f64> = point.get("x")?;
+        let y: Option<f64> = point.get("y")?;
+        return Ok((x.unwrap_or(0.0), y.unwrap_or(0.0)));
+    }
+    let radius = f64::from_js(ctx, value)?;
+    return Ok((radius, radius));
+}
+
+impl<'js> FromJs<'js> for CornerRadii {
+    fn from_js(ctx: &Ctx<'js>, value: Value<'js>) -> rquickjs::Result<Self> {
+        if let Some(list) = value.as_array() {
+            let radii = list
+                .iter::<Value>()
+                .map(|v| corner_radius(ctx, v?))
+                .collect::<rquickjs::Result<_>>()?;
+            return Ok(CornerRadii(radii));
+        }
+        return Ok(CornerRadii(vec![corner_radius(ctx, value)?]));
+    }
+}
+
+/// Angle swept going from start_angle to end_angle, negative when counterclockwise.
+///
+/// Follows the Canvas spec: a difference of at least a full turn in the
+/// direction of drawing gives a full circle, otherwise angles wrap around.
+fn arc_sweep(start_angle: f64, end_angle: f64, counterclockwise: bool) -> f64 {
+    if !counterclockwise {
+        if end_angle - start_angle >= TAU {
+            return TAU;
+        }
+        return (end_angle - start_angle).rem_euclid(TAU);
+    }
+    if start_angle - end_angle >= TAU {
+        return -TAU;
+    }
+    return -(start_angle - end_angle).rem_euclid(TAU);
+}
+
+/// Add arc of ellipse to path.
+///
+/// The ellipse has center (x, y) and radii rx and ry, rotated clockwise by
+/// rotation. Draws a line from the current point to the start of the arc if
+/// the path is not empty, otherwise starts a new subpath there.
+#[allow(clippy::too_many_arguments)]
+fn push_elliptic_arc(
+    builder: &mut PathBuilder,
+    x: f64,
+    y: f64,
+    rx: f64,
+    ry: f64,
+    rotation: f64,
+    start_angle: f64,
+    end_angle: f64,
+    counterclockwise: bool,
+) {
+    let (sin_r, cos_r) = rotation.sin_cos();
+    // Map point (u, v) relative to unrotated ellipse center into user space.
+    let map = |u: f64, v: f64| (x + u * cos_r - v * sin_r, y + u * sin_r + v * cos_r);
+    let point = |angle: f64| map(rx * angle.cos(), ry * angle.sin());
+    let (start_x, start_y) = point(start_angle);
+    if builder.is_empty() {
+        builder.move_to(start_x as f32, start_y as f32);
+    } else {
+        builder.line_to(start_x as f32, start_y as f32);
+    }
+    let sweep = arc_sweep(start_angle, end_angle, counterclockwise);
+    // Split into segments of at most a quarter turn, each one cubic.
+    let segments = (sweep.abs() / FRAC_PI_2).ceil().max(1.0) as usize;
+    let step = sweep / segments as f64;
+    // Control point distance along tangent for unit circle arc of angle step.
+    let k = 4.0 / 3.0 * (step / 4.0).tan();
+    let mut angle = start_angle;
+    for _ in 0..segments {
+        let next = angle + step;
+        let (cos0, sin0) = (angle.cos(), angle.sin());
+        let (cos1, sin1) = (next.cos(), next.sin());
+        let (x1, y1) = map(rx * (cos0 - k * sin0), ry * (sin0 + k * cos0));
+        let (x2, y2) = map(rx * (cos1 + k * sin1), ry * (sin1 - k * cos1));
+        let (x3, y3) = point(next);
+        builder.cubic_to(
+            x1 as f32, y1 as f32, x2 as f32, y2 as f32, x3 as f32, y3 as f32,
+        );
+        angle = next;
+    }
+}
+
+/// Add arc of ellipse to path, like Canvas ellipse().
+///
+/// Returns error for negative radii, leaving path unchanged.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn push_ellipse(
+    builder: &mut PathBuilder,
+    x: f64,
+    y: f64,
+    rx: f64,
+    ry: f64,
+    rotation: f64,
+    start_angle: f64,
+    end_angle: f64,
+    counterclockwise: bool,
+) -> Result<(), String> {
+    if rx < 0.0 || ry < 0.0 {
+        return Err(format!("Negative ellipse radius {}, {}", rx, ry));
+    }
+    push_elliptic_arc(
+        builder,
+        x,
+        y,
+        rx,
+        ry,
+        rotation,
+        start_angle,
+        end_angle,
+        counterclockwise,
+    );
+    return Ok(());
+}
+
+/// Add circular arc to path, like Canvas arc().
+///
+/// Returns error for negative radius, leaving path unchanged.
+pub(crate) fn push_arc(
+    builder: &mut PathBuilder,
+    x: f64,
+    y: f64,
+    radius: f64,
+    start_angle: f64,
+    end_angle: f64,
+    counterclockwise: bool,
+) -> Result<(), String> {
+    if radius < 0.0 {
+        return Err(format!("Negative arc radius {}", radius));
+    }
+    push_elliptic_arc(
+        builder,
+        x,
+        y,
+        radius,
+        radius,
+        0.0,
+        start_angle,
+        end_angle,
+        counterclockwise,
+    );
+    return Ok(());
+}
+
+/// Add arc tangent to the lines from the current point to (x1, y1) and from
+/// there to (x2, y2), like Canvas arcTo().
+///
+/// Returns error for negative radius, leaving path unchanged.
+pub(crate) fn push_arc_to(
+    builder: &mut PathBuilder,
+    x1: f64,
+    y1: f64,
+    x2: f64,
+    y2: f64,
+    radius: f64,
+) -> Result<(), String> {
+    if radius < 0.0 {
+        return Err(format!("Negative arcTo radius {}", radius));
+    }
+    let Some(p0) = builder.last_point() else {
+        builder.move_to(x1 as f32, y1 as f32);
+        return Ok(());
+    };
+    let (x0, y0) = (p0.x as f64, p0.y as f64);
+    // Unit vectors from (x1, y1) back to current point and on to (x2, y2).
+    let (ax, ay) = (x0 - x1, y0 - y1);
+    let (bx, by) = (x2 - x1, y2 - y1);
+    let a_len = ax.hypot(ay);
+    let b_len = bx.hypot(by);
+    let cross = ax * by - ay * bx;
+    if radius == 0.0 || a_len == 0.0 || b_len == 0.0 || (cross / (a_len * b_len)).abs() < 1e-10 {
+        builder.line_to(x1 as f32, y1 as f32);
+        return Ok(());
+    }
+    let (ax, ay, bx, by) = (ax / a_len, ay / a_len, bx / b_len, by / b_len);
+    // Half of angle between the two lines at (x1, y1).
+    let half = ((ax * bx + ay * by).clamp(-1.0, 1.0).acos()) / 2.0;
+    let tangent = radius / half.tan();
+    let (t1x, t1y) = (x1 + ax * tangent, y1 + ay * tangent);
+    let (t2x, t2y) = (x1 + bx * tangent, y1 + by * tangent);
+    // Center is along bisector, at distance from corner to make circle touch both lines.
+    let (mx, my) = (ax + bx, ay + by);
+    let m_len = mx.hypot(my);
+    let center = radius / half.sin();
+    let (cx, cy) = (x1 + mx / m_len * center, y1 + my / m_len * center);
+    let start_angle = (t1y - cy).atan2(t1x - cx);
+    let end_angle = (t2y - cy).atan2(t2x - cx);
+    // Turning right on screen (y down) means arc goes clockwise.
+    push_elliptic_arc(
+        builder,
+        cx,
+        cy,
+        radius,
+        radius,
+        0.0,
+        start_angle,
+        end_angle,
+        cross > 0.0,
+    );
+    return Ok(());
+}
+
+/// Add rectangle to path as a closed subpath, like Canvas rect().
+///
+/// Width and height may be negative, then the sides are drawn the other way.
+/// Lines added next start from (x, y), as after any closed subpath.
+pub(crate) fn push_rect(builder: &mut PathBuilder, x: f64, y: f64, width: f64, height: f64) {
+    let (x0, y0, x1, y1) = (x as f32, y as f32, (x + width) as f32, (y + height) as f32);
+    builder.move_to(x0, y0);
+    builder.line_to(x1, y0);
+    builder.line_to(x1, y1);
+    builder.line_to(x0, y1);
+    builder.close();
+}
+
+/// Add rounded rectangle to path, like Canvas roundRect().
+///
+/// Returns error for invalid radii, leaving path unchanged.
+pub(crate) fn push_round_rect(
+    builder: &mut PathBuilder,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    radii: &CornerRadii,
+) -> Result<(), String> {
+    let radii = &radii.0;
+    if radii.is_empty() || radii.len() > 4 {
+        return Err(format!("roundRect needs 1 to 4 radii, got {}", radii.len()));
+    }
+    if radii.iter().any(|&(rx, ry)| rx < 0.0 || ry < 0.0) {
+        return Err("roundRect radii cannot be negative".to_string());
+    }
+    // Corners in order upper left, upper right, lower right, lower left.
+    let mut corners = match radii.len() {
+        1 => [radii[0]; 4],
+        2 => [radii[0], radii[1], radii[0], radii[1]],
+        3 => [radii[0], radii[1], radii[2], radii[1]],
+        _ => [radii[0], radii[1], radii[2], radii[3]],
+    };
+    let (mut x, mut y, mut width, mut height) = (x, y, width, height);
+    if width < 0.0 {
+        x += width;
+        width = -width;
+        corners = [corners[1], corners[0], corners[3], corners[2]];
+    }
+    if height < 0.0 {
+        y += height;
+        height = -height;
+        corners = [corners[3], corners[2], corners[1], corners[0]];
+    }
+    // Scale radii down if corners on any side would overlap.
+    let [ul, ur, lr, ll] = corners;
+    let scale = [
+        width / (ul.0 + ur.0),
+        height / (ur.1 + lr.1),
+        width / (lr.0 + ll.0),
+        height / (ul.1 + ll.1),
+    ]
+    .into_iter()
+    .fold(1.0, f64::min);
+    let [ul, ur, lr, ll] = corners.map(|(rx, ry)| (rx * scale, ry * scale));
+    let corner = |builder: &mut PathBuilder, (rx, ry): (f64, f64), cx: f64, cy: f64, start: f64| {
+        if rx > 0.0 && ry > 0.0 {
+            push_elliptic_arc(
+                builder,
+                cx,
+                cy,
+                rx,
+                ry,
+                0.0,
+                start,
+                start + FRAC_PI_2,
+                false,
+            );
+        }
+    };
+    builder.move_to((x + ul.0) as f32, y as f32);
+    builder.line_to((x + width - ur.0) as f32, y as f32);
+    corner(builder, ur, x + width - ur.0, y + ur.1, -FRAC_PI_2);
+    builder.line_to((x + width) as f32, (y + height - lr.1) as f32);
+    corner(builder, lr, x + width - lr.0, y + height - lr.1, 0.0);
+    builder.line_to((x + ll.0) as f32, (y + height) as f32);
+    corner(builder, ll, x + ll.0, y + height - ll.1, FRAC_PI_2);
+    builder.line_to(x as f32, (y + ul.1) as f32);
+    corner(builder, ul, x + ul.0, y + ul.1, PI);
+    builder.close();
+    builder.move_to(x as f32, y as f32);
+    return Ok(());
+}
+
+/// Add arc of SVG path data from current point (x1, y1) to (x2, y2).
+///
+/// SVG gives the arc by its endpoints, radii, rotation in degrees and two
+/// flags choosing one of four possible arcs. This converts to center and
+/// angles as in the SVG implementation notes, scaling radii up if they are
+/// too small to reach.
+#[allow(clippy::too_many_arguments)]
+fn push_svg_arc(
+    builder: &mut PathBuilder,
+    (x1, y1): (f64, f64),
+    rx: f64,
+    ry: f64,
+    rotation: f64,
+    large_arc: bool,
+    sweep: bool,
+    (x2, y2): (f64, f64),
+) {
+    if x1 == x2 && y1 == y2 {
+        return;
+    }
+    let (mut rx, mut ry) = (rx.abs(), ry.abs());
+    if rx == 0.0 || ry == 0.0 {
+        builder.line_to(x2 as f32, y2 as f32);
+        return;
+    }
+    let rotation = rotation.to_radians();
+    let (sin_r, cos_r) = rotation.sin_cos();
+    // Start point relative to midpoint of chord, in unrotated ellipse space.
+    let (dx, dy) = ((x1 - x2) / 2.0, (y1 - y2) / 2.0);
+    let (x1p, y1p) = (cos_r * dx + sin_r * dy, -sin_r * dx + cos_r * dy);
+    let scale = (x1p / rx).powi(2) + (y1p / ry).powi(2);
+    if scale > 1.0 {
+        rx *= scale.sqrt();
+        ry *= scale.sqrt();
+    }
+    let numerator = (rx * ry).powi(2) - (rx * y1p).powi(2) - (ry * x1p).powi(2);
+    let denominator = (rx * y1p).powi(2) + (ry * x1p).powi(2);
+    let mut coefficient = (numerator / denominator).max(0.0).sqrt();
+    if large_arc == sweep {
+        coefficient = -coefficient;
+    }
+    let (cxp, cyp) = (coefficient * rx * y1p / ry, -coefficient * ry * x1p / rx);
+    let cx = cos_r * cxp - sin_r * cyp + (x1 + x2) / 2.0;
+    let cy = sin_r * cxp + cos_r * cyp + (y1 + y2) / 2.0;
+    let start_angle = ((y1p - cyp) / ry).atan2((x1p - cxp) / rx);
+    let end_angle = ((-y1p - cyp) / ry).atan2((-x1p - cxp) / rx);
+    let mut delta = end_angle - start_angle;
+    if sweep && delta < 0.0 {
+        delta += TAU;
+    } else if !sweep && delta > 0.0 {
+        delta -= TAU;
+    }
+    // Positive sweep in SVG is clockwise on screen, like Canvas arcs.
+    push_elliptic_arc(
+        builder,
+        cx,
+        cy,
+        rx,
+        ry,
+        rotation,
+        start_angle,
+        start_angle + delta,
+        !sweep,
+    );
+}
+
+/// Reads numbers and flags of SVG path data
+struct PathDataReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl PathDataReader<'_> {
+    fn skip_separators(&mut self) {
+        while self.pos < self.data.len()
+            && (self.data[self.pos].is_ascii_whitespace() || self.data[self.pos] == b',')
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        return self.data.get(self.pos).copied();
+    }
+
+    /// Whether a number follows, meaning the last command repeats
+    fn at_number(&mut self) -> bool {
+        self.skip_separators();
+        return matches!(self.peek(), Some(b'0'..=b'9' | b'.' | b'-' | b'+'));
+    }
+
+    /// Command letter, if one follows
+    fn command(&mut self) -> Option<u8> {
+        self.skip_separators();
+        let letter = self.peek().filter(u8::is_ascii_alphabetic)?;
+        self.pos += 1;
+        return Some(letter);
+    }
+
+    fn number(&mut self) -> Option<f64> {
+        self.skip_separators();
+        let start = self.pos;
+        let digits = |reader: &mut Self| {
+            let from = reader.pos;
+            while reader.peek().is_some_and(|c| c.is_ascii_digit()) {
+                reader.pos += 1;
+            }
+            return reader.pos > from;
+        };
+        if matches!(self.peek(), Some(b'-' | b'+')) {
+            self.pos += 1;
+        }
+        let mut mantissa = digits(self);
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            mantissa |= digits(self);
+        }
+        if !mantissa {
+            self.pos = start;
+            return None;
+        }
+        // Exponent only if digits follow, so "2e" stays a number then a letter.
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            let before = self.pos;
+            self.pos += 1;
+            if matches!(self.peek(), Some(b'-' | b'+')) {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                self.pos = before;
+            }
+        }
+        let text = std::str::from_utf8(&self.data[start..self.pos]).ok()?;
+        return text.parse().ok();
+    }
+
+    /// Arc flag, a single 0 or 1 that need not be followed by a separator
+    fn flag(&mut self) -> Option<bool> {
+        self.skip_separators();
+        let flag = match self.peek()? {
+            b'0' => false,
+            b'1' => true,
+            _ => return None,
+        };
+        self.pos += 1;
+        return Some(flag);
+    }
+
+    fn point(&mut self) -> Option<(f64, f64)> {
+        return Some((self.number()?, self.number()?));
+    }
+}
+
+/// Path from SVG path data like "M0 0 L10 10 Z", as used by `new Path2D(d)`.
+///
+/// All SVG commands are supported, absolute and relative. Like in browsers,
+/// the path is made up to the first error and the rest is ignored.
+pub fn parse_path_data(data: &str) -> PathBuilder {
+    let mut builder = PathBuilder::new();
+    let mut reader = PathDataReader {
+        data: data.as_bytes(),
+        pos: 0,
+    };
+    let mut current = (0.0, 0.0);
+    let mut start = (0.0, 0.0);
+    // Last control point of a cubic or quadratic curve, for smooth curves.
+    let mut last_cubic: Option<(f64, f64)> = None;
+    let mut last_quad: Option<(f64, f64)> = None;
+    let mut previous: Option<u8> = None;
+    loop {
+        let command = match reader.command() {
+            Some(command) => command,
+            None => match previous {
+                Some(b'M') if reader.at_number() => b'L',
+                Some(b'm') if reader.at_number() => b'l',
+                Some(command) if reader.at_number() && !command.eq_ignore_ascii_case(&b'z') => {
+                    command
+                }
+                _ => break,
+            },
+        };
+        if previous.is_none() && !command.eq_ignore_ascii_case(&b'm') {
+            break;
+        }
+        let relative = command.is_ascii_lowercase();
+        let offset = |(x, y): (f64, f64)| {
+            if relative {
+                return (current.0 + x, current.1 + y);
+            }
+            return (x, y);
+        };
+        let reflect = |control: Option<(f64, f64)>| match control {
+            Some((x, y)) => (2.0 * current.0 - x, 2.0 * current.1 - y),
+            None => current,
+        };
+        let (mut cubic, mut quad) = (None, None);
+        match command.to_ascii_uppercase() {
+            b'M' => {
+                let Some(point) = reader.point() else { break };
+                current = offset(point);
+                start = current;
+                builder.move_to(current.0 as f32, current.1 as f32);
+            }
+            b'L' => {
+                let Some(point) = reader.point() else { break };
+                current = offset(point);
+                builder.line_to(current.0 as f32, current.1 as f32);
+            }
+            b'H' => {
+                let Some(x) = reader.number() else { break };
+                current.0 = if relative { current.0 + x } else { x };
+                builder.line_to(current.0 as f32, current.1 as f32);
+            }
+            b'V' => {
+                let Some(y) = reader.number() else { break };
+                current.1 = if relative { current.1 + y } else { y };
+                builder.line_to(current.0 as f32, current.1 as f32);
+            }
+            b'C' | b'S' => {
+                let first = if command.eq_ignore_ascii_case(&b'C') {
+                    let Some(point) = reader.point() else { break };
+                    offset(point)
+                } else {
+                    reflect(last_cubic)
+                };
+                let (Some(second), Some(end)) = (reader.point(), reader.point()) else {
+                    break;
+                };
+                let (second, end) = (offset(second), offset(end));
+                builder.cubic_to(
+                    first.0 as f32,
+                    first.1 as f32,
+                    second.0 as f32,
+                    second.1 as f32,
+                    end.0 as f32,
+                    end.1 as f32,
+                );
+                cubic = Some(second);
+                current = end;
+            }
+            b'Q' | b'T' => {
+                let control = if command.eq_ignore_ascii_case(&b'Q') {
+                    let Some(point) = reader.point() else { break };
+                    offset(point)
+                } else {
+                    reflect(last_quad)
+                };
+                let Some(end) = reader.point() else { break };
+                let end = offset(end);
+                builder.quad_to(
+                    control.0 as f32,
+                    control.1 as f32,
+                    end.0 as f32,
+                    end.1 as f32,
+                );
+                quad = Some(control);
+                current = end;
+            }
+            b'A' => {
+                let (Some(rx), Some(ry), Some(rotation)) =
+                    (reader.number(), reader.number(), reader.number())
+                else {
+                    break;
+                };
+                let (Some(large_arc), Some(sweep), Some(end)) =
+                    (reader.flag(), reader.flag(), reader.point())
+                else {
+                    break;
+                };
+                let end = offset(end);
+                push_svg_arc(
+                    &mut builder,
+                    current,
+                    rx,
+                    ry,
+                    rotation,
+                    large_arc,
+                    sweep,
+                    end,
+                );
+                current = end;
+            }
+            b'Z' => {
+                builder.close();
+                current = start;
+            }
+            _ => break,
+        }
+        last_cubic = cubic;
+        last_quad = quad;
+        previous = Some(command);
+    }
+    return builder;
+}
+
+/// Whether point (x, y) is inside path filled with fill rule.
+///
+/// Curves are flattened into lines and open subpaths are closed, like when
+/// filling. Points on an edge may count either way.
+pub(crate) fn path_contains(path: &Path, fill_rule: FillRule, x: f32, y: f32) -> bool {
+    const CURVE_STEPS: usize = 16;
+    let mut winding = 0;
+    let mut edge = |(x0, y0): (f32, f32), (x1, y1): (f32, f32)| {
+        // Which side of the edge the point is on, positive for left
+        let side = (x1 - x0) * (y - y0) - (x - x0) * (y1 - y0);
+        if y0 <= y && y1 > y && side > 0.0 {
+            winding += 1;
+        } else if y1 <= y && y0 > y && side < 0.0 {
+            winding -= 1;
+        }
+    };
+    let mut start = (0.0, 0.0);
+    let mut current = (0.0, 0.0);
+    for segment in path.segments() {
+        match segment {
+            PathSegment::MoveTo(p) => {
+                edge(current, start);
+                start = (p.x, p.y);
+                current = start;
+            }
+            PathSegment::LineTo(p) => {
+                edge(current, (p.x, p.y));
+                current = (p.x, p.y);
+            }
+            PathSegment::QuadTo(p1, p) => {
+                let (x0, y0) = current;
+                for step in 1..=CURVE_STEPS {
+                    let t = step as f32 / CURVE_STEPS as f32;
+                    let (a, b, c) = ((1.0 - t) * (1.0 - t), 2.0 * (1.0 - t) * t, t * t);
+                    let next = (a * x0 + b * p1.x + c * p.x, a * y0 + b * p1.y + c * p.y);
+                    edge(current, next);
+                    current = next;
+                }
+            }
+            PathSegment::CubicTo(p1, p2, p) => {
+                let (x0, y0) = current;
+                for step in 1..=CURVE_STEPS {
+                    let t = step as f32 / CURVE_STEPS as f32;
+                    let u = 1.0 - t;
+                    let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+                    let next = (
+                        a * x0 + b * p1.x + c * p2.x + d * p.x,
+                        a * y0 + b * p1.y + c * p2.y + d * p.y,
+                    );
+                    edge(current, next);
+                    current = next;
+                }
+            }
+            PathSegment::Close => {
+                edge(current, start);
+                current = start;
+            }
+        }
+    }
+    edge(current, start);
+    return match fill_rule {
+        FillRule::Winding => winding != 0,
+        FillRule::EvenOdd => winding % 2 != 0,
+    };
+}
+
+/// Copy of the path of a Path2D argument
+pub(crate) struct PathArg(pub PathBuilder);
+
+impl<'js> FromJs<'js> for PathArg {
+    fn from_js(ctx: &Ctx<'js>, value: Value<'js>) -> rquickjs::Result<Self> {
+        let path = Class::<Path2D>::from_js(ctx, value)?;
+        let builder = path.try_borrow()?.builder.clone();
+        return Ok(PathArg(builder));
+    }
+}
+
+/// Argument that is either a Path2D, copied, or some other value, for Canvas
+/// methods taking an optional path first
+pub(crate) enum PathOr<T> {
+    Path(PathBuilder),
+    Other(T),
+}
+
+impl<'js, T: FromJs<'js>> FromJs<'js> for PathOr<T> {
+    fn from_js(ctx: &Ctx<'js>, value: Value<'js>) -> rquickjs::Result<Self> {
+        if let Ok(path) = PathArg::from_js(ctx, value.clone()) {
+            return Ok(PathOr::Path(path.0));
+        }
+        return Ok(PathOr::Other(T::from_js(ctx, value)?));
+    }
+}
+
+/// Path that can be built once and then filled, stroked and clipped to any
+/// number of times, like Canvas Path2D
+#[derive(Trace)]
+#[rquickjs::class]
+pub(crate) struct Path2D {
+    #[qjs(skip_trace)]
+    pub(crate) builder: PathBuilder,
+}
+
+#[rquickjs::methods(rename_all = "camelCase")]
+impl Path2D {
+    /// Either `new Path2D()`, empty, `new Path2D(path)` copying another
+    /// Path2D, or `new Path2D(d)` from SVG path data.
+    #[qjs(constructor)]
+    pub fn new(source: Opt<PathOr<String>>) -> Self {
+        let builder = match source.0 {
+            None => PathBuilder::new(),
+            Some(PathOr::Path(builder)) => builder,
+            Some(PathOr::Other(data)) => parse_path_data(&data),
+        };
+        return Path2D { builder };
+    }
+
+    /// Add subpaths of path, transformed by matrix if given
+    pub fn add_path(&mut self, path: PathArg, transform: Opt<MatrixInit>) {
+        let transform = transform.0.map_or(Transform::identity(), |m| m.0);
+        if let Some(path) = path.0.finish().and_then(|p| p.transform(transform)) {
+            self.builder.push_path(&path);
+        }
+    }
+
+    pub fn move_to(&mut self, x: f64, y: f64) {
+        self.builder.move_to(x as f32, y as f32);
+    }
+
+    pub fn line_to(&mut self, x: f64, y: f64) {
+        self.builder.line_to(x as f32, y as f32);
+    }
+
+    pub fn close_path(&mut self) {
+        self.builder.close();
+    }
+
+    pub fn quadratic_curve_to(&mut self, x1: f64, y1: f64, x: f64, y: f64) {
+        self.builder
+            .quad_to(x1 as f32, y1 as f32, x as f32, y as f32);
+    }
+
+    pub fn bezier_curve_to(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, x: f64, y: f64) {
+        self.builder.cubic_to(
+            x1 as f32, y1 as f32, x2 as f32, y2 as f32, x as f32, y as f32,
+        );
+    }
+
+    /// Add arc of circle, joined to current point by a line
+    pub fn arc(
+        &mut self,
+        x: f64,
+        y: f64,
+        radius: f64,
+        start_angle: f64,
+        end_angle: f64,
+        counterclockwise: Opt<bool>,
+    ) {
+        let counterclockwise = counterclockwise.0.unwrap_or(false);
+        if let Err(e) = push_arc(
+            &mut self.builder,
+            x,
+            y,
+            radius,
+            start_angle,
+            end_angle,
+            counterclockwise,
+        ) {
+            println!("{}, ignoring", e);
+        }
+    }
+
+    /// Add arc of ellipse, joined to current point by a line
+    #[allow(clippy::too_many_arguments)]
+    pub fn ellipse(
+        &mut self,
+        x: f64,
+        y: f64,
+        radius_x: f64,
+        radius_y: f64,
+        rotation: f64,
+        start_angle: f64,
+        end_angle: f64,
+        counterclockwise: Opt<bool>,
+    ) {
+        let counterclockwise = counterclockwise.0.unwrap_or(false);
+        if let Err(e) = push_ellipse(
+            &mut self.builder,
+            x,
+            y,
+            radius_x,
+            radius_y,
+            rotation,
+            start_angle,
+            end_angle,
+            counterclockwise,
+        ) {
+            println!("{}, ignoring", e);
+        }
+    }
+
+    /// Add arc with given radius joining line to (x1, y1) and line from there to (x2, y2)
+    pub fn arc_to(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, radius: f64) {
+        if let Err(e) = push_arc_to(&mut self.builder, x1, y1, x2, y2, radius) {
+            println!("{}, ignoring", e);
+        }
+    }
+
+    /// Add rectangle as a closed subpath, sides may be negative
+    pub fn rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        push_rect(&mut self.builder, x, y, width, height);
+    }
+
+    /// Add rectangle with rounded corners
+    pub fn round_rect(&mut self, x: f64, y: f64, width: f64, height: f64, radii: Opt<CornerRadii>) {
+        let radii = radii.0.unwrap_or(CornerRadii(vec![(0.0, 0.0)]));
+        if let Err(e) = push_round_rect(&mut self.builder, x, y, width, height, &radii) {
+            println!("{}, ignoring", e);
+        }
+    }
+
+    /// Describe path the way browsers do
+    #[qjs(rename = "toString")]
+    pub fn to_js_string(&self) -> String {
+        return "[object Path2D]".to_string();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use tiny_skia::Point;
+
+    fn close(a: Point, x: f32, y: f32) -> bool {
+        return (a.x - x).abs() < 1e-4 && (a.y - y).abs() < 1e-4;
+    }
+
+    #[test]
+    fn test_arc_sweep() {
+        assert_eq!(arc_sweep(0.0, FRAC_PI_2, false), FRAC_PI_2);
+        assert_eq!(arc_sweep(0.0, FRAC_PI_2, true), -3.0 * FRAC_PI_2);
+        assert_eq!(arc_sweep(0.0, TAU, false), TAU);
+        assert_eq!(arc_sweep(0.0, 3.0 * TAU, false), TAU);
+        assert_eq!(arc_sweep(0.0, -TAU, true), -TAU);
+        assert_eq!(arc_sweep(0.0, TAU, true), 0.0);
+        assert!((arc_sweep(0.0, 2.5 * PI, true) - -1.5 * PI).abs() < 1e-12);
+        assert!((arc_sweep(3.0 * TAU, 0.5 * PI, false) - 0.5 * PI).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_push_arc() {
+        let mut builder = PathBuilder::new();
+        push_arc(&mut builder, 10.0, 10.0, 5.0, 0.0, PI, false).unwrap();
+        let path = builder.finish().unwrap();
+        let points = path.points();
+        assert!(close(points[0], 15.0, 10.0));
+        // Clockwise in canvas coordinates goes through bottom point.
+        assert!(close(points[3], 10.0, 15.0));
+        assert!(close(*points.last().unwrap(), 5.0, 10.0));
+
+        let mut builder = PathBuilder::new();
+        builder.move_to(0.0, 0.0);
+        push_arc(&mut builder, 10.0, 10.0, 5.0, 0.0, PI, true).unwrap();
+        let path = builder.finish().unwrap();
+        let points = path.points();
+        // Line from current point to start of arc, then through top point.
+        assert!(close(points[1], 15.0, 10.0));
+        assert!(close(points[4], 10.0, 5.0));
+        assert!(close(*points.last().unwrap(), 5.0, 10.0));
+
+        let mut builder = PathBuilder::new();
+        assert!(push_arc(&mut builder, 0.0, 0.0, -1.0, 0.0, PI, false).is_err());
+        assert!(push_ellipse(&mut builder, 0.0, 0.0, 1.0, -1.0, 0.0, 0.0, PI, false).is_err());
+        assert!(builder.is_empty());
+    }
+
+    #[test]
+    fn test_push_arc_to() {
+        let mut builder = PathBuilder::new();
+        builder.move_to(0.0, 0.0);
+        push_arc_to(&mut builder, 10.0, 0.0, 10.0, 10.0, 4.0).unwrap();
+        let path = builder.finish().unwrap();
+        let points = path.points();
+        // Line to start of quarter circle turning right, which ends on second line.
+        assert!(close(points[1], 6.0, 0.0));
+        assert!(close(*points.last().unwrap(), 10.0, 4.0));
+
+        // Collinear points just get a line.
+        let mut builder = PathBuilder::new();
+        builder.move_to(0.0, 0.0);
+        push_arc_to(&mut builder, 10.0, 0.0, 20.0, 0.0, 4.0).unwrap();
+        assert_eq!(builder.len(), 2);
+
+        // Negative radius leaves path unchanged.
+        assert!(push_arc_to(&mut builder, 10.0, 0.0, 20.0, 5.0, -1.0).is_err());
+        assert_eq!(builder.len(), 2);
+    }
+
+    #[test]
+    fn test_push_rect() {
+        let mut builder = PathBuilder::new();
+        push_rect(&mut builder, 10.0, 10.0, -5.0, 5.0);
+        builder.line_to(20.0, 10.0);
+        let path = builder.finish().unwrap();
+        let points = path.points();
+        assert!(close(points[1], 5.0, 10.0));
+        assert!(close(points[2], 5.0, 15.0));
+        // Line after close starts a new subpath from the first corner.
+        assert!(close(points[4], 10.0, 10.0));
+        assert!(close(points[5], 20.0, 10.0));
+        let bounds = path.bounds();
+        assert_eq!((bounds.left(), bounds.bottom()), (5.0, 15.0));
+    }
+
+    #[test]
+    fn test_push_round_rect() {
+        let mut builder = PathBuilder::new();
+        let radii = CornerRadii(vec![(2.0, 2.0), (0.0, 0.0)]);
+        push_round_rect(&mut builder, 0.0, 0.0, 10.0, 10.0, &radii).unwrap();
+        let path = builder.finish().unwrap();
+        let bounds = path.bounds();
+        assert_eq!(
+            (bounds.left(), bounds.top(), bounds.right(), bounds.bottom()),
+            (0.0, 0.0, 10.0, 10.0)
+        );
+        assert!(close(path.points()[0], 2.0, 0.0));
+        // Square upper right corner, rounded lower right.
+        assert!(close(path.points()[1], 10.0, 0.0));
+        assert!(close(path.points()[2], 10.0, 8.0));
+
+        // Overlapping radii are scaled down to fit.
+        let mut builder = PathBuilder::new();
+        push_round_rect(
+            &mut builder,
+            0.0,
+            0.0,
+            10.0,
+            4.0,
+            &CornerRadii(vec![(4.0, 4.0)]),
+        )
+        .unwrap();
+        assert!(close(
+            builder.clone().finish().unwrap().points()[0],
+            2.0,
+            0.0
+        ));
+
+        let mut builder = PathBuilder::new();
+        assert!(push_round_rect(&mut builder, 0.0, 0.0, 1.0, 1.0, &CornerRadii(vec![])).is_err());
+        assert!(push_round_rect(
+            &mut builder,
+            0.0,
+            0.0,
+            1.0,
+            1.0,
+            &CornerRadii(vec![(-1.0, 1.0)])
+        )
+        .is_err());
+        assert!(builder.is_empty());
+    }
+
+    #[test]
+    fn test_parse_path_data() {
+        let path = parse_path_data("M1 2 L3 4 h2 v-1 Z m1,1 l2 2 3 3")
+            .finish()
+            .unwrap();
+        let segments: Vec<PathSegment> = path.segments().collect();
+        assert_eq!(segments.len(), 8);
+        assert!(close(path.points()[2], 5.0, 4.0));
+        assert!(close(path.points()[3], 5.0, 3.0));
+        // Relative move after close starts from start of closed subpath.
+        assert!(close(path.points()[4], 2.0, 3.0));
+        // Implicit lineto repeats after moveto and lineto.
+        assert!(close(*path.points().last().unwrap(), 7.0, 8.0));
+
+        // Compact numbers, exponents, and smooth curves reflect control points.
+        let path = parse_path_data("M0,0C1-1 2-1 3,0S5 1 6 0Q7-1 8 0t2e0 0")
+            .finish()
+            .unwrap();
+        let points = path.points();
+        assert!(close(points[4], 4.0, 1.0));
+        assert!(close(points[9], 9.0, 1.0));
+        assert!(close(points[10], 10.0, 0.0));
+
+        // Small arc bulging down, then half circle over the top, with compact flags.
+        let path = parse_path_data("M0 10a10 10 0 0010 0 .5.5 0 1 1 1e1 0")
+            .finish()
+            .unwrap();
+        assert!(path.points()[2].y > 10.0);
+        assert!((path.bounds().top() - 5.0).abs() < 1e-3);
+        assert!(close(*path.points().last().unwrap(), 20.0, 10.0));
+        // Radii too small are scaled up to reach the end point.
+        let path = parse_path_data("M0 0 A1 1 0 0 1 10 0").finish().unwrap();
+        assert!((path.bounds().top() - -5.0).abs() < 1e-3);
+
+        // Path is kept up to first error, and must start with moveto.
+        assert_eq!(parse_path_data("M0 0 L10 10 L20 x L30 30").len(), 2);
+        assert_eq!(parse_path_data("M0 0 L10").len(), 1);
+        assert!(parse_path_data("L10 10").is_empty());
+        assert!(parse_path_data("").is_empty());
+    }
+
+    #[test]
+    fn test_path_contains() {
+        let mut builder = PathBuilder::new();
+        builder.push_rect(tiny_skia::Rect::from_xywh(0.0, 0.0, 10.0, 10.0).unwrap());
+        builder.push_rect(tiny_skia::Rect::from_xywh(2.0, 2.0, 4.0, 4.0).unwrap());
+        let path = builder.finish().unwrap();
+        assert!(path_contains(&path, FillRule::Winding, 1.0, 1.0));
+        assert!(path_contains(&path, FillRule::Winding, 3.0, 3.0));
+        assert!(!path_contains(&path, FillRule::EvenOdd, 3.0, 3.0));
+        assert!(!path_contains(&path, FillRule::Winding, 11.0, 3.0));
+
+        // Open subpaths are closed, curves are followed.
+        let path = parse_path_data("M0 0 Q10 20 20 0").finish().unwrap();
+        assert!(path_contains(&path, FillRule::Winding, 10.0, 8.0));
+        assert!(!path_contains(&path, FillRule::Winding, 10.0, 11.0));
+    }
+}
@@ -0,0 +1,1169 @@
+//
+// PDF output for drawing operations
+//
+// The document is a single page the size of the surface, one PDF point per
+// pixel of the PNG output (zoom included). Shapes are written as vector paths
+// with the drawing transform attached. Text is written with the built-in
+// fonts embedded as subsets, so it stays selectable and searchable.
+//
+// Linear and radial gradients become shadings painted inside a clip of the
+// shape. PDF shadings cannot vary opacity, so the most opaque stop is used
+// for the whole gradient. Conic gradients are drawn as many thin wedges.
+// Image patterns become tiling patterns, with the image stored uncompressed.
+// Clip paths are set inside a saved graphics state that stays in effect for
+// as long as consecutive operations share them. Global alpha is multiplied
+// into the opacity of each operation, and blend modes that mix colors are set
+// with graphics state parameters. PDF has no blur, so shadows are drawn sharp,
+// in the shadow color under the operation. Filters that change colors are
+// applied to the colors of the paint, the blur filter is left out.
+//
+
+use crate::display_list::{
+    text_bounds, Backend, ClipPath, Compositing, DrawCommand, Shadow, StrokeStyle, TextGlyph,
+};
+use crate::filter::Filter;
+use crate::font::FontLibrary;
+use crate::paint::{ColorStop, Gradient, GradientShape, PaintStyle};
+use ab_glyph::{Font, GlyphId};
+use pdf_writer::types::{
+    BlendMode as PdfBlendMode, CidFontType, ColorSpaceOperand, FontFlags, FunctionShadingType,
+    LineCapStyle, LineJoinStyle, PaintType, SystemInfo, TextRenderingMode, TilingType, UnicodeCmap,
+};
+use pdf_writer::{Content, Finish, Name, Pdf, Rect as PdfRect, Ref, Str};
+use std::collections::BTreeMap;
+use tiny_skia::{
+    BlendMode, Color, FillRule, LineCap, LineJoin, Path, PathBuilder, PathSegment, PathStroker,
+    Pixmap, Rect, Transform,
+};
+
+const SYSTEM_INFO: SystemInfo = SystemInfo {
+    registry: Str(b"Adobe"),
+    ordering: Str(b"Identity"),
+    supplement: 0,
+};
+
+/// Glyphs used from one embedded font
+struct PdfFont {
+    /// PostScript name of font in FontLibrary
+    name: String,
+    /// Glyph id to character and advance width
+    glyphs: BTreeMap<u16, (char, f32)>,
+}
+
+/// Image pattern as drawn with one transform
+///
+/// PDF patterns ignore the transform in effect where they are used, so the
+/// whole transform to the page is part of the pattern.
+#[derive(PartialEq)]
+struct Tiling {
+    /// Index of image in PdfDocument
+    image: usize,
+    /// Transform from image pixels to page
+    matrix: [f32; 6],
+}
+
+/// A PDF document being built up from drawing operations
+pub struct PdfDocument {
+    width: u32,
+    height: u32,
+    /// Content stream operations drawn so far, in order
+    body: Vec<u8>,
+    /// Fonts used so far, resource name is F followed by index
+    fonts: Vec<PdfFont>,
+    /// Opacity levels used so far (0-255), resource name is A followed by level
+    alphas: Vec<u8>,
+    /// Blend modes used so far, resource name is B followed by index
+    blends: Vec<PdfBlendMode>,
+    /// Linear and radial gradients used so far, resource name is Sh followed by index
+    shadings: Vec<Gradient>,
+    /// Pattern images used so far, resource name is Im followed by index
+    images: Vec<Pixmap>,
+    /// Image patterns used so far, resource name is P followed by index
+    tilings: Vec<Tiling>,
+    /// Clip paths in effect at end of body, each in its own saved state
+    clip: Vec<ClipPath>,
+    /// Compositing of operation being drawn
+    compositing: Compositing,
+}
+
+/// Number of wedges used to draw a conic gradient, PDF does not have them
+const CONIC_WEDGES: usize = 180;
+
+/// Opacity to draw paint with, gradients use their most opaque stop
+///
+/// Patterns carry their own opacity in a soft mask of the image.
+fn paint_alpha(paint: &PaintStyle) -> f32 {
+    return match paint {
+        PaintStyle::Color(color) => color.alpha(),
+        PaintStyle::Pattern(_) => 1.0,
+        PaintStyle::Gradient(gradient) => gradient
+            .stops
+            .iter()
+            .map(|stop| stop.color.alpha())
+            .fold(0.0, f32::max),
+    };
+}
+
+/// PDF blend mode, None for source-over and modes PDF does not have
+fn pdf_blend_mode(mode: BlendMode) -> Option<PdfBlendMode> {
+    return match mode {
+        BlendMode::Multiply => Some(PdfBlendMode::Multiply),
+        BlendMode::Screen => Some(PdfBlendMode::Screen),
+        BlendMode::Overlay => Some(PdfBlendMode::Overlay),
+        BlendMode::Darken => Some(PdfBlendMode::Darken),
+        BlendMode::Lighten => Some(PdfBlendMode::Lighten),
+        BlendMode::ColorDodge => Some(PdfBlendMode::ColorDodge),
+        BlendMode::ColorBurn => Some(PdfBlendMode::ColorBurn),
+        BlendMode::HardLight => Some(PdfBlendMode::HardLight),
+        BlendMode::SoftLight => Some(PdfBlendMode::SoftLight),
+        BlendMode::Difference => Some(PdfBlendMode::Difference),
+        BlendMode::Exclusion => Some(PdfBlendMode::Exclusion),
+        BlendMode::Hue => Some(PdfBlendMode::Hue),
+        BlendMode::Saturation => Some(PdfBlendMode::Saturation),
+        BlendMode::Color => Some(PdfBlendMode::Color),
+        BlendMode::Luminosity => Some(PdfBlendMode::Luminosity),
+        _ => None,
+    };
+}
+
+/// Whether paint draws nothing at all
+fn is_blank(paint: &PaintStyle) -> bool {
+    return match paint {
+        PaintStyle::Gradient(gradient) => gradient.is_empty(),
+        _ => false,
+    };
+}
+
+/// Write image as RGB with separate soft mask for alpha if needed, returning its id.
+fn write_image(pdf: &mut Pdf, image: &Pixmap, alloc: &mut impl FnMut() -> Ref) -> Ref {
+    let pixels: Vec<_> = image.pixels().iter().map(|p| p.demultiply()).collect();
+    let rgb: Vec<u8> = pixels
+        .iter()
+        .flat_map(|p| [p.red(), p.green(), p.blue()])
+        .collect();
+    let alpha: Vec<u8> = pixels.iter().map(|p| p.alpha()).collect();
+    let id = alloc();
+    let mask_id = if alpha.iter().any(|a| *a < 255) {
+        let mask_id = alloc();
+        let mut mask = pdf.image_xobject(mask_id, &alpha);
+        mask.width(image.width() as i32);
+        mask.height(image.height() as i32);
+        mask.color_space().device_gray();
+        mask.bits_per_component(8);
+        Some(mask_id)
+    } else {
+        None
+    };
+    let mut xobject = pdf.image_xobject(id, &rgb);
+    xobject.width(image.width() as i32);
+    xobject.height(image.height() as i32);
+    xobject.color_space().device_rgb();
+    xobject.bits_per_component(8);
+    if let Some(mask_id) = mask_id {
+        xobject.s_mask(mask_id);
+    }
+    return id;
+}
+
+/// Write function from 0..1 to RGB interpolating between stops, returning its id.
+///
+/// Stops are padded out to 0 and 1 and joined with a stitching function.
+fn write_stops(pdf: &mut Pdf, stops: &[ColorStop], alloc: &mut impl FnMut() -> Ref) -> Ref {
+    let rgb = |c: &Color| [c.red(), c.green(), c.blue()];
+    let mut points = vec![(0.0, stops[0].color)];
+    points.extend(stops.iter().map(|stop| (stop.offset, stop.color)));
+    points.push((1.0, stops[stops.len() - 1].color));
+    // Zero width intervals are hard steps, nothing to interpolate
+    let intervals: Vec<_> = points
+        .windows(2)
+        .filter(|pair| pair[1].0 > pair[0].0)
+        .map(|pair| (pair[0], pair[1]))
+        .collect();
+    let ids: Vec<Ref> = intervals.iter().map(|_| alloc()).collect();
+    for (((_, c0), (_, c1)), id) in intervals.iter().zip(&ids) {
+        pdf.exponential_function(*id)
+            .domain([0.0, 1.0])
+            .c0(rgb(c0))
+            .c1(rgb(c1))
+            .n(1.0);
+    }
+    if ids.len() == 1 {
+        return ids[0];
+    }
+    let id = alloc();
+    pdf.stitching_function(id)
+        .domain([0.0, 1.0])
+        .functions(ids.iter().copied())
+        .bounds(intervals[1..].iter().map(|((offset, _), _)| *offset))
+        .encode(intervals.iter().flat_map(|_| [0.0, 1.0]));
+    return id;
+}
+
+/// Convert transform to PDF matrix [a b c d e f]
+fn matrix(t: &Transform) -> [f32; 6] {
+    return [t.sx, t.ky, t.kx, t.sy, t.tx, t.ty];
+}
+
+/// Add path segments to content stream
+fn write_path(content: &mut Content, path: &Path) {
+    let mut last = (0.0, 0.0);
+    let mut start = (0.0, 0.0);
+    for segment in path.segments() {
+        match segment {
+            PathSegment::MoveTo(p) => {
+                content.move_to(p.x, p.y);
+                start = (p.x, p.y);
+                last = start;
+            }
+            PathSegment::LineTo(p) => {
+                content.line_to(p.x, p.y);
+                last = (p.x, p.y);
+            }
+            PathSegment::QuadTo(p1, p) => {
+                // PDF only has cubic curves, raise degree of quadratic
+                let c1 = (
+                    last.0 + 2.0 / 3.0 * (p1.x - last.0),
+                    last.1 + 2.0 / 3.0 * (p1.y - last.1),
+                );
+                let c2 = (
+                    p.x + 2.0 / 3.0 * (p1.x - p.x),
+                    p.y + 2.0 / 3.0 * (p1.y - p.y),
+                );
+                content.cubic_to(c1.0, c1.1, c2.0, c2.1, p.x, p.y);
+                last = (p.x, p.y);
+            }
+            PathSegment::CubicTo(p1, p2, p) => {
+                content.cubic_to(p1.x, p1.y, p2.x, p2.y, p.x, p.y);
+                last = (p.x, p.y);
+            }
+            PathSegment::Close => {
+                content.close_path();
+                last = start;
+            }
+        }
+    }
+}
+
+/// Tag identifying a font subset, derived from the glyphs it contains
+fn subset_tag(glyphs: &[u16]) -> String {
+    let mut hash: u32 = 2166136261;
+    for id in glyphs {
+        hash = (hash ^ *id as u32).wrapping_mul(16777619);
+    }
+    let mut tag = String::new();
+    for _ in 0..6 {
+        tag.push((b'A' + (hash % 26) as u8) as char);
+        hash /= 26;
+    }
+    return tag;
+}
+
+impl PdfDocument {
+    /// Create empty document filled with background color.
+    pub fn new(width: u32, height: u32, background: Color) -> Self {
+        let mut document = PdfDocument {
+            width,
+            height,
+            body: vec![],
+            fonts: vec![],
+            alphas: vec![],
+            blends: vec![],
+            shadings: vec![],
+            images: vec![],
+            tilings: vec![],
+            clip: vec![],
+            compositing: Compositing::default(),
+        };
+        document.clear(background);
+        return document;
+    }
+
+    /// Throw away everything drawn, fill with color.
+    pub fn clear(&mut self, color: Color) {
+        self.body.clear();
+        self.clip.clear();
+        if color.alpha() > 0.0 {
+            let rect = Rect::from_xywh(0.0, 0.0, self.width as f32, self.height as f32).unwrap();
+            self.fill_rect(rect, &PaintStyle::Color(color), Transform::identity());
+        }
+    }
+
+    /// Start content for one drawing operation, with transform and opacity set.
+    ///
+    /// Opacity is multiplied by global alpha, and blend mode is set too.
+    fn begin(&mut self, transform: Transform, alpha: f32) -> Content {
+        let mut content = Content::new();
+        content.save_state();
+        if !transform.is_identity() {
+            content.transform(matrix(&transform));
+        }
+        let level = (alpha * self.compositing.alpha * 255.0).round() as u8;
+        if level < 255 {
+            if !self.alphas.contains(&level) {
+                self.alphas.push(level);
+            }
+            content.set_parameters(Name(format!("A{}", level).as_bytes()));
+        }
+        if let Some(mode) = pdf_blend_mode(self.compositing.blend_mode) {
+            let index = match self.blends.iter().position(|m| *m == mode) {
+                Some(index) => index,
+                None => {
+                    self.blends.push(mode);
+                    self.blends.len() - 1
+                }
+            };
+            content.set_parameters(Name(format!("B{}", index).as_bytes()));
+        }
+        return content;
+    }
+
+    /// Finish content for one drawing operation and add it to the page.
+    fn end(&mut self, mut content: Content) {
+        content.restore_state();
+        self.body.extend(content.finish());
+        // Operations are not newline terminated, separate them
+        self.body.push(b'\n');
+    }
+
+    /// Clip operations added next to all paths in clip.
+    ///
+    /// Clip paths already in effect for the previous operation are kept.
+    fn set_clip(&mut self, clip: &[ClipPath]) {
+        let kept = self
+            .clip
+            .iter()
+            .zip(clip)
+            .take_while(|(open, new)| open == new)
+            .count();
+        let mut content = Content::new();
+        for _ in kept..self.clip.len() {
+            content.restore_state();
+        }
+        self.clip.truncate(kept);
+        for path in &clip[kept..] {
+            content.save_state();
+            // Transform points, a matrix would stay in effect for what is drawn next
+            if let Some(transformed) = path.path.clone().transform(path.transform) {
+                write_path(&mut content, &transformed);
+                match path.fill_rule {
+                    FillRule::Winding => content.clip_nonzero(),
+                    FillRule::EvenOdd => content.clip_even_odd(),
+                };
+                content.end_path();
+            }
+            self.clip.push(path.clone());
+        }
+        let content = content.finish();
+        if !content.is_empty() {
+            self.body.extend(content);
+            self.body.push(b'\n');
+        }
+    }
+
+    /// Paint over the current clip, which lies within bounds.
+    ///
+    /// Transform is the one content was started with, which patterns need.
+    fn paint_clip(
+        &mut self,
+        content: &mut Content,
+        paint: &PaintStyle,
+        bounds: Rect,
+        transform: Transform,
+    ) {
+        match paint {
+            PaintStyle::Color(color) => {
+                content.set_fill_rgb(color.red(), color.green(), color.blue());
+            }
+            PaintStyle::Gradient(gradient) => {
+                self.paint_gradient(content, gradient, bounds);
+                return;
+            }
+            PaintStyle::Pattern(pattern) => {
+                let image = match self.images.iter().position(|i| *i == pattern.image) {
+                    Some(index) => index,
+                    None => {
+                        self.images.push(pattern.image.clone());
+                        self.images.len() - 1
+                    }
+                };
+                let page = Transform::from_row(1.0, 0.0, 0.0, -1.0, 0.0, self.height as f32);
+                let tiling = Tiling {
+                    image,
+                    matrix: matrix(
+                        &page
+                            .pre_concat(transform)
+                            .pre_concat(pattern.image_transform()),
+                    ),
+                };
+                let index = match self.tilings.iter().position(|t| *t == tiling) {
+                    Some(index) => index,
+                    None => {
+                        self.tilings.push(tiling);
+                        self.tilings.len() - 1
+                    }
+                };
+                content.set_fill_color_space(ColorSpaceOperand::Pattern);
+                content.set_fill_pattern(None, Name(format!("P{}", index).as_bytes()));
+            }
+        }
+        content.rect(bounds.x(), bounds.y(), bounds.width(), bounds.height());
+        content.fill_nonzero();
+    }
+
+    /// Clip to region covered by pattern paint, if it does not repeat both ways.
+    fn clip_coverage(&mut self, content: &mut Content, paint: &PaintStyle, bounds: Rect) {
+        let PaintStyle::Pattern(pattern) = paint else {
+            return;
+        };
+        if let Some(coverage) = pattern.coverage(bounds, Transform::identity()) {
+            write_path(content, &coverage);
+            content.clip_nonzero();
+            content.end_path();
+        }
+    }
+
+    /// Paint gradient over the current clip, which lies within bounds.
+    fn paint_gradient(&mut self, content: &mut Content, gradient: &Gradient, bounds: Rect) {
+        if let GradientShape::Conic { .. } = gradient.shape {
+            for (wedge, color) in gradient.conic_wedges(bounds, CONIC_WEDGES) {
+                content.set_fill_rgb(color.red(), color.green(), color.blue());
+                write_path(content, &wedge);
+                content.fill_nonzero();
+            }
+            return;
+        }
+        let index = match self.shadings.iter().position(|g| g == gradient) {
+            Some(index) => index,
+            None => {
+                self.shadings.push(gradient.clone());
+                self.shadings.len() - 1
+            }
+        };
+        content.shading(Name(format!("Sh{}", index).as_bytes()));
+    }
+
+    pub fn fill_path(
+        &mut self,
+        path: &Path,
+        paint: &PaintStyle,
+        fill_rule: FillRule,
+        transform: Transform,
+    ) {
+        if is_blank(paint) {
+            return;
+        }
+        let mut content = self.begin(transform, paint_alpha(paint));
+        if let PaintStyle::Color(color) = paint {
+            content.set_fill_rgb(color.red(), color.green(), color.blue());
+            write_path(&mut content, path);
+            match fill_rule {
+                FillRule::Winding => content.fill_nonzero(),
+                FillRule::EvenOdd => content.fill_even_odd(),
+            };
+        } else {
+            self.clip_coverage(&mut content, paint, path.bounds());
+            write_path(&mut content, path);
+            match fill_rule {
+                FillRule::Winding => content.clip_nonzero(),
+                FillRule::EvenOdd => content.clip_even_odd(),
+            };
+            content.end_path();
+            self.paint_clip(&mut content, paint, path.bounds(), transform);
+        }
+        self.end(content);
+    }
+
+    pub fn stroke_path(
+        &mut self,
+        path: &Path,
+        paint: &PaintStyle,
+        stroke: &StrokeStyle,
+        transform: Transform,
+    ) {
+        let color = match paint {
+            PaintStyle::Color(color) => color,
+            PaintStyle::Gradient(_) | PaintStyle::Pattern(_) => {
+                // Shadings can only fill, so fill outline of stroke instead
+                let resolution = PathStroker::compute_resolution_scale(&transform);
+                if let Some(outline) = path.stroke(&stroke.to_stroke(), resolution) {
+                    self.fill_path(&outline, paint, FillRule::Winding, transform);
+                }
+                return;
+            }
+        };
+        let mut content = self.begin(transform, color.alpha());
+        content.set_stroke_rgb(color.red(), color.green(), color.blue());
+        content.set_line_width(stroke.width);
+        // PDF defaults are butt caps, miter joins and miter limit 10 like Canvas
+        match stroke.line_cap {
+            LineCap::Butt => {}
+            LineCap::Round => {
+                content.set_line_cap(LineCapStyle::RoundCap);
+            }
+            LineCap::Square => {
+                content.set_line_cap(LineCapStyle::ProjectingSquareCap);
+            }
+        }
+        match stroke.line_join {
+            LineJoin::Miter | LineJoin::MiterClip => {
+                if stroke.miter_limit != 10.0 {
+                    content.set_miter_limit(stroke.miter_limit);
+                }
+            }
+            LineJoin::Round => {
+                content.set_line_join(LineJoinStyle::RoundJoin);
+            }
+            LineJoin::Bevel => {
+                content.set_line_join(LineJoinStyle::BevelJoin);
+            }
+        }
+        if stroke.is_dashed() {
+            content.set_dash_pattern(stroke.dash.iter().copied(), stroke.dash_offset);
+        }
+        write_path(&mut content, path);
+        content.stroke();
+        self.end(content);
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, paint: &PaintStyle, transform: Transform) {
+        self.fill_path(
+            &PathBuilder::from_rect(rect),
+            paint,
+            FillRule::Winding,
+            transform,
+        );
+    }
+
+    /// Write run of glyphs, all from the font of the first glyph.
+    ///
+    /// Each glyph is positioned individually so that spacing matches the
+    /// renderer's own font metrics.
+    pub fn fill_text(
+        &mut self,
+        glyphs: &[TextGlyph],
+        em_size: f32,
+        paint: &PaintStyle,
+        transform: Transform,
+    ) {
+        let (Some(first), Some(bounds)) = (glyphs.first(), text_bounds(glyphs, em_size)) else {
+            return;
+        };
+        if is_blank(paint) {
+            return;
+        }
+        let index = match self.fonts.iter().position(|f| f.name == first.font) {
+            Some(index) => index,
+            None => {
+                self.fonts.push(PdfFont {
+                    name: first.font.clone(),
+                    glyphs: BTreeMap::new(),
+                });
+                self.fonts.len() - 1
+            }
+        };
+        let mut content = self.begin(transform, paint_alpha(paint));
+        if let PaintStyle::Color(color) = paint {
+            content.set_fill_rgb(color.red(), color.green(), color.blue());
+        } else {
+            // Glyphs become the clip for the paint drawn after the text
+            self.clip_coverage(&mut content, paint, bounds);
+            content.set_text_rendering_mode(TextRenderingMode::Clip);
+        }
+        content.begin_text();
+        content.set_font(Name(format!("F{}", index).as_bytes()), em_size);
+        // Glyph 0 is the missing glyph box, nothing to show
+        for glyph in glyphs.iter().filter(|glyph| glyph.id != 0) {
+            // Page has y going down, flip text back upright
+            content.set_text_matrix([1.0, 0.0, 0.0, -1.0, glyph.x, glyph.y]);
+            content.show(Str(&glyph.id.to_be_bytes()));
+            self.fonts[index]
+                .glyphs
+                .entry(glyph.id)
+                .or_insert((glyph.ch, glyph.advance));
+        }
+        content.end_text();
+        if !matches!(paint, PaintStyle::Color(_)) {
+            // Rough bounds of glyphs are enough, the clip trims the rest
+            self.paint_clip(&mut content, paint, bounds, transform);
+        }
+        self.end(content);
+    }
+
+    /// Draw shadow of command, filled with the shadow color without blur.
+    ///
+    /// Text shadows are glyph outlines, so searching does not find the text twice.
+    fn draw_shadow(&mut self, command: &DrawCommand, shadow: &Shadow, fonts: &FontLibrary) {
+        let paint = PaintStyle::Color(shadow.color);
+        let offset = |t: &Transform| t.post_translate(shadow.offset_x, shadow.offset_y);
+        match command {
+            DrawCommand::Clear { .. } | DrawCommand::ClearRect { .. } => {}
+            DrawCommand::FillRect {
+                rect, transform, ..
+            } => self.fill_rect(*rect, &paint, offset(transform)),
+            DrawCommand::FillPath {
+                path,
+                fill_rule,
+                transform,
+                ..
+            } => self.fill_path(path, &paint, *fill_rule, offset(transform)),
+            DrawCommand::StrokePath {
+                path,
+                stroke,
+                transform,
+                ..
+            } => self.stroke_path(path, &paint, stroke, offset(transform)),
+            DrawCommand::FillText {
+                glyphs,
+                size,
+                transform,
+                ..
+            } => {
+                for glyph in glyphs {
+                    if let Some(path) =
+                        fonts.glyph_path(&glyph.font, glyph.id, *size, glyph.x, glyph.y)
+                    {
+                        self.fill_path(&path, &paint, FillRule::Winding, offset(transform));
+                    }
+                }
+            }
+            DrawCommand::StrokeText {
+                glyphs,
+                size,
+                stroke,
+                transform,
+                ..
+            } => {
+                for glyph in glyphs {
+                    if let Some(path) =
+                        fonts.glyph_path(&glyph.font, glyph.id, *size, glyph.x, glyph.y)
+                    {
+                        self.stroke_path(&path, &paint, stroke, offset(transform));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Complete PDF file contents
+    ///
+    /// Fonts used by text are looked up by name in the font library and
+    /// embedded as subsets containing only the glyphs drawn.
+    pub fn finish(&self, font_library: &FontLibrary) -> Vec<u8> {
+        let mut pdf = Pdf::new();
+        let catalog_id = Ref::new(1);
+        let pages_id = Ref::new(2);
+        let page_id = Ref::new(3);
+        let content_id = Ref::new(4);
+        let mut next_id = 5;
+        let mut alloc = || {
+            next_id += 1;
+            return Ref::new(next_id - 1);
+        };
+
+        let font_ids: Vec<Ref> = self.fonts.iter().map(|_| alloc()).collect();
+        let alpha_ids: Vec<Ref> = self.alphas.iter().map(|_| alloc()).collect();
+        let blend_ids: Vec<Ref> = self.blends.iter().map(|_| alloc()).collect();
+        let shading_ids: Vec<Ref> = self.shadings.iter().map(|_| alloc()).collect();
+        let tiling_ids: Vec<Ref> = self.tilings.iter().map(|_| alloc()).collect();
+
+        pdf.catalog(catalog_id).pages(pages_id);
+        pdf.pages(pages_id).kids([page_id]).count(1);
+        let mut page = pdf.page(page_id);
+        page.parent(pages_id)
+            .media_box(PdfRect::new(
+                0.0,
+                0.0,
+                self.width as f32,
+                self.height as f32,
+            ))
+            .contents(content_id);
+        let mut resources = page.resources();
+        let mut fonts = resources.fonts();
+        for (index, id) in font_ids.iter().enumerate() {
+            fonts.pair(Name(format!("F{}", index).as_bytes()), *id);
+        }
+        fonts.finish();
+        let mut states = resources.ext_g_states();
+        for (level, id) in self.alphas.iter().zip(&alpha_ids) {
+            states.pair(Name(format!("A{}", level).as_bytes()), *id);
+        }
+        for (index, id) in blend_ids.iter().enumerate() {
+            states.pair(Name(format!("B{}", index).as_bytes()), *id);
+        }
+        states.finish();
+        let mut shadings = resources.shadings();
+        for (index, id) in shading_ids.iter().enumerate() {
+            shadings.pair(Name(format!("Sh{}", index).as_bytes()), *id);
+        }
+        shadings.finish();
+        let mut patterns = resources.patterns();
+        for (index, id) in tiling_ids.iter().enumerate() {
+            patterns.pair(Name(format!("P{}", index).as_bytes()), *id);
+        }
+        patterns.finish();
+        resources.finish();
+        page.finish();
+
+        // Flip page so y goes down like the canvas
+        let mut content = Content::new();
+        content.transform([1.0, 0.0, 0.0, -1.0, 0.0, self.height as f32]);
+        let mut data = content.finish();
+        data.push(b'\n');
+        data.extend(&self.body);
+        data.extend(b"Q\n".repeat(self.clip.len()));
+        pdf.stream(content_id, &data);
+
+        for (level, id) in self.alphas.iter().zip(&alpha_ids) {
+            let alpha = *level as f32 / 255.0;
+            pdf.ext_graphics(*id)
+                .non_stroking_alpha(alpha)
+                .stroking_alpha(alpha);
+        }
+        for (mode, id) in self.blends.iter().zip(&blend_ids) {
+            pdf.ext_graphics(*id).blend_mode(*mode);
+        }
+
+        let image_ids: Vec<Ref> = self
+            .images
+            .iter()
+            .map(|image| write_image(&mut pdf, image, &mut alloc))
+            .collect();
+        for (tiling, id) in self.tilings.iter().zip(&tiling_ids) {
+            // One tile is the image in pixel units, upside down as PDF images are
+            let image = &self.images[tiling.image];
+            let (width, height) = (image.width() as f32, image.height() as f32);
+            let mut tile = Content::new();
+            tile.transform([width, 0.0, 0.0, -height, 0.0, height]);
+            tile.x_object(Name(format!("Im{}", tiling.image).as_bytes()));
+            let tile = tile.finish();
+            let mut pattern = pdf.tiling_pattern(*id, &tile);
+            pattern
+                .tiling_type(TilingType::ConstantSpacing)
+                .paint_type(PaintType::Colored)
+                .bbox(PdfRect::new(0.0, 0.0, width, height))
+                .x_step(width)
+                .y_step(height)
+                .matrix(tiling.matrix);
+            pattern.resources().x_objects().pair(
+                Name(format!("Im{}", tiling.image).as_bytes()),
+                image_ids[tiling.image],
+            );
+        }
+
+        for (gradient, id) in self.shadings.iter().zip(&shading_ids) {
+            let function_id = write_stops(&mut pdf, &gradient.stops, &mut alloc);
+            let mut shading = pdf.function_shading(*id);
+            match gradient.shape {
+                GradientShape::Linear { x0, y0, x1, y1 } => {
+                    shading
+                        .shading_type(FunctionShadingType::Axial)
+                        .coords([x0, y0, x1, y1]);
+                }
+                GradientShape::Radial {
+                    x0,
+                    y0,
+                    r0,
+                    x1,
+                    y1,
+                    r1,
+                } => {
+                    shading
+                        .shading_type(FunctionShadingType::Radial)
+                        .coords([x0, y0, r0, x1, y1, r1]);
+                }
+                GradientShape::Conic { .. } => unreachable!("Conic gradients are drawn as wedges"),
+            }
+            shading.color_space().device_rgb();
+            shading.function(function_id).extend([true, true]);
+        }
+
+        for (font, type0_id) in self.fonts.iter().zip(&font_ids) {
+            let cid_id = alloc();
+            let descriptor_id = alloc();
+            let cmap_id = alloc();
+            let data_id = alloc();
+            let face = font_library
+                .font_named(&font.name)
+                .expect("Font used in PDF is not in font library");
+            let units_per_em = face.units_per_em().unwrap_or(1000.0);
+            let to_thousandths = |v: f32| v * 1000.0 / units_per_em;
+            let glyph_ids: Vec<u16> = font.glyphs.keys().copied().collect();
+            let base_font = format!("{}+{}", subset_tag(&glyph_ids), font.name);
+
+            pdf.type0_font(*type0_id)
+                .base_font(Name(format!("{}-Identity-H", base_font).as_bytes()))
+                .encoding_predefined(Name(b"Identity-H"))
+                .descendant_font(cid_id)
+                .to_unicode(cmap_id);
+
+            // Fonts are not CID-keyed, so CIDs are glyph ids
+            let mut cid = pdf.cid_font(cid_id);
+            cid.subtype(CidFontType::Type0)
+                .base_font(Name(base_font.as_bytes()))
+                .system_info(SYSTEM_INFO)
+                .font_descriptor(descriptor_id)
+                .default_width(0.0);
+            let mut widths = cid.widths();
+            for (id, (_, advance)) in &font.glyphs {
+                widths.same(*id, *id, *advance);
+            }
+            widths.finish();
+            cid.finish();
+
+            // Bounding box of glyphs used is good enough for viewers
+            let mut bbox = PdfRect::new(0.0, 0.0, 0.0, 0.0);
+            for id in &glyph_ids {
+                if let Some(outline) = face.outline(GlyphId(*id)) {
+                    bbox.x1 = bbox.x1.min(to_thousandths(outline.bounds.min.x));
+                    bbox.y1 = bbox.y1.min(to_thousandths(outline.bounds.min.y));
+                    bbox.x2 = bbox.x2.max(to_thousandths(outline.bounds.max.x));
+                    bbox.y2 = bbox.y2.max(to_thousandths(outline.bounds.max.y));
+                }
+            }
+            let mut flags = FontFlags::SYMBOLIC;
+            if face.italic_angle() != 0.0 {
+                flags |= FontFlags::ITALIC;
+            }
+            pdf.font_descriptor(descriptor_id)
+                .name(Name(base_font.as_bytes()))
+                .flags(flags)
+                .bbox(bbox)
+                .italic_angle(face.italic_angle())
+                .ascent(to_thousandths(face.ascent_unscaled()))
+                .descent(to_thousandths(face.descent_unscaled()))
+                .cap_height(to_thousandths(face.ascent_unscaled()))
+                .stem_v(80.0)
+                .font_file3(data_id);
+
+            // Map glyphs back to text for copy and search
+            let mut cmap = UnicodeCmap::new(Name(b"Custom"), SYSTEM_INFO);
+            for (id, (ch, _)) in &font.glyphs {
+                cmap.pair(*id, *ch);
+            }
+            pdf.cmap(cmap_id, &cmap.finish());
+
+            // Glyph ids are unchanged by subsetting, fall back to whole font on failure
+            let font_data = face.as_slice();
+            let subset = subsetter::subset(font_data, 0, subsetter::Profile::pdf(&glyph_ids));
+            let embedded = match &subset {
+                Ok(data) => data.as_slice(),
+                Err(_) => font_data,
+            };
+            pdf.stream(data_id, embedded)
+                .pair(Name(b"Subtype"), Name(b"OpenType"));
+        }
+
+        return pdf.finish();
+    }
+}
+
+impl Backend for PdfDocument {
+    fn draw(&mut self, command: &DrawCommand, fonts: &FontLibrary) {
+        self.set_clip(command.clip());
+        self.compositing = command.compositing();
+        let filter = self.compositing.filter.clone();
+        let filtered;
+        let command = if filter.is_empty() {
+            command
+        } else {
+            filtered = command.map_paint(|paint| {
+                paint.map_colors(|color| filter.iter().fold(color, |c, f| f.apply_to_color(c)))
+            });
+            &filtered
+        };
+        let drop_shadows = filter.iter().filter_map(|f| match f {
+            Filter::DropShadow(shadow) => Some(*shadow),
+            _ => None,
+        });
+        for shadow in drop_shadows.chain(self.compositing.shadow) {
+            self.draw_shadow(command, &shadow, fonts);
+        }
+        match command {
+            DrawCommand::Clear { color } => self.clear(*color),
+            // PDF cannot erase, best we can do is paint over with clear color
+            DrawCommand::ClearRect {
+                rect,
+                color,
+                transform,
+                ..
+            } => self.fill_rect(*rect, &PaintStyle::Color(*color), *transform),
+            DrawCommand::FillRect {
+                rect,
+                paint,
+                transform,
+                ..
+            } => self.fill_rect(*rect, paint, *transform),
+            DrawCommand::FillPath {
+                path,
+                paint,
+                fill_rule,
+                transform,
+                ..
+            } => self.fill_path(path, paint, *fill_rule, *transform),
+            DrawCommand::StrokePath {
+                path,
+                paint,
+                stroke,
+                transform,
+                ..
+            } => self.stroke_path(path, paint, stroke, *transform),
+            DrawCommand::FillText {
+                glyphs,
+                size,
+                paint,
+                transform,
+                ..
+            } => {
+                for run in glyphs.chunk_by(|a, b| a.font == b.font) {
+                    let em_size = fonts.em_size(&run[0].font, *size);
+                    self.fill_text(run, em_size, paint, *transform);
+                }
+            }
+            // Stroked as outlines, so this text cannot be selected or searched
+            DrawCommand::StrokeText {
+                glyphs,
+                size,
+                paint,
+                stroke,
+                transform,
+                ..
+            } => {
+                for glyph in glyphs {
+                    if let Some(path) =
+                        fonts.glyph_path(&glyph.font, glyph.id, *size, glyph.x, glyph.y)
+                    {
+                        self.stroke_path(&path, paint, stroke, *transform);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{subset_tag, PdfDocument};
+    use crate::display_list::{
+        Backend, ClipPath, Compositing, DrawCommand, Shadow, StrokeStyle, TextGlyph,
+    };
+    use crate::filter::Filter;
+    use crate::font::FontLibrary;
+    use crate::paint::{Gradient, GradientShape, PaintStyle};
+    use tiny_skia::{BlendMode, Color, FillRule, LineCap, LineJoin, PathBuilder, Rect, Transform};
+
+    #[test]
+    fn test_document() {
+        let mut font_library = FontLibrary::new();
+        let mut doc = PdfDocument::new(20, 10, Color::from_rgba8(255, 255, 255, 255));
+        doc.fill_rect(
+            Rect::from_xywh(1.0, 2.0, 3.0, 4.0).unwrap(),
+            &PaintStyle::Color(Color::from_rgba8(255, 0, 0, 128)),
+            Transform::from_scale(2.0, 2.0),
+        );
+        let mut gradient = Gradient::new(GradientShape::Linear {
+            x0: 0.0,
+            y0: 0.0,
+            x1: 10.0,
+            y1: 0.0,
+        });
+        gradient.add_color_stop(0.0, Color::BLACK);
+        gradient.add_color_stop(0.5, Color::WHITE);
+        gradient.add_color_stop(1.0, Color::BLACK);
+        doc.fill_rect(
+            Rect::from_xywh(0.0, 0.0, 10.0, 10.0).unwrap(),
+            &PaintStyle::Gradient(gradient),
+            Transform::identity(),
+        );
+        let glyph = font_library.resolve_glyph('a' as u32, 10.0, false, false);
+        doc.fill_text(
+            &[TextGlyph {
+                ch: 'a',
+                font: glyph.font.to_string(),
+                id: glyph.id,
+                advance: glyph.advance,
+                x: 1.0,
+                y: 2.0,
+            }],
+            font_library.em_size(glyph.font, 10.0),
+            &PaintStyle::Color(Color::BLACK),
+            Transform::identity(),
+        );
+        let pdf = doc.finish(&font_library);
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(pdf.starts_with(b"%PDF-"));
+        assert!(text.contains("/MediaBox [0 0 20 10]"));
+        assert!(text.contains("1 0 0 -1 0 10 cm\nq\n"));
+        assert!(text.contains("/A128 gs"));
+        assert!(text.contains("W\nn\n/Sh0 sh"));
+        assert!(text.contains("/ShadingType 2"));
+        assert!(text.contains("/FunctionType 3"));
+        assert!(text.contains("/FontFile3"));
+        assert!(text.contains("+Academico-Regular"));
+        assert!(text.contains("/Subtype /OpenType"));
+    }
+
+    #[test]
+    fn test_dash() {
+        let font_library = FontLibrary::new();
+        let mut doc = PdfDocument::new(20, 10, Color::TRANSPARENT);
+        let path = PathBuilder::from_rect(Rect::from_xywh(1.0, 1.0, 5.0, 5.0).unwrap());
+        let mut stroke = StrokeStyle {
+            dash: vec![4.0, 1.0],
+            dash_offset: 2.0,
+            ..StrokeStyle::default()
+        };
+        let black = PaintStyle::Color(Color::BLACK);
+        doc.stroke_path(&path, &black, &stroke, Transform::identity());
+        // All zero lengths are a solid line
+        stroke.dash = vec![0.0, 0.0];
+        doc.stroke_path(&path, &black, &stroke, Transform::identity());
+        let pdf = doc.finish(&font_library);
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.contains("[4 1] 2 d"));
+        assert_eq!(text.matches(" d\n").count(), 1);
+    }
+
+    #[test]
+    fn test_line_cap_join() {
+        let font_library = FontLibrary::new();
+        let path = PathBuilder::from_rect(Rect::from_xywh(1.0, 1.0, 5.0, 5.0).unwrap());
+        let stroked = |stroke: StrokeStyle| {
+            let mut doc = PdfDocument::new(20, 10, Color::TRANSPARENT);
+            let black = PaintStyle::Color(Color::BLACK);
+            doc.stroke_path(&path, &black, &stroke, Transform::identity());
+            let pdf = doc.finish(&font_library);
+            return String::from_utf8_lossy(&pdf).to_string();
+        };
+        let text = stroked(StrokeStyle {
+            line_cap: LineCap::Square,
+            line_join: LineJoin::Round,
+            ..StrokeStyle::default()
+        });
+        assert!(text.contains("\n2 J\n1 j\n"));
+        let text = stroked(StrokeStyle {
+            line_cap: LineCap::Round,
+            line_join: LineJoin::Bevel,
+            ..StrokeStyle::default()
+        });
+        assert!(text.contains("\n1 J\n2 j\n"));
+        let text = stroked(StrokeStyle {
+            miter_limit: 4.0,
+            ..StrokeStyle::default()
+        });
+        assert!(text.contains("\n4 M\n"));
+        // Canvas defaults are the PDF defaults
+        let text = stroked(StrokeStyle::default());
+        assert!(!text.contains(" J\n") && !text.contains(" j\n") && !text.contains(" M\n"));
+    }
+
+    #[test]
+    fn test_clip() {
+        let font_library = FontLibrary::new();
+        let mut doc = PdfDocument::new(20, 10, Color::TRANSPARENT);
+        let fill = |clip: Vec<ClipPath>| DrawCommand::FillRect {
+            rect: Rect::from_xywh(0.0, 0.0, 10.0, 10.0).unwrap(),
+            paint: PaintStyle::Color(Color::BLACK),
+            transform: Transform::identity(),
+            clip,
+            compositing: Compositing::default(),
+        };
+        let clip = ClipPath {
+            path: PathBuilder::from_rect(Rect::from_xywh(1.0, 1.0, 2.0, 2.0).unwrap()),
+            fill_rule: FillRule::EvenOdd,
+            transform: Transform::from_scale(2.0, 2.0),
+        };
+        doc.draw(&fill(vec![clip.clone()]), &font_library);
+        doc.draw(&fill(vec![clip.clone()]), &font_library);
+        doc.draw(&fill(vec![]), &font_library);
+        doc.draw(&fill(vec![clip]), &font_library);
+        let pdf = doc.finish(&font_library);
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.contains("q\n2 2 m\n6 2 l\n6 6 l\n2 6 l\nh\nW*\nn\n"));
+        assert_eq!(text.matches("W*").count(), 2);
+        assert_eq!(text.matches("q\n").count(), text.matches("Q\n").count());
+    }
+
+    #[test]
+    fn test_compositing() {
+        let font_library = FontLibrary::new();
+        let mut doc = PdfDocument::new(20, 10, Color::TRANSPARENT);
+        doc.draw(
+            &DrawCommand::FillRect {
+                rect: Rect::from_xywh(0.0, 0.0, 10.0, 10.0).unwrap(),
+                paint: PaintStyle::Color(Color::from_rgba8(0, 0, 0, 128)),
+                transform: Transform::identity(),
+                clip: vec![],
+                compositing: Compositing {
+                    alpha: 0.5,
+                    blend_mode: BlendMode::Screen,
+                    ..Compositing::default()
+                },
+            },
+            &font_library,
+        );
+        let pdf = doc.finish(&font_library);
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.contains("q\n/A64 gs\n/B0 gs\n"));
+        assert!(text.contains("/BM /Screen"));
+    }
+
+    #[test]
+    fn test_filter() {
+        let font_library = FontLibrary::new();
+        let mut doc = PdfDocument::new(20, 10, Color::TRANSPARENT);
+        doc.draw(
+            &DrawCommand::FillRect {
+                rect: Rect::from_xywh(0.0, 0.0, 10.0, 10.0).unwrap(),
+                paint: PaintStyle::Color(Color::BLACK),
+                transform: Transform::identity(),
+                clip: vec![],
+                compositing: Compositing {
+                    filter: vec![Filter::Blur(2.0), Filter::Invert(1.0), Filter::Opacity(0.5)],
+                    ..Compositing::default()
+                },
+            },
+            &font_library,
+        );
+        let pdf = doc.finish(&font_library);
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.contains("q\n/A128 gs\n1 1 1 rg\n"));
+    }
+
+    #[test]
+    fn test_shadow() {
+        let font_library = FontLibrary::new();
+        let mut doc = PdfDocument::new(20, 10, Color::TRANSPARENT);
+        doc.draw(
+            &DrawCommand::FillRect {
+                rect: Rect::from_xywh(0.0, 0.0, 10.0, 10.0).unwrap(),
+                paint: PaintStyle::Color(Color::BLACK),
+                transform: Transform::identity(),
+                clip: vec![],
+                compositing: Compositing {
+                    shadow: Some(Shadow {
+                        color: Color::from_rgba8(255, 0, 0, 255),
+                        blur: 4.0,
+                        offset_x: 2.0,
+                        offset_y: 3.0,
+                    }),
+                    ..Compositing::default()
+                },
+            },
+            &font_library,
+        );
+        let pdf = doc.finish(&font_library);
+        let text = String::from_utf8_lossy(&pdf);
+        let shadow = text.find("q\n1 0 0 1 2 3 cm\n1 0 0 rg\n").unwrap();
+        let fill = text.find("q\n0 0 0 rg\n").unwrap();
+        assert!(shadow < fill);
+    }
+
+    #[test]
+    fn test_subset_tag() {
+        let tag = subset_tag(&[1, 2, 3]);
+        assert_eq!(tag.len(), 6);
+        assert!(tag.chars().all(|c| c.is_ascii_uppercase()));
+        assert_eq!(tag, subset_tag(&[1, 2, 3]));
+        assert_ne!(tag, subset_tag(&[1, 2, 4]));
+    }
+}
@@ -0,0 +1,1113 @@
+//
+// Raster output for drawing operations
+//
+// Draws display list commands into a tiny-skia Pixmap with anti-aliasing.
+// Clip paths become a mask that every drawing operation is drawn through.
+// Color filters are applied to the paint. Operations with other filters or
+// shadows are drawn into an offscreen layer first, just big enough for them,
+// which is filtered, and blurred and colored for the shadow, before it is
+// drawn to the surface.
+//
+
+use crate::display_list::{Backend, ClipPath, Compositing, DrawCommand, Shadow, TextGlyph};
+use crate::filter::Filter;
+use crate::font::FontLibrary;
+use crate::paint::{ColorStop, Gradient, GradientShape, PaintStyle};
+use ab_glyph::{point, Font, GlyphId, ScaleFont};
+use std::cmp::{max, min};
+use std::f32::consts::TAU;
+use tiny_skia::{
+    BlendMode, Color, FillRule, FilterQuality, GradientStop, IntRect, LinearGradient, Mask, Paint,
+    Pattern, Pixmap, PixmapPaint, Point, PremultipliedColorU8, RadialGradient, Rect, SpreadMode,
+    Transform,
+};
+
+/// Render conic gradient for area of surface, in device pixels.
+///
+/// tiny-skia has no conic gradient shader, so this is drawn as a pattern.
+fn conic_pixmap(gradient: &Gradient, transform: Transform, area: IntRect) -> Option<Pixmap> {
+    let GradientShape::Conic { angle, x, y } = gradient.shape else {
+        return None;
+    };
+    let inverse = transform.invert()?;
+    let (width, height) = (area.width(), area.height());
+    let mut pixmap = Pixmap::new(width, height)?;
+    let pixels = pixmap.pixels_mut();
+    for py in 0..height {
+        for px in 0..width {
+            let mut p = Point::from_xy(
+                (area.x() + px as i32) as f32 + 0.5,
+                (area.y() + py as i32) as f32 + 0.5,
+            );
+            inverse.map_point(&mut p);
+            let t = ((p.y - y).atan2(p.x - x) - angle).rem_euclid(TAU) / TAU;
+            let color = gradient.color_at(t).premultiply().to_color_u8();
+            pixels[(px + py * width) as usize] = color;
+        }
+    }
+    return Some(pixmap);
+}
+
+/// Mask for surface of given size covering the intersection of clip paths, None if clip is empty
+pub(crate) fn clip_mask(width: u32, height: u32, clip: &[ClipPath]) -> Option<Mask> {
+    let (first, rest) = clip.split_first()?;
+    let mut mask = Mask::new(width, height)?;
+    mask.fill_path(&first.path, first.fill_rule, true, first.transform);
+    for path in rest {
+        mask.intersect_path(&path.path, path.fill_rule, true, path.transform);
+    }
+    return Some(mask);
+}
+
+/// Call draw with a tiny-skia Paint for paint style, unless it draws nothing.
+///
+/// Gradients and patterns are in user coordinates, tiny-skia applies the
+/// transform of what is drawn to the shader as well. Conic gradients are only
+/// rendered over the bounds of command. Draw gets the clip mask, narrowed for
+/// patterns that do not repeat both ways.
+fn with_paint(
+    pixmap: &mut Pixmap,
+    command: &DrawCommand,
+    style: &PaintStyle,
+    transform: Transform,
+    compositing: &Compositing,
+    clip: Option<&Mask>,
+    draw: impl FnOnce(&mut Pixmap, &Paint, Option<&Mask>),
+) {
+    let conic;
+    let mut mask = None;
+    let mut paint = Paint::default();
+    paint.anti_alias = true;
+    match style {
+        PaintStyle::Color(color) => paint.set_color(*color),
+        PaintStyle::Gradient(gradient) => {
+            if gradient.is_empty() {
+                return;
+            }
+            let stops = |stops: &[ColorStop]| {
+                stops
+                    .iter()
+                    .map(|stop| GradientStop::new(stop.offset, stop.color))
+                    .collect()
+            };
+            let shader = match gradient.shape {
+                GradientShape::Linear { x0, y0, x1, y1 } => LinearGradient::new(
+                    Point::from_xy(x0, y0),
+                    Point::from_xy(x1, y1),
+                    stops(&gradient.stops),
+                    SpreadMode::Pad,
+                    Transform::identity(),
+                ),
+                GradientShape::Radial { .. } => {
+                    let Some((focal, center, radius, focal_stops)) = gradient.focal_radial() else {
+                        return;
+                    };
+                    RadialGradient::new(
+                        focal,
+                        center,
+                        radius,
+                        stops(&focal_stops),
+                        SpreadMode::Pad,
+                        Transform::identity(),
+                    )
+                }
+                GradientShape::Conic { .. } => {
+                    let surface =
+                        Rect::from_xywh(0.0, 0.0, pixmap.width() as f32, pixmap.height() as f32);
+                    // One more pixel for anti-aliasing
+                    let area = match command.bounds() {
+                        Some(bounds) => bounds
+                            .outset(1.0, 1.0)
+                            .and_then(|bounds| bounds.intersect(&surface?)),
+                        None => surface,
+                    };
+                    let Some(area) = area.and_then(|area| area.round_out()) else {
+                        return;
+                    };
+                    // Pattern is in device pixels at area, undo transform tiny-skia applies
+                    let (Some(pattern), Some(inverse)) =
+                        (conic_pixmap(gradient, transform, area), transform.invert())
+                    else {
+                        return;
+                    };
+                    conic = pattern;
+                    Some(Pattern::new(
+                        conic.as_ref(),
+                        SpreadMode::Pad,
+                        FilterQuality::Nearest,
+                        1.0,
+                        inverse.pre_translate(area.x() as f32, area.y() as f32),
+                    ))
+                }
+            };
+            let Some(shader) = shader else {
+                return;
+            };
+            paint.shader = shader;
+        }
+        PaintStyle::Pattern(pattern) => {
+            paint.shader = Pattern::new(
+                pattern.image.as_ref(),
+                SpreadMode::Repeat,
+                FilterQuality::Bilinear,
+                1.0,
+                pattern.image_transform(),
+            );
+            let bounds = Rect::from_xywh(0.0, 0.0, pixmap.width() as f32, pixmap.height() as f32);
+            if let Some(coverage) = bounds.and_then(|bounds| pattern.coverage(bounds, transform)) {
+                let coverage_mask = match clip {
+                    Some(clip) => {
+                        let mut mask = clip.clone();
+                        mask.intersect_path(
+                            &coverage,
+                            FillRule::Winding,
+                            true,
+                            Transform::identity(),
+                        );
+                        mask
+                    }
+                    None => {
+                        let Some(mut mask) = Mask::new(pixmap.width(), pixmap.height()) else {
+                            return;
+                        };
+                        mask.fill_path(&coverage, FillRule::Winding, true, Transform::identity());
+                        mask
+                    }
+                };
+                mask = Some(coverage_mask);
+            }
+        }
+    }
+    paint.shader.apply_opacity(compositing.alpha);
+    paint.blend_mode = compositing.blend_mode;
+    draw(pixmap, &paint, mask.as_ref().or(clip));
+}
+
+/// Draw one glyph.
+///
+/// Algorithm is to render glyph to fresh pixmap with anti-aliasing and final
+/// color, then draw the glyph pixmap to the surface through the transformation
+/// matrix. This allows text to be scaled, rotated, etc. and to have alpha
+/// blending with existing surface.
+///
+/// extra_zoom parameter is extra factor to avoid pixellation during rendering
+/// for transformations that do scaling. Avoids doing things like drawing pixel
+/// glyph bitmap with scale factor of 2 (blocky pixels).
+#[allow(clippy::too_many_arguments)]
+fn fill_glyph(
+    pixmap: &mut Pixmap,
+    fonts: &FontLibrary,
+    glyph: &TextGlyph,
+    size: f32,
+    color: Color,
+    transform: Transform,
+    extra_zoom: f32,
+    blend_mode: BlendMode,
+    clip: Option<&Mask>,
+) {
+    // Glyph is rendered at extra_zoom, undo that before transform so that its
+    // translation is kept as it is
+    let descaled_transform = transform
+        .pre_scale(1.0 / extra_zoom, 1.0 / extra_zoom)
+        .post_translate(-1.3, -1.3);
+    let r = color.red() as f64;
+    let g = color.green() as f64;
+    let b = color.blue() as f64;
+    let a = color.alpha() as f64;
+    let x_real = glyph.x * extra_zoom;
+    let y_real = glyph.y * extra_zoom;
+    // Glyphs this far out would overflow pixel coordinates, and cannot show
+    if !(x_real.abs() < 1e8 && y_real.abs() < 1e8) {
+        return;
+    }
+    let x_i = x_real.floor() as i32;
+    let y_i = y_real.floor() as i32;
+    let x_frac = x_real.fract();
+    let y_frac = y_real.fract();
+    let Some(scaled_font) = fonts.scaled_font(&glyph.font, size * extra_zoom) else {
+        return;
+    };
+    let positioned =
+        GlyphId(glyph.id).with_scale_and_position(scaled_font.scale(), point(x_frac, y_frac));
+    let Some(og) = scaled_font.font().outline_glyph(positioned) else {
+        return;
+    };
+    let bounds = og.px_bounds();
+    // Compute size of pixmap for glyph, leaving ring of empty pixels around it.
+    // In worst case, bounds are exact like 0.0--1.0. Then we need actual size 2, with padding on both sides gets to 4.
+    let rg_width = (f32::ceil(bounds.max.x) as i32 - f32::floor(bounds.min.x) as i32 + 3) as u32;
+    let rg_height = (f32::ceil(bounds.max.y) as i32 - f32::floor(bounds.min.y) as i32 + 3) as u32;
+    let Some(mut rendered_glyph) = Pixmap::new(rg_width, rg_height) else {
+        return;
+    };
+    let rg_pixels = rendered_glyph.pixels_mut();
+    og.draw(|xx, yy, c| {
+        let true_alpha = (c as f64) * a;
+        let rg_xi = xx;
+        let rg_yi = yy;
+        if let Some(color) = PremultipliedColorU8::from_rgba(
+            (r * true_alpha * 255.0) as u8,
+            (g * true_alpha * 255.0) as u8,
+            (b * true_alpha * 255.0) as u8,
+            (true_alpha * 255.0) as u8,
+        ) {
+            // Offset by (1, 1) to get ring of transparency for interpolation purposes by draw_pixmap
+            rg_pixels[(rg_xi + 1 + (rg_yi + 1) * rg_width) as usize] = color;
+        }
+    });
+    pixmap.draw_pixmap(
+        x_i + bounds.min.x as i32,
+        y_i + bounds.min.y as i32,
+        rendered_glyph.as_ref(),
+        &PixmapPaint {
+            blend_mode,
+            ..PixmapPaint::default()
+        },
+        descaled_transform,
+        clip,
+    );
+}
+
+/// Widths of three box blurs that together approximate a Gaussian of standard deviation sigma
+fn box_sizes(sigma: f32) -> [usize; 3] {
+    let variance = 12.0 * sigma * sigma;
+    let ideal = (variance / 3.0 + 1.0).sqrt().floor().max(1.0) as usize;
+    // Widths must be odd so boxes have a center
+    let lower = ideal - 1 + ideal % 2;
+    // How many boxes are of the lower width, the others are 2 wider
+    let l = lower as f32;
+    let count = ((variance - 3.0 * l * l - 12.0 * l - 9.0) / (-4.0 * l - 4.0)).round();
+    return [0.0, 1.0, 2.0].map(|i| if i < count { lower } else { lower + 2 });
+}
+
+/// How far blur() with standard deviation spreads what is drawn, in pixels
+fn blur_margin(deviation: f32) -> usize {
+    return box_sizes(deviation).iter().map(|size| size / 2).sum();
+}
+
+/// Box blur rows or columns of width by height grid, with box of odd size.
+///
+/// Values outside the grid count as 0.
+fn box_blur(values: &mut [f32], width: usize, height: usize, size: usize, rows: bool) {
+    let (lines, length, step) = if rows {
+        (height, width, 1)
+    } else {
+        (width, height, width)
+    };
+    let radius = size / 2;
+    let mut line = vec![0.0; length];
+    for l in 0..lines {
+        let start = if rows { l * width } else { l };
+        for (i, value) in line.iter_mut().enumerate() {
+            *value = values[start + i * step];
+        }
+        // Running sum of the box around i
+        let mut sum: f32 = line.iter().take(radius + 1).sum();
+        for i in 0..length {
+            values[start + i * step] = sum / size as f32;
+            if i + radius + 1 < length {
+                sum += line[i + radius + 1];
+            }
+            if i >= radius {
+                sum -= line[i - radius];
+            }
+        }
+    }
+}
+
+/// Blur width by height grid of values with a Gaussian of standard deviation sigma.
+///
+/// The Gaussian is approximated by three box blurs, so time does not grow with
+/// sigma. Values outside the grid count as 0.
+fn gaussian_blur(values: &[f32], width: usize, height: usize, sigma: f32) -> Vec<f32> {
+    let mut result = values.to_vec();
+    if sigma <= 0.0 {
+        return result;
+    }
+    for size in box_sizes(sigma) {
+        box_blur(&mut result, width, height, size, true);
+        box_blur(&mut result, width, height, size, false);
+    }
+    return result;
+}
+
+/// Bounds of pixels that are not transparent, grown by margin and kept inside pixmap.
+///
+/// Bounds are x0, y0, x1, y1 with x1 and y1 just past the last column and row.
+fn drawn_bounds(pixmap: &Pixmap, margin: usize) -> Option<(usize, usize, usize, usize)> {
+    let width = pixmap.width() as usize;
+    let height = pixmap.height() as usize;
+    let mut drawn = None;
+    for (i, pixel) in pixmap.pixels().iter().enumerate() {
+        if pixel.alpha() > 0 {
+            let (x, y) = (i % width, i / width);
+            drawn = Some(match drawn {
+                Some((x0, y0, x1, y1)) => (min(x0, x), min(y0, y), max(x1, x), max(y1, y)),
+                None => (x, y, x, y),
+            });
+        }
+    }
+    let (x0, y0, x1, y1) = drawn?;
+    return Some((
+        x0.saturating_sub(margin),
+        y0.saturating_sub(margin),
+        min(x1 + margin + 1, width),
+        min(y1 + margin + 1, height),
+    ));
+}
+
+/// Blur pixmap with a Gaussian of standard deviation in pixels.
+fn blur(pixmap: &mut Pixmap, deviation: f32) {
+    if deviation <= 0.0 {
+        return;
+    }
+    let Some((x0, y0, x1, y1)) = drawn_bounds(pixmap, blur_margin(deviation)) else {
+        return;
+    };
+    let width = pixmap.width() as usize;
+    let index = |x: usize, y: usize| (x + y * width) * 4;
+    let data = pixmap.data_mut();
+    let channels: Vec<Vec<f32>> = (0..4)
+        .map(|channel| {
+            let mut values = vec![];
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    values.push(data[index(x, y) + channel] as f32);
+                }
+            }
+            gaussian_blur(&values, x1 - x0, y1 - y0, deviation)
+        })
+        .collect();
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let i = x - x0 + (y - y0) * (x1 - x0);
+            let alpha = channels[3][i].round().clamp(0.0, 255.0);
+            // Premultiplied color components must not end up above alpha
+            for (channel, values) in channels.iter().enumerate() {
+                data[index(x, y) + channel] = values[i].round().clamp(0.0, alpha) as u8;
+            }
+        }
+    }
+}
+
+/// Shadow of what is on layer, coverage filled with shadow color and blurred but not offset
+fn shadow_pixmap(layer: &Pixmap, shadow: &Shadow) -> Pixmap {
+    let mut pixmap = layer.clone();
+    for pixel in pixmap.pixels_mut() {
+        let mut color = shadow.color;
+        color.apply_opacity(pixel.alpha() as f32 / 255.0);
+        *pixel = color.premultiply().to_color_u8();
+    }
+    blur(&mut pixmap, shadow.blur / 2.0);
+    return pixmap;
+}
+
+/// Apply filter to everything on layer.
+fn apply_filter(layer: &mut Pixmap, filter: &Filter) {
+    match filter {
+        Filter::Blur(deviation) => blur(layer, *deviation),
+        Filter::DropShadow(shadow) => {
+            let Some(mut result) = Pixmap::new(layer.width(), layer.height()) else {
+                return;
+            };
+            result.draw_pixmap(
+                0,
+                0,
+                shadow_pixmap(layer, shadow).as_ref(),
+                &PixmapPaint {
+                    quality: FilterQuality::Bilinear,
+                    ..PixmapPaint::default()
+                },
+                Transform::from_translate(shadow.offset_x, shadow.offset_y),
+                None,
+            );
+            result.draw_pixmap(
+                0,
+                0,
+                layer.as_ref(),
+                &PixmapPaint::default(),
+                Transform::identity(),
+                None,
+            );
+            *layer = result;
+        }
+        _ => {
+            for pixel in layer.pixels_mut() {
+                if pixel.alpha() == 0 {
+                    continue;
+                }
+                let c = pixel.demultiply();
+                let color = Color::from_rgba8(c.red(), c.green(), c.blue(), c.alpha());
+                *pixel = filter.apply_to_color(color).premultiply().to_color_u8();
+            }
+        }
+    }
+}
+
+/// How far the shadow blur spreads what is drawn, in pixels
+fn shadow_margin(shadow: &Shadow) -> f32 {
+    // Shadow blur is twice the deviation
+    return blur_margin(shadow.blur / 2.0) as f32;
+}
+
+/// Area of surface, in pixels, that command is drawn into a layer for.
+///
+/// This is the bounds of the command, outset by how far filters and the shadow
+/// blur spread them. Parts of the command are left out when neither they nor
+/// their shadow can reach the surface.
+fn layer_rect(
+    pixmap: &Pixmap,
+    command: &DrawCommand,
+    compositing: &Compositing,
+) -> Option<IntRect> {
+    let mut margin = 0.0;
+    for filter in &compositing.filter {
+        margin += match filter {
+            Filter::Blur(deviation) => blur_margin(*deviation) as f32,
+            Filter::DropShadow(shadow) => {
+                shadow_margin(shadow) + shadow.offset_x.abs().max(shadow.offset_y.abs())
+            }
+            _ => 0.0,
+        };
+    }
+    let mut reach = margin;
+    if let Some(shadow) = &compositing.shadow {
+        margin += shadow_margin(shadow);
+        reach = margin + shadow.offset_x.abs().max(shadow.offset_y.abs());
+    }
+    // One more pixel for anti-aliasing
+    let margin = margin.ceil() + 1.0;
+    let reach = reach.ceil() + 1.0;
+    let surface = Rect::from_xywh(0.0, 0.0, pixmap.width() as f32, pixmap.height() as f32)?
+        .outset(reach, reach)?;
+    let rect = match command.bounds() {
+        Some(bounds) => bounds.outset(margin, margin)?.intersect(&surface)?,
+        None => surface,
+    };
+    return rect.round_out();
+}
+
+/// Draw command through an offscreen layer, for filters and shadows.
+///
+/// The command is drawn alone into a layer covering its bounds and filtered.
+/// The shadow of the layer is drawn first, then the layer itself, both with
+/// the compositing of the command.
+fn draw_layer(
+    pixmap: &mut Pixmap,
+    command: &DrawCommand,
+    fonts: &FontLibrary,
+    compositing: &Compositing,
+    clip: Option<&Mask>,
+) {
+    let Some(rect) = layer_rect(pixmap, command, compositing) else {
+        return;
+    };
+    let Some(mut layer) = Pixmap::new(rect.width(), rect.height()) else {
+        return;
+    };
+    let (x, y) = (rect.x(), rect.y());
+    draw_clipped(
+        &mut layer,
+        &command
+            .with_compositing(Compositing::default())
+            .translated(-x as f32, -y as f32),
+        fonts,
+        None,
+    );
+    for filter in &compositing.filter {
+        apply_filter(&mut layer, filter);
+    }
+    let paint = PixmapPaint {
+        opacity: compositing.alpha,
+        blend_mode: compositing.blend_mode,
+        quality: FilterQuality::Bilinear,
+    };
+    if let Some(shadow) = &compositing.shadow {
+        pixmap.draw_pixmap(
+            x,
+            y,
+            shadow_pixmap(&layer, shadow).as_ref(),
+            &paint,
+            Transform::from_translate(shadow.offset_x, shadow.offset_y),
+            clip,
+        );
+    }
+    pixmap.draw_pixmap(x, y, layer.as_ref(), &paint, Transform::identity(), clip);
+}
+
+/// Draw command through clip mask, which must match the clip of the command.
+///
+/// DrawContext keeps the mask for its current clip, so it is not made again
+/// for every command.
+pub(crate) fn draw_clipped(
+    pixmap: &mut Pixmap,
+    command: &DrawCommand,
+    fonts: &FontLibrary,
+    clip: Option<&Mask>,
+) {
+    let compositing = command.compositing();
+    // Color filters can be applied to the paint instead of a layer
+    if !compositing.filter.is_empty() && compositing.filter.iter().all(Filter::is_color) {
+        let filter = compositing.filter.clone();
+        let filtered = command
+            .map_paint(|paint| {
+                paint.map_colors(|color| filter.iter().fold(color, |c, f| f.apply_to_color(c)))
+            })
+            .with_compositing(Compositing {
+                filter: vec![],
+                ..compositing
+            });
+        draw_clipped(pixmap, &filtered, fonts, clip);
+        return;
+    }
+    if compositing.shadow.is_some() || !compositing.filter.is_empty() {
+        draw_layer(pixmap, command, fonts, &compositing, clip);
+        return;
+    }
+    match command {
+        DrawCommand::Clear { color } => {
+            pixmap.fill(*color);
+        }
+        DrawCommand::ClearRect {
+            rect,
+            color,
+            transform,
+            ..
+        } => {
+            let mut paint = Paint::default();
+            paint.set_color(*color);
+            paint.anti_alias = true;
+            paint.blend_mode = BlendMode::Source;
+            pixmap.fill_rect(*rect, &paint, *transform, clip);
+        }
+        DrawCommand::FillRect {
+            rect,
+            paint,
+            transform,
+            compositing,
+            ..
+        } => with_paint(
+            pixmap,
+            command,
+            paint,
+            *transform,
+            compositing,
+            clip,
+            |pixmap, paint, mask| {
+                pixmap.fill_rect(*rect, paint, *transform, mask);
+            },
+        ),
+        DrawCommand::FillPath {
+            path,
+            paint: PaintStyle::Color(color),
+            fill_rule,
+            transform,
+            compositing,
+            ..
+        } => {
+            let mut paint = Paint::default();
+            paint.set_color_rgba8(
+                (color.red() * 255.0) as u8,
+                (color.green() * 255.0) as u8,
+                (color.blue() * 255.0) as u8,
+                (color.alpha() * 255.0) as u8,
+            );
+            paint.anti_alias = true;
+            paint.shader.apply_opacity(compositing.alpha);
+            paint.blend_mode = compositing.blend_mode;
+            pixmap.fill_path(path, &paint, *fill_rule, *transform, clip);
+        }
+        DrawCommand::FillPath {
+            path,
+            paint,
+            fill_rule,
+            transform,
+            compositing,
+            ..
+        } => with_paint(
+            pixmap,
+            command,
+            paint,
+            *transform,
+            compositing,
+            clip,
+            |pixmap, paint, mask| {
+                pixmap.fill_path(path, paint, *fill_rule, *transform, mask);
+            },
+        ),
+        DrawCommand::StrokePath {
+            path,
+            paint,
+            stroke,
+            transform,
+            compositing,
+            ..
+        } => with_paint(
+            pixmap,
+            command,
+            paint,
+            *transform,
+            compositing,
+            clip,
+            |pixmap, paint, mask| {
+                pixmap.stroke_path(path, paint, &stroke.to_stroke(), *transform, mask);
+            },
+        ),
+        DrawCommand::FillText {
+            glyphs,
+            size,
+            paint: PaintStyle::Color(color),
+            transform,
+            compositing,
+            ..
+        } => {
+            // Compute extra_zoom as max of scale factors. Should look good in every situation I think.
+            let extra_zoom = f32::max(transform.sx.abs(), transform.sy.abs());
+            let mut color = *color;
+            color.apply_opacity(compositing.alpha);
+            for glyph in glyphs {
+                fill_glyph(
+                    pixmap,
+                    fonts,
+                    glyph,
+                    *size,
+                    color,
+                    *transform,
+                    extra_zoom,
+                    compositing.blend_mode,
+                    clip,
+                );
+            }
+        }
+        DrawCommand::FillText {
+            glyphs,
+            size,
+            paint,
+            transform,
+            compositing,
+            ..
+        } => {
+            // Glyph bitmaps are drawn in one color, fill outlines instead
+            with_paint(
+                pixmap,
+                command,
+                paint,
+                *transform,
+                compositing,
+                clip,
+                |pixmap, paint, mask| {
+                    for glyph in glyphs {
+                        if let Some(path) =
+                            fonts.glyph_path(&glyph.font, glyph.id, *size, glyph.x, glyph.y)
+                        {
+                            pixmap.fill_path(&path, paint, FillRule::Winding, *transform, mask);
+                        }
+                    }
+                },
+            );
+        }
+        DrawCommand::StrokeText {
+            glyphs,
+            size,
+            paint,
+            stroke,
+            transform,
+            compositing,
+            ..
+        } => with_paint(
+            pixmap,
+            command,
+            paint,
+            *transform,
+            compositing,
+            clip,
+            |pixmap, paint, mask| {
+                let stroke = stroke.to_stroke();
+                for glyph in glyphs {
+                    if let Some(path) =
+                        fonts.glyph_path(&glyph.font, glyph.id, *size, glyph.x, glyph.y)
+                    {
+                        pixmap.stroke_path(&path, paint, &stroke, *transform, mask);
+                    }
+                }
+            },
+        ),
+    }
+}
+
+impl Backend for Pixmap {
+    fn draw(&mut self, command: &DrawCommand, fonts: &FontLibrary) {
+        let clip = clip_mask(self.width(), self.height(), command.clip());
+        draw_clipped(self, command, fonts, clip.as_ref());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::DrawContext;
+    use crate::paint::StyleValue;
+    use rquickjs::function::Opt;
+    use tiny_skia::{PathBuilder, Rect};
+
+    fn rect_path(x: f32, y: f32, width: f32, height: f32) -> ClipPath {
+        return ClipPath {
+            path: PathBuilder::from_rect(Rect::from_xywh(x, y, width, height).unwrap()),
+            fill_rule: FillRule::Winding,
+            transform: Transform::identity(),
+        };
+    }
+
+    fn surface() -> Pixmap {
+        let mut pixmap = Pixmap::new(20, 10).unwrap();
+        pixmap.fill(Color::WHITE);
+        return pixmap;
+    }
+
+    fn fill_rect(x: f32, y: f32, width: f32, height: f32, compositing: Compositing) -> DrawCommand {
+        return DrawCommand::FillRect {
+            rect: Rect::from_xywh(x, y, width, height).unwrap(),
+            paint: PaintStyle::Color(Color::BLACK),
+            transform: Transform::identity(),
+            clip: vec![],
+            compositing,
+        };
+    }
+
+    /// Shadow in red without offset
+    fn red_shadow(blur: f32) -> Shadow {
+        return Shadow {
+            color: Color::from_rgba8(255, 0, 0, 255),
+            blur,
+            offset_x: 0.0,
+            offset_y: 0.0,
+        };
+    }
+
+    #[test]
+    fn test_conic_pixmap() {
+        let mut gradient = Gradient {
+            shape: GradientShape::Conic {
+                angle: 0.0,
+                x: 10.0,
+                y: 10.0,
+            },
+            stops: vec![
+                ColorStop {
+                    offset: 0.0,
+                    color: Color::from_rgba8(255, 0, 0, 255),
+                },
+                ColorStop {
+                    offset: 1.0,
+                    color: Color::from_rgba8(0, 0, 255, 255),
+                },
+            ],
+        };
+        // Area around the center, which is at 20, 20 on the surface
+        let area = IntRect::from_xywh(10, 10, 20, 20).unwrap();
+        let transform = Transform::from_scale(2.0, 2.0);
+        let pixmap = conic_pixmap(&gradient, transform, area).unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (20, 20));
+        let blue = |pixmap: &Pixmap, x, y| pixmap.pixel(x, y).unwrap().blue();
+        // Clockwise from the right, a quarter turn below the center
+        assert!(blue(&pixmap, 19, 10) < 5);
+        assert!((55..70).contains(&blue(&pixmap, 10, 19)));
+        assert!((120..135).contains(&blue(&pixmap, 0, 10)));
+        gradient.shape = GradientShape::Conic {
+            angle: TAU / 4.0,
+            x: 10.0,
+            y: 10.0,
+        };
+        let pixmap = conic_pixmap(&gradient, transform, area).unwrap();
+        assert!(blue(&pixmap, 9, 19) < 5);
+        assert!(blue(&pixmap, 11, 19) > 240);
+        gradient.shape = GradientShape::Linear {
+            x0: 0.0,
+            y0: 0.0,
+            x1: 10.0,
+            y1: 0.0,
+        };
+        assert!(conic_pixmap(&gradient, transform, area).is_none());
+    }
+
+    #[test]
+    fn test_clip_mask() {
+        assert!(clip_mask(20, 10, &[]).is_none());
+        let mut ring = PathBuilder::new();
+        ring.push_rect(Rect::from_xywh(0.0, 0.0, 10.0, 10.0).unwrap());
+        ring.push_rect(Rect::from_xywh(2.0, 2.0, 4.0, 4.0).unwrap());
+        let ring = ClipPath {
+            path: ring.finish().unwrap(),
+            fill_rule: FillRule::EvenOdd,
+            transform: Transform::identity(),
+        };
+        let mask = clip_mask(20, 10, std::slice::from_ref(&ring)).unwrap();
+        let at = |mask: &Mask, x: usize, y: usize| mask.data()[x + y * 20];
+        assert_eq!(
+            [at(&mask, 1, 1), at(&mask, 8, 1), at(&mask, 4, 4)],
+            [255, 255, 0]
+        );
+        assert_eq!(at(&mask, 12, 4), 0);
+        // Second path intersects, at its own transform
+        let mut half = rect_path(0.0, 0.0, 5.0, 10.0);
+        half.transform = Transform::from_scale(0.5, 1.0);
+        let mask = clip_mask(20, 10, &[ring, half]).unwrap();
+        assert_eq!(
+            [at(&mask, 1, 1), at(&mask, 3, 1), at(&mask, 8, 1)],
+            [255, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_draw_compositing() {
+        let fonts = FontLibrary::new();
+        let mut pixmap = surface();
+        let half = Compositing {
+            alpha: 0.5,
+            ..Compositing::default()
+        };
+        draw_clipped(
+            &mut pixmap,
+            &fill_rect(0.0, 0.0, 10.0, 10.0, half),
+            &fonts,
+            None,
+        );
+        let erase = Compositing {
+            blend_mode: BlendMode::DestinationOut,
+            ..Compositing::default()
+        };
+        draw_clipped(
+            &mut pixmap,
+            &fill_rect(15.0, 0.0, 5.0, 10.0, erase),
+            &fonts,
+            None,
+        );
+        let pixel = |x, y| {
+            let p = pixmap.pixel(x, y).unwrap();
+            return (p.red(), p.alpha());
+        };
+        assert_eq!(pixel(5, 5), (127, 255));
+        assert_eq!(pixel(12, 5), (255, 255));
+        assert_eq!(pixel(17, 5), (0, 0));
+    }
+
+    #[test]
+    fn test_box_sizes() {
+        assert_eq!(box_sizes(0.0), [1, 1, 1]);
+        assert_eq!(box_sizes(1.0), [1, 1, 3]);
+        assert_eq!(box_sizes(2.0), [3, 3, 5]);
+        assert_eq!(box_sizes(10.0), [19, 19, 21]);
+        assert_eq!(blur_margin(2.0), 4);
+    }
+
+    #[test]
+    fn test_gaussian_blur() {
+        // Blurring a single point gives the kernel
+        let (size, sigma) = (41, 3.0);
+        let mut values = vec![0.0; size * size];
+        values[size / 2 * (size + 1)] = 1.0;
+        let blurred = gaussian_blur(&values, size, size, sigma);
+        let gaussian: Vec<f32> = (0..size)
+            .map(|i| {
+                let d = i as f32 - (size / 2) as f32;
+                (-d * d / (2.0 * sigma * sigma)).exp()
+            })
+            .collect();
+        let total: f32 = gaussian.iter().sum();
+        // Within 6% of the peak of the exact kernel
+        for y in 0..size {
+            for x in 0..size {
+                let expected = gaussian[x] * gaussian[y] / (total * total);
+                assert!((blurred[x + y * size] - expected).abs() < 1e-3);
+            }
+        }
+        assert!((blurred.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+        // Nothing is blurred in from outside
+        let edge = gaussian_blur(&vec![1.0; 100], 10, 10, 2.0);
+        assert!(edge[0] < edge[11] && edge[11] < edge[55]);
+        assert!((edge[55] - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_shadow_pixmap() {
+        let mut layer = Pixmap::new(20, 20).unwrap();
+        layer.fill_rect(
+            Rect::from_xywh(5.0, 5.0, 10.0, 10.0).unwrap(),
+            &Paint::default(),
+            Transform::identity(),
+            None,
+        );
+        let sharp = shadow_pixmap(&layer, &red_shadow(0.0));
+        let pixel = |pixmap: &Pixmap, x| {
+            let p = pixmap.pixel(x, 10).unwrap();
+            return (p.red(), p.green(), p.alpha());
+        };
+        assert_eq!(
+            [pixel(&sharp, 4), pixel(&sharp, 5)],
+            [(0, 0, 0), (255, 0, 255)]
+        );
+        // Shadow blur 4 is a Gaussian blur of standard deviation 2
+        let blurred = shadow_pixmap(&layer, &red_shadow(4.0));
+        assert!(pixel(&blurred, 0).2 < 10);
+        assert!((100..200).contains(&pixel(&blurred, 5).2));
+        assert!(pixel(&blurred, 10).2 > 245);
+        assert!(blurred
+            .pixels()
+            .iter()
+            .all(|p| p.green() == 0 && p.red() == p.alpha()));
+    }
+
+    #[test]
+    fn test_layer_rect() {
+        let pixmap = surface();
+        let mut compositing = Compositing::default();
+        let command = fill_rect(2.0, 2.0, 4.0, 4.0, Compositing::default());
+        // Bounds and a pixel for anti-aliasing
+        let rect = layer_rect(&pixmap, &command, &compositing);
+        assert_eq!(rect, IntRect::from_xywh(1, 1, 6, 6));
+        // Shadow blur 4 spreads 4 pixels
+        compositing.shadow = Some(red_shadow(4.0));
+        let rect = layer_rect(&pixmap, &command, &compositing);
+        assert_eq!(rect, IntRect::from_xywh(-3, -3, 14, 14));
+        // Commands off the surface are left out, unless their shadow reaches it
+        let outside = fill_rect(40.0, 2.0, 4.0, 4.0, Compositing::default());
+        assert_eq!(layer_rect(&pixmap, &outside, &compositing), None);
+        compositing.shadow.as_mut().unwrap().offset_x = -20.0;
+        let rect = layer_rect(&pixmap, &outside, &compositing);
+        assert_eq!(rect, IntRect::from_xywh(35, -3, 10, 14));
+    }
+
+    #[test]
+    fn test_apply_filter() {
+        let mut layer = Pixmap::new(20, 10).unwrap();
+        let mut paint = Paint::default();
+        paint.set_color_rgba8(255, 0, 0, 255);
+        let square = Rect::from_xywh(5.0, 2.0, 6.0, 6.0).unwrap();
+        layer.fill_rect(square, &paint, Transform::identity(), None);
+        let pixel = |layer: &Pixmap, x, y| {
+            let p = layer.pixel(x, y).unwrap();
+            return (p.red(), p.green(), p.alpha());
+        };
+        let mut gray = layer.clone();
+        apply_filter(&mut gray, &Filter::Grayscale(1.0));
+        apply_filter(&mut gray, &Filter::Opacity(0.5));
+        assert_eq!(
+            [pixel(&gray, 8, 5), pixel(&gray, 2, 5)],
+            [(27, 27, 128), (0, 0, 0)]
+        );
+        let mut blurred = layer.clone();
+        apply_filter(&mut blurred, &Filter::Blur(1.0));
+        assert!((1..128).contains(&pixel(&blurred, 4, 5).2));
+        assert!(pixel(&blurred, 8, 5).2 > 245);
+        let mut shadowed = layer.clone();
+        let shadow = Shadow {
+            offset_x: 6.0,
+            color: Color::BLACK,
+            ..red_shadow(0.0)
+        };
+        apply_filter(&mut shadowed, &Filter::DropShadow(shadow));
+        assert_eq!(pixel(&shadowed, 8, 5), (255, 0, 255));
+        assert_eq!(pixel(&shadowed, 14, 5), (0, 0, 255));
+        assert_eq!(pixel(&shadowed, 18, 5), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_draw_color_filter() {
+        let fonts = FontLibrary::new();
+        let red = |_: &PaintStyle| PaintStyle::Color(Color::from_rgba8(255, 0, 0, 255));
+        let compositing = Compositing {
+            filter: vec![Filter::Grayscale(1.0), Filter::Opacity(0.5)],
+            ..Compositing::default()
+        };
+        let mut filtered = surface();
+        let command = fill_rect(0.0, 0.0, 5.0, 10.0, compositing).map_paint(red);
+        draw_clipped(&mut filtered, &command, &fonts, None);
+        // Red is 21% gray, half transparent over white
+        let p = filtered.pixel(2, 5).unwrap();
+        assert_eq!((p.red(), p.green(), p.blue()), (154, 154, 154));
+        assert_eq!(filtered.pixel(6, 5).unwrap().red(), 255);
+        // Same as drawing in the filtered color
+        let mut painted = surface();
+        let gray = Color::from_rgba(0.2126, 0.2126, 0.2126, 0.5).unwrap();
+        let command = fill_rect(0.0, 0.0, 5.0, 10.0, Compositing::default());
+        let command = command.map_paint(|_| PaintStyle::Color(gray));
+        draw_clipped(&mut painted, &command, &fonts, None);
+        assert_eq!(filtered.data(), painted.data());
+    }
+
+    #[test]
+    fn test_draw_shadow() {
+        let fonts = FontLibrary::new();
+        let mut pixmap = surface();
+        let compositing = Compositing {
+            shadow: Some(Shadow {
+                offset_x: 5.0,
+                ..red_shadow(0.0)
+            }),
+            ..Compositing::default()
+        };
+        draw_clipped(
+            &mut pixmap,
+            &fill_rect(0.0, 0.0, 5.0, 5.0, compositing),
+            &fonts,
+            None,
+        );
+        let pixel = |x, y| {
+            let p = pixmap.pixel(x, y).unwrap();
+            return (p.red(), p.green());
+        };
+        assert_eq!(
+            [pixel(2, 2), pixel(7, 2), pixel(12, 2)],
+            [(0, 0), (255, 0), (255, 255)]
+        );
+        assert_eq!(pixel(7, 7), (255, 255));
+    }
+
+    #[test]
+    fn test_fill_glyph_translated() {
+        let context = || DrawContext::new(40, 30, 2.0, "#fff".to_string(), "#000".to_string());
+        let mut moved = context();
+        moved.set_font("20px Academico".to_string());
+        // Moves 20, 10 surface pixels, which is 10, 5 at zoom 2
+        moved.translate(-20.0, -10.0);
+        moved.fill_text("Hi".to_string(), 5.0, 20.0);
+        let mut placed = context();
+        placed.set_font("20px Academico".to_string());
+        placed.fill_text("Hi".to_string(), 15.0, 25.0);
+        let blank = context();
+        assert_ne!(placed.surface().data(), blank.surface().data());
+        assert_eq!(moved.surface().data(), placed.surface().data());
+    }
+
+    #[test]
+    fn test_stroke_text() {
+        let mut context = DrawContext::new(40, 30, 1.0, "#fff".to_string(), "#000".to_string());
+        context.set_font("40pt Bravura".to_string());
+        context.set_line_width(2.0);
+        context.set_stroke_style(StyleValue::Css("#00f".to_string()));
+        // Black notehead, only its outline is drawn
+        context.stroke_text("\u{e0a4}".to_string(), 5.0, 15.0);
+        let pixel = |x, y| {
+            let p = context.surface().pixel(x, y).unwrap();
+            return (p.red(), p.green(), p.blue());
+        };
+        assert_eq!(pixel(17, 15), (255, 255, 255));
+        assert!((0..40).any(|x| pixel(x, 15) == (0, 0, 255)));
+    }
+
+    #[test]
+    fn test_dashed_stroke() {
+        let mut context = DrawContext::new(20, 10, 1.0, "#fff".to_string(), "#000".to_string());
+        context.set_line_width(2.0);
+        context.set_line_dash(vec![4.0, 4.0]);
+        context.begin_path();
+        context.move_to(0.0, 5.0);
+        context.line_to(20.0, 5.0);
+        context.stroke(Opt(None));
+        let red = |x| context.surface().pixel(x, 4).unwrap().red();
+        assert_eq!(
+            [red(1), red(2), red(5), red(6), red(9), red(10)],
+            [0, 0, 255, 255, 0, 0]
+        );
+    }
+}
@@ -0,0 +1,51 @@
+// Render one user score script to an image.
+//
+// The Rust side sets up `renderOptions` as a global before evaluating this
+// script, and registers the user score as the module `@score`.
+//
+// Use dynamic import to be able to catch exceptions during import.
+try {
+    const { Canvas } = await import('@wrap');
+    let { VexFlow } = await import('@vexflow');
+
+    const { Factory, Renderer } = VexFlow;
+
+    class HeadlessFactory extends Factory {
+        constructor(options) {
+            const opts = options || {};
+            const width = opts.width || 500;
+            const height = opts.height || 200;
+            super({ renderer: { elementId: null, width, height } });
+            const zoom = opts.zoom || 1.0;
+            const background = opts.background || '#fff5f0ff';
+            const foreground = opts.foreground || '#111';
+            const canvas = new Canvas(width, height, zoom, background, foreground, /*forceForeground=*/false);
+            if (opts.trace) {
+                canvas.getContext().startTrace();
+            }
+            this.canvas = canvas;
+            const context = Renderer.buildContext(canvas, 1/*canvas backend*/, width, height, background);
+            this.context = context;
+        }
+    }
+
+    async function main() {
+        const vf = new HeadlessFactory(renderOptions);
+
+        // Score script does not have lexical scope so can't see the const vf, so expose it globally.
+        globalThis.VF = VexFlow;
+        globalThis.context = vf.context;
+        globalThis.vf = vf;
+        globalThis.factory = vf;
+        await import('@score');
+
+        // Rust side picks up the drawn image from here
+        globalThis.renderResult = vf.canvas.getContext();
+    }
+    await main();
+} catch(err) {
+    // Need to catch exceptions here, at Rust QuickJS scope we just see pending jobs and don't get the exceptions.
+    // Luckily they do have stack traces.
+    console.error(`Uncaught exception: ${err}\n${err.stack}`);
+    panic(`${err}`);
+}
@@ -0,0 +1,215 @@
+//
+// Rendering score scripts to images from Rust
+//
+
+use crate::context::DrawContext;
+use crate::display_list::DisplayList;
+use crate::easyscore::EasyScore;
+use crate::svg::SvgTextMode;
+use crate::trace::Trace;
+use crate::runtime::{create_runtime, eval_script, load_vexflow_bundle, register_globals, run_pending_jobs};
+use rquickjs::Class;
+use std::path::PathBuf;
+use tiny_skia::Pixmap;
+
+/// Things that can go wrong while rendering
+#[derive(Debug)]
+pub enum RenderError {
+    /// The VexFlow bundle at the path could not be read
+    Bundle(PathBuf, std::io::Error),
+    /// JavaScript threw an exception or called `panic()`
+    Script(String),
+}
+
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderError::Bundle(path, e) => {
+                write!(f, "Could not read VexFlow bundle '{}': {}", path.display(), e)
+            }
+            RenderError::Script(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+/// Settings for the image that scores are rendered to
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    /// VexFlow bundle to load, `None` means default location (or embedded bundle)
+    pub vexflow: Option<PathBuf>,
+    /// Width of the score in pixels (before zoom)
+    pub width: u32,
+    /// Height of the score in pixels (before zoom)
+    pub height: u32,
+    /// Zoom factor applied to the output image
+    pub zoom: f64,
+    /// Background color as CSS color string
+    pub background: String,
+    /// Foreground color as CSS color string
+    pub foreground: String,
+    /// How text is written in SVG output
+    pub svg_text: SvgTextMode,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            vexflow: None,
+            width: 800,
+            height: 600,
+            zoom: 2.0,
+            background: "#fff5f0ff".to_string(),
+            foreground: "#222".to_string(),
+            svg_text: SvgTextMode::Outlines,
+        }
+    }
+}
+
+/// Renders VexFlow score scripts to images.
+///
+/// Each render runs in a fresh JavaScript runtime, so scripts cannot affect
+/// each other. Example:
+///
+/// ```no_run
+/// use vexflowrust::{RenderOptions, Renderer};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let renderer = Renderer::new(RenderOptions::default())?;
+/// let pixmap = renderer.render_script(r#"
+///     const stave = new VF.Stave(10, 40, 400);
+///     stave.addClef("treble").setContext(context).draw();
+/// "#)?;
+/// pixmap.save_png("score.png")?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Renderer {
+    vexflow_bundle: Vec<u8>,
+    options: RenderOptions,
+}
+
+impl Renderer {
+    /// Create renderer, loading VexFlow bundle given in options.
+    pub fn new(options: RenderOptions) -> Result<Self, RenderError> {
+        let vexflow_bundle = load_vexflow_bundle(options.vexflow.as_deref())?;
+        return Ok(Self::with_bundle(vexflow_bundle, options));
+    }
+
+    /// Create renderer with VexFlow bundle source already loaded.
+    pub fn with_bundle(vexflow_bundle: Vec<u8>, options: RenderOptions) -> Self {
+        Renderer {
+            vexflow_bundle,
+            options,
+        }
+    }
+
+    /// Settings used for each render
+    pub fn options(&self) -> &RenderOptions {
+        return &self.options;
+    }
+
+    /// Run score script, then pull the result out of its drawing context.
+    ///
+    /// With trace, calls made on the drawing context are recorded.
+    fn run_script<T>(
+        &self,
+        script: &str,
+        trace: bool,
+        extract: impl FnOnce(&DrawContext) -> T,
+    ) -> Result<T, RenderError> {
+        let (runtime, ctx) = create_runtime(
+            &self.vexflow_bundle,
+            vec![("@score", script.as_bytes().to_vec())],
+        );
+        let panic_slot = ctx.with(|ctx| {
+            let panic_slot = register_globals(ctx.clone());
+            let options = rquickjs::Object::new(ctx.clone()).unwrap();
+            options.set("width", self.options.width).unwrap();
+            options.set("height", self.options.height).unwrap();
+            options.set("zoom", self.options.zoom).unwrap();
+            options.set("background", self.options.background.clone()).unwrap();
+            options.set("foreground", self.options.foreground.clone()).unwrap();
+            options.set("trace", trace).unwrap();
+            ctx.globals().set("renderOptions", options).unwrap();
+            eval_script(ctx, include_bytes!("./render.js")).map(|_| panic_slot)
+        })?;
+        run_pending_jobs(&runtime, &panic_slot)?;
+        return ctx.with(|ctx| {
+            let result: Class<DrawContext> = ctx
+                .globals()
+                .get("renderResult")
+                .map_err(|_| RenderError::Script("Score script did not produce an image".to_string()))?;
+            return Ok(extract(&result.borrow()));
+        });
+    }
+
+    /// Run score script and return the image it drew.
+    ///
+    /// The script is evaluated as a module with globals `VF` (the VexFlow
+    /// namespace), `vf` and `factory` (a Factory drawing to the image), and
+    /// `context` (the rendering context).
+    pub fn render_script(&self, script: &str) -> Result<Pixmap, RenderError> {
+        return self.run_script(script, false, |context| context.surface().clone());
+    }
+
+    /// Run score script and return every drawing operation it did.
+    ///
+    /// The display list can be turned into PNG, SVG and PDF output at any
+    /// zoom without running the script again.
+    pub fn render_script_display_list(&self, script: &str) -> Result<DisplayList, RenderError> {
+        return self.run_script(script, false, |context| context.display_list().clone());
+    }
+
+    /// Run score script and return every drawing operation, plus a trace of
+    /// every call it made on the drawing context.
+    ///
+    /// The trace can be replayed later without JavaScript, see Trace::replay().
+    pub fn render_script_traced(&self, script: &str) -> Result<(DisplayList, Trace), RenderError> {
+        return self.run_script(script, true, |context| {
+            let trace = context.trace().expect("Tracing was started").clone();
+            return (context.display_list().clone(), trace);
+        });
+    }
+
+    /// Run score script and return what it drew as SVG file contents.
+    pub fn render_script_svg(&self, script: &str) -> Result<String, RenderError> {
+        let display_list = self.render_script_display_list(script)?;
+        return Ok(display_list.to_svg(self.options.zoom, self.options.svg_text));
+    }
+
+    /// Run score script and return what it drew as PDF file contents.
+    ///
+    /// Fonts are embedded as subsets, so text stays selectable.
+    pub fn render_script_pdf(&self, script: &str) -> Result<Vec<u8>, RenderError> {
+        let display_list = self.render_script_display_list(script)?;
+        return Ok(display_list.to_pdf(self.options.zoom));
+    }
+
+    /// Render EasyScore voices as a system of staves and return the image.
+    pub fn render_easyscore(&self, score: &EasyScore) -> Result<Pixmap, RenderError> {
+        return self.render_script(&score.to_script(self.options.width));
+    }
+
+    /// Render EasyScore voices as a system of staves and return every drawing operation.
+    pub fn render_easyscore_display_list(&self, score: &EasyScore) -> Result<DisplayList, RenderError> {
+        return self.render_script_display_list(&score.to_script(self.options.width));
+    }
+
+    /// Render EasyScore voices as a system of staves and return every drawing
+    /// operation, plus a trace of calls made on the drawing context.
+    pub fn render_easyscore_traced(&self, score: &EasyScore) -> Result<(DisplayList, Trace), RenderError> {
+        return self.render_script_traced(&score.to_script(self.options.width));
+    }
+
+    /// Render EasyScore voices as a system of staves and return SVG file contents.
+    pub fn render_easyscore_svg(&self, score: &EasyScore) -> Result<String, RenderError> {
+        return self.render_script_svg(&score.to_script(self.options.width));
+    }
+
+    /// Render EasyScore voices as a system of staves and return PDF file contents.
+    pub fn render_easyscore_pdf(&self, score: &EasyScore) -> Result<Vec<u8>, RenderError> {
+        return self.render_script_pdf(&score.to_script(self.options.width));
+    }
+}
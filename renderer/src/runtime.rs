@@ -0,0 +1,204 @@
+//
+// QuickJS runtime setup shared by score rendering and the test suite
+//
+
+use crate::context::DrawContext;
+use crate::font::{FontMetrics, SpanFontParser};
+use crate::image::{Image, ImageData};
+use crate::paint::{CanvasGradient, CanvasPattern};
+use crate::path::Path2D;
+use crate::renderer::RenderError;
+use rquickjs::{
+    context::EvalOptions,
+    function::IntoJsFunc,
+    loader::{BuiltinLoader, BuiltinResolver},
+    Class, Context, Ctx, Error, Function, Runtime, Value,
+};
+use std::cell::{Cell, RefCell};
+use std::path::Path;
+use std::rc::Rc;
+
+thread_local! {
+    /// Whether printed output on this thread is collected into whole lines before writing
+    ///
+    /// Needed when several test jobs run at once so their lines do not interleave.
+    static LINE_BUFFERED_OUTPUT: Cell<bool> = Cell::new(false);
+    /// Partial line of printed output for this thread
+    static OUTPUT_LINE: RefCell<String> = RefCell::new(String::new());
+}
+
+/// Print to console
+pub fn print(msg: String) {
+    if !LINE_BUFFERED_OUTPUT.with(|buffered| buffered.get()) {
+        print!("{msg}");
+        return;
+    }
+    OUTPUT_LINE.with(|line| {
+        let mut line = line.borrow_mut();
+        line.push_str(&msg);
+        if let Some(end) = line.rfind('\n') {
+            let rest = line.split_off(end + 1);
+            // Single print!() call holds stdout lock for all complete lines
+            print!("{line}");
+            *line = rest;
+        }
+    });
+}
+
+/// Choose whether printed output on this thread is written in whole lines
+pub(crate) fn set_line_buffered_output(buffered: bool) {
+    LINE_BUFFERED_OUTPUT.with(|cell| cell.set(buffered));
+}
+
+/// Write out any partial line of output left for this thread
+pub(crate) fn flush_output() {
+    OUTPUT_LINE.with(|line| {
+        let mut line = line.borrow_mut();
+        if !line.is_empty() {
+            println!("{line}");
+            line.clear();
+        }
+    });
+}
+
+pub(crate) fn register_function<'js, F, P>(ctx: Ctx<'js>, name: &str, func: F)
+where
+    F: IntoJsFunc<'js, P> + 'js,
+{
+    let global = ctx.globals();
+    let name_string = String::from(name);
+    global
+        .set(
+            name_string.clone(),
+            Function::new(ctx.clone(), func)
+                .unwrap()
+                .with_name(name_string.clone())
+                .unwrap(),
+        )
+        .unwrap();
+}
+
+fn format_exception(v: Value) -> String {
+    if v.is_error() || v.is_exception() {
+        let ex = v.as_exception().expect("Value that had v.is_error() || v.is_exception() could not be converted with v.as_exception()");
+        return format!(
+            "Uncaught exception: {}\n{}",
+            ex.message().unwrap_or_else(|| "<no msg>".to_string()),
+            ex.stack().unwrap_or_else(|| "<no stack>".to_string())
+        );
+    }
+    if v.is_string() {
+        if let Some(s) = v.into_string() {
+            return s
+                .to_string()
+                .unwrap_or_else(|_| "<no string value>".to_string());
+        }
+        return "<unconvertable string>".to_string();
+    }
+    // Fallback to debugger output if we get something unknown, make sure to show something at least.
+    return format!("Uncaught exception: {:?}", v);
+}
+
+fn path_join(path: String, more: String) -> String {
+    return format!("{}", std::path::PathBuf::from(path).join(more).display());
+}
+
+/// Message from JavaScript `panic()`, if it has been called
+///
+/// If we call actual panic!() from a function called by JavaScript, it is
+/// within JavaScript context. The panic would be caught and turned into an
+/// exception somewhere internal. Instead record that we need to stop, check
+/// the value between jobs.
+pub(crate) type PanicSlot = Rc<RefCell<Option<String>>>;
+
+/// Define classes and functions that every script expects to find globally.
+pub(crate) fn register_globals(ctx: Ctx) -> PanicSlot {
+    let global = ctx.globals();
+    Class::<DrawContext>::define(&global).unwrap();
+    Class::<CanvasGradient>::define(&global).unwrap();
+    Class::<CanvasPattern>::define(&global).unwrap();
+    Class::<Image>::define(&global).unwrap();
+    Class::<ImageData>::define(&global).unwrap();
+    Class::<Path2D>::define(&global).unwrap();
+    Class::<FontMetrics>::define(&global).unwrap();
+    Class::<SpanFontParser>::define(&global).unwrap();
+    register_function(ctx.clone(), "print", print);
+    register_function(ctx.clone(), "path_join", path_join);
+    let panic_slot: PanicSlot = Rc::new(RefCell::new(None));
+    let slot = panic_slot.clone();
+    register_function(ctx.clone(), "panic", move |msg: String| {
+        *slot.borrow_mut() = Some(msg);
+    });
+    return panic_slot;
+}
+
+/// Evaluate top-level script, returning any exception that comes out of it.
+pub(crate) fn eval_script(ctx: Ctx, script: &[u8]) -> Result<(), RenderError> {
+    let mut options = EvalOptions::default();
+    options.global = false;
+    options.strict = true;
+    options.promise = true;
+    match ctx.eval_with_options::<(), _>(script, options) {
+        Err(Error::Exception) => Err(RenderError::Script(format_exception(ctx.catch()))),
+        Err(e) => Err(RenderError::Script(format!("Error! {:?}", e))),
+        Ok(_) => Ok(()),
+    }
+}
+
+/// Make sure to keep going until work is actually done
+pub(crate) fn run_pending_jobs(runtime: &Runtime, panic_slot: &PanicSlot) -> Result<(), RenderError> {
+    loop {
+        if let Some(msg) = panic_slot.borrow_mut().take() {
+            return Err(RenderError::Script(msg));
+        }
+        if !runtime.is_job_pending() {
+            return Ok(());
+        }
+        if let Err(e) = runtime.execute_pending_job() {
+            return Err(RenderError::Script(format!("Error! {:?}", e)));
+        }
+    }
+}
+
+/// Bundle location used when none is given, relative to repository root
+pub const DEFAULT_VEXFLOW_BUNDLE: &str = "build/vexflow-debug-with-tests.js";
+
+/// Read VexFlow bundle source.
+///
+/// With the `embedded-vexflow` feature, the bundle built into the binary is
+/// used unless a path is given explicitly.
+pub fn load_vexflow_bundle(path: Option<&Path>) -> Result<Vec<u8>, RenderError> {
+    #[cfg(feature = "embedded-vexflow")]
+    if path.is_none() {
+        return Ok(include_bytes!("../../build/vexflow-debug-with-tests.js").to_vec());
+    }
+    let path = path.unwrap_or(Path::new(DEFAULT_VEXFLOW_BUNDLE));
+    return std::fs::read(path).map_err(|e| RenderError::Bundle(path.to_path_buf(), e));
+}
+
+/// Create a fresh JS runtime that can import VexFlow and any extra modules given.
+///
+/// The bundle can be imported as either `@vexflow` or `@vexflow-debug-with-tests`.
+/// The second only re-exports the named exports of the first, so scripts
+/// importing both share one VexFlow instance.
+pub(crate) fn create_runtime(
+    vexflow_bundle: &[u8],
+    extra_modules: Vec<(&str, Vec<u8>)>,
+) -> (Runtime, Context) {
+    let runtime = Runtime::new().expect("Could not create JS Runtime");
+    let ctx = Context::full(&runtime).expect("Could not create JS Context");
+    let mut resolver = BuiltinResolver::default()
+        .with_module("@wrap")
+        .with_module("@vexflow")
+        .with_module("@vexflow-debug-with-tests");
+    let mut loader = BuiltinLoader::default()
+        .with_module("@wrap", include_bytes!("./wrap.js"))
+        .with_module("@vexflow", vexflow_bundle)
+        .with_module("@vexflow-debug-with-tests", "export * from '@vexflow';\n");
+    for (name, source) in extra_modules {
+        resolver.add_module(name);
+        loader.add_module(name, source);
+    }
+    runtime.set_loader(resolver, loader);
+    return (runtime, ctx);
+}
@@ -0,0 +1,211 @@
+//
+// Running the VexFlow test suite
+//
+// Tests are registered by the VexFlow bundle itself (the debug build with
+// tests). Each test renders to its own PNG file in `build/images/current/`.
+//
+
+use crate::renderer::RenderError;
+use crate::runtime::{
+    create_runtime, eval_script, flush_output, register_function, register_globals,
+    run_pending_jobs, set_line_buffered_output,
+};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Which tests to run and how
+#[derive(Clone, Debug)]
+pub struct SuiteOptions {
+    /// Only run tests whose `Module::Test name` matches this regex
+    pub filter: Option<String>,
+    /// Skip tests whose `Module::Test name` matches any of these regexes
+    pub exclude: Vec<String>,
+    /// List test names instead of running them
+    pub list: bool,
+    /// Number of worker threads to split the test modules between
+    pub jobs: usize,
+}
+
+impl Default for SuiteOptions {
+    fn default() -> Self {
+        SuiteOptions {
+            filter: None,
+            exclude: vec![],
+            list: false,
+            jobs: 1,
+        }
+    }
+}
+
+/// Things that can go wrong while running the test suite
+#[derive(Debug)]
+pub enum SuiteError {
+    /// A test name pattern was not a valid regular expression
+    Pattern(regex::Error),
+    /// A test job failed to load VexFlow or run its tests
+    Render(RenderError),
+}
+
+impl std::fmt::Display for SuiteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SuiteError::Pattern(e) => write!(f, "Invalid test pattern: {}", e),
+            SuiteError::Render(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SuiteError {}
+
+impl From<RenderError> for SuiteError {
+    fn from(e: RenderError) -> Self {
+        return SuiteError::Render(e);
+    }
+}
+
+/// Totals from a run of the test suite
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SuiteSummary {
+    /// Number of tests that passed (or were listed)
+    pub passed: usize,
+    /// Number of tests skipped by filter or exclude patterns
+    pub filtered: usize,
+}
+
+/// Which tests from the VexFlow suite should run
+struct TestSelection {
+    filter: Option<regex::Regex>,
+    exclude: Vec<regex::Regex>,
+}
+
+impl TestSelection {
+    /// Compile patterns from options, report bad regex syntax to user.
+    fn from_options(options: &SuiteOptions) -> Result<Self, SuiteError> {
+        let filter = options
+            .filter
+            .as_deref()
+            .map(regex::Regex::new)
+            .transpose()
+            .map_err(SuiteError::Pattern)?;
+        let exclude = options
+            .exclude
+            .iter()
+            .map(|pattern| regex::Regex::new(pattern))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(SuiteError::Pattern)?;
+        return Ok(TestSelection { filter, exclude });
+    }
+
+    /// Decide if test with full name like `Module::Test name` should run
+    fn is_selected(&self, name: &str) -> bool {
+        if let Some(filter) = &self.filter {
+            if !filter.is_match(name) {
+                return false;
+            }
+        }
+        return !self.exclude.iter().any(|pattern| pattern.is_match(name));
+    }
+}
+
+/// Run one shard of the test suite in its own JS runtime.
+fn run_test_job(
+    vexflow_bundle: &[u8],
+    selection: Arc<TestSelection>,
+    list: bool,
+    job: usize,
+    jobs: usize,
+) -> Result<SuiteSummary, RenderError> {
+    let (runtime, ctx) = create_runtime(vexflow_bundle, vec![]);
+    let summary = Rc::new(Cell::new(SuiteSummary::default()));
+    let panic_slot = ctx.with(|ctx| {
+        let panic_slot = register_globals(ctx.clone());
+        register_function(ctx.clone(), "is_test_selected", move |name: String| {
+            selection.is_selected(&name)
+        });
+        let reported = summary.clone();
+        register_function(
+            ctx.clone(),
+            "report_test_result",
+            move |passed: usize, filtered: usize| {
+                reported.set(SuiteSummary { passed, filtered });
+            },
+        );
+        let options = rquickjs::Object::new(ctx.clone()).unwrap();
+        options.set("list", list).unwrap();
+        options.set("jobs", jobs).unwrap();
+        options.set("job", job).unwrap();
+        ctx.globals().set("testOptions", options).unwrap();
+        eval_script(ctx, include_bytes!("./unittest.js")).map(|_| panic_slot)
+    })?;
+    run_pending_jobs(&runtime, &panic_slot)?;
+    return Ok(summary.get());
+}
+
+/// Run the test suite split between worker threads, then total up results.
+///
+/// Progress is printed as tests run. With more than one job, output from each
+/// job is written in whole lines so that jobs do not garble each other.
+pub fn run_suite(vexflow_bundle: &[u8], options: &SuiteOptions) -> Result<SuiteSummary, SuiteError> {
+    let selection = Arc::new(TestSelection::from_options(options)?);
+    let jobs = options.jobs.max(1);
+    let results: Vec<Result<SuiteSummary, RenderError>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..jobs)
+            .map(|job| {
+                let selection = selection.clone();
+                // QuickJS recursion can get deep, give workers same stack as main thread
+                std::thread::Builder::new()
+                    .name(format!("test-job-{}", job))
+                    .stack_size(8 * 1024 * 1024)
+                    .spawn_scoped(scope, move || {
+                        set_line_buffered_output(jobs > 1);
+                        let result = run_test_job(vexflow_bundle, selection, options.list, job, jobs);
+                        flush_output();
+                        result
+                    })
+                    .expect("Could not spawn test job thread")
+            })
+            .collect();
+        // Join every handle before deciding, so all output gets flushed
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(RenderError::Script("Test job panicked".to_string())))
+            })
+            .collect()
+    });
+    let mut total = SuiteSummary::default();
+    for result in results {
+        let summary = result?;
+        total.passed += summary.passed;
+        total.filtered += summary.filtered;
+    }
+    return Ok(total);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_test_selection() {
+        let selection = TestSelection::from_options(&SuiteOptions {
+            filter: Some("^Beam::".to_string()),
+            exclude: vec!["Complex".to_string()],
+            ..SuiteOptions::default()
+        })
+        .unwrap();
+        assert!(selection.is_selected("Beam::Simple Beam"));
+        assert!(!selection.is_selected("Beam::Complex Beams with Annotations"));
+        assert!(!selection.is_selected("Stave::Simple Beam"));
+        let everything = TestSelection::from_options(&SuiteOptions::default()).unwrap();
+        assert!(everything.is_selected("Stave::Vertical Bar Test"));
+        assert!(TestSelection::from_options(&SuiteOptions {
+            filter: Some("(".to_string()),
+            ..SuiteOptions::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,1061 @@
+//
+// SVG output for drawing operations
+//
+// Shapes are written as <path> elements with the drawing transform attached,
+// so output stays resolution independent. Coordinates of the document match
+// pixels of the PNG output (zoom included). Clipped elements are put in
+// nested groups, one for each clip path. Global alpha and blend modes also
+// become groups, with the opacity and mix-blend-mode of one drawing operation.
+// Filters and shadows become an SVG filter on the same group.
+//
+
+use crate::display_list::{
+    blend_mode_name, line_cap_name, line_join_name, text_bounds, Backend, ClipPath, Compositing,
+    DrawCommand, StrokeStyle, TextGlyph,
+};
+use crate::filter::Filter;
+use crate::font::FontLibrary;
+use crate::image::png_data_url;
+use crate::paint::{Gradient, GradientShape, PaintStyle, Pattern, Repetition};
+use tiny_skia::{
+    BlendMode, Color, FillRule, LineJoin, Path, PathBuilder, PathSegment, PathStroker, Rect,
+    Transform,
+};
+
+/// How text is written to SVG
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SvgTextMode {
+    /// Glyph outlines as paths, looks the same everywhere
+    #[default]
+    Outlines,
+    /// <text> elements naming the font, keeps text selectable
+    Text,
+}
+
+/// An SVG document being built up from drawing operations
+#[derive(Clone, Debug)]
+pub struct SvgDocument {
+    width: u32,
+    height: u32,
+    text_mode: SvgTextMode,
+    /// Elements drawn so far, in order
+    body: String,
+    /// Gradients and clip paths referred to by elements
+    defs: String,
+    /// Number of ids given to definitions so far
+    ids: usize,
+    /// Gradients already defined, with their ids
+    gradients: Vec<(Gradient, String)>,
+    /// Patterns already defined, with their ids
+    patterns: Vec<(Pattern, String)>,
+    /// Clip paths already defined, with their ids
+    clips: Vec<(ClipPath, String)>,
+    /// Filters already defined, with their ids
+    filters: Vec<(Vec<Filter>, String)>,
+    /// Clip paths of groups left open at end of body, outermost first
+    clip: Vec<ClipPath>,
+}
+
+/// Number of wedges used to draw a conic gradient, SVG does not have them
+const CONIC_WEDGES: usize = 180;
+
+/// Format number compactly with limited precision
+fn num(v: f32) -> String {
+    let s = format!("{:.3}", v);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" {
+        return "0".to_string();
+    }
+    return s.to_string();
+}
+
+/// Convert path into SVG path data
+pub fn path_data(path: &Path) -> String {
+    let mut parts: Vec<String> = vec![];
+    for segment in path.segments() {
+        parts.push(match segment {
+            PathSegment::MoveTo(p) => format!("M{} {}", num(p.x), num(p.y)),
+            PathSegment::LineTo(p) => format!("L{} {}", num(p.x), num(p.y)),
+            PathSegment::QuadTo(p1, p) => {
+                format!("Q{} {} {} {}", num(p1.x), num(p1.y), num(p.x), num(p.y))
+            }
+            PathSegment::CubicTo(p1, p2, p) => format!(
+                "C{} {} {} {} {} {}",
+                num(p1.x),
+                num(p1.y),
+                num(p2.x),
+                num(p2.y),
+                num(p.x),
+                num(p.y)
+            ),
+            PathSegment::Close => "Z".to_string(),
+        });
+    }
+    return parts.join(" ");
+}
+
+/// Stroke width, cap, join and dash attributes
+fn stroke_attributes(stroke: &StrokeStyle) -> String {
+    let mut attributes = format!(
+        r#"stroke-width="{}" stroke-linecap="{}""#,
+        num(stroke.width),
+        line_cap_name(stroke.line_cap)
+    );
+    // SVG defaults to miter joins like Canvas, but with miter limit 4
+    if stroke.line_join == LineJoin::Miter || stroke.line_join == LineJoin::MiterClip {
+        attributes.push_str(&format!(
+            r#" stroke-miterlimit="{}""#,
+            num(stroke.miter_limit)
+        ));
+    } else {
+        attributes.push_str(&format!(
+            r#" stroke-linejoin="{}""#,
+            line_join_name(stroke.line_join)
+        ));
+    }
+    if stroke.is_dashed() {
+        let dash: Vec<String> = stroke.dash.iter().map(|d| num(*d)).collect();
+        attributes.push_str(&format!(r#" stroke-dasharray="{}""#, dash.join(" ")));
+        if stroke.dash_offset != 0.0 {
+            attributes.push_str(&format!(
+                r#" stroke-dashoffset="{}""#,
+                num(stroke.dash_offset)
+            ));
+        }
+    }
+    return attributes;
+}
+
+/// Color as `#rrggbb`, without alpha
+fn rgb(c: &Color) -> String {
+    return format!(
+        "#{:02x}{:02x}{:02x}",
+        (c.red() * 255.0).round() as u8,
+        (c.green() * 255.0).round() as u8,
+        (c.blue() * 255.0).round() as u8
+    );
+}
+
+/// Color as `#rrggbb` plus separate opacity
+fn color_attributes(c: &Color, kind: &str) -> String {
+    let rgb = rgb(c);
+    if c.alpha() >= 1.0 {
+        return format!(r#"{}="{}""#, kind, rgb);
+    }
+    return format!(r#"{}="{}" {}-opacity="{}""#, kind, rgb, kind, num(c.alpha()));
+}
+
+/// Transform attribute, empty for identity
+fn transform_attribute(t: &Transform) -> String {
+    return matrix_attribute("transform", t);
+}
+
+/// Attribute with matrix value, empty for identity
+fn matrix_attribute(name: &str, t: &Transform) -> String {
+    if t.is_identity() {
+        return "".to_string();
+    }
+    // SVG matrix(a b c d e f) maps x' = a x + c y + e, y' = b x + d y + f
+    return format!(
+        r#" {}="matrix({} {} {} {} {} {})""#,
+        name,
+        num(t.sx),
+        num(t.ky),
+        num(t.kx),
+        num(t.sy),
+        num(t.tx),
+        num(t.ty)
+    );
+}
+
+/// Attributes of group for compositing, empty if there is nothing to set
+///
+/// CSS only has the blend modes that mix colors, others are drawn normally.
+fn compositing_attributes(compositing: &Compositing) -> String {
+    let mut attributes = String::new();
+    if compositing.alpha < 1.0 {
+        attributes.push_str(&format!(r#" opacity="{}""#, num(compositing.alpha)));
+    }
+    match compositing.blend_mode {
+        BlendMode::Multiply
+        | BlendMode::Screen
+        | BlendMode::Overlay
+        | BlendMode::Darken
+        | BlendMode::Lighten
+        | BlendMode::ColorDodge
+        | BlendMode::ColorBurn
+        | BlendMode::HardLight
+        | BlendMode::SoftLight
+        | BlendMode::Difference
+        | BlendMode::Exclusion
+        | BlendMode::Hue
+        | BlendMode::Saturation
+        | BlendMode::Color
+        | BlendMode::Luminosity => {
+            attributes.push_str(&format!(
+                r#" style="mix-blend-mode:{}""#,
+                blend_mode_name(compositing.blend_mode)
+            ));
+        }
+        _ => {}
+    }
+    return attributes;
+}
+
+/// Filter primitive doing the same as a CSS filter function
+fn filter_primitive(filter: &Filter) -> String {
+    // Same transfer function for red, green and blue
+    let transfer = |function: String| {
+        format!(
+            "<feComponentTransfer><feFuncR {f}/><feFuncG {f}/><feFuncB {f}/></feComponentTransfer>\n",
+            f = function
+        )
+    };
+    return match filter {
+        Filter::Blur(deviation) => {
+            format!("<feGaussianBlur stdDeviation=\"{}\"/>\n", num(*deviation))
+        }
+        Filter::Grayscale(amount) => format!(
+            "<feColorMatrix type=\"saturate\" values=\"{}\"/>\n",
+            num(1.0 - amount)
+        ),
+        Filter::Opacity(amount) => format!(
+            "<feComponentTransfer><feFuncA type=\"linear\" slope=\"{}\"/></feComponentTransfer>\n",
+            num(*amount)
+        ),
+        Filter::Brightness(amount) => {
+            transfer(format!(r#"type="linear" slope="{}""#, num(*amount)))
+        }
+        Filter::Invert(amount) => transfer(format!(
+            r#"type="table" tableValues="{} {}""#,
+            num(*amount),
+            num(1.0 - amount)
+        )),
+        Filter::DropShadow(shadow) => {
+            let opacity = if shadow.color.alpha() < 1.0 {
+                format!(r#" flood-opacity="{}""#, num(shadow.color.alpha()))
+            } else {
+                "".to_string()
+            };
+            format!(
+                "<feDropShadow dx=\"{}\" dy=\"{}\" stdDeviation=\"{}\" flood-color=\"{}\"{}/>\n",
+                num(shadow.offset_x),
+                num(shadow.offset_y),
+                num(shadow.blur / 2.0),
+                rgb(&shadow.color),
+                opacity
+            )
+        }
+    };
+}
+
+/// Escape text for use in XML content and attributes
+fn escape_xml(text: &str) -> String {
+    let mut result = String::new();
+    for ch in text.chars() {
+        match ch {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            c => result.push(c),
+        }
+    }
+    return result;
+}
+
+impl SvgDocument {
+    /// Create empty document filled with background color.
+    pub fn new(width: u32, height: u32, background: Color, text_mode: SvgTextMode) -> Self {
+        let mut document = SvgDocument {
+            width,
+            height,
+            text_mode,
+            body: String::new(),
+            defs: String::new(),
+            ids: 0,
+            gradients: vec![],
+            patterns: vec![],
+            clips: vec![],
+            filters: vec![],
+            clip: vec![],
+        };
+        document.clear(background);
+        return document;
+    }
+
+    /// Throw away everything drawn, fill with color.
+    pub fn clear(&mut self, color: Color) {
+        self.body.clear();
+        self.defs.clear();
+        self.gradients.clear();
+        self.patterns.clear();
+        self.clips.clear();
+        self.filters.clear();
+        self.clip.clear();
+        if color.alpha() > 0.0 {
+            self.body.push_str(&format!(
+                "<rect width=\"{}\" height=\"{}\" {}/>\n",
+                self.width,
+                self.height,
+                color_attributes(&color, "fill")
+            ));
+        }
+    }
+
+    /// New id for a definition, starting with prefix
+    fn new_id(&mut self, prefix: &str) -> String {
+        self.ids += 1;
+        return format!("{}{}", prefix, self.ids);
+    }
+
+    /// Write gradient definition if not already written and return its id.
+    fn define_gradient(&mut self, gradient: &Gradient) -> String {
+        if let Some((_, id)) = self.gradients.iter().find(|(g, _)| g == gradient) {
+            return id.clone();
+        }
+        let id = self.new_id("g");
+        self.gradients.push((gradient.clone(), id.clone()));
+        let (element, geometry, stops) = match gradient.shape {
+            GradientShape::Linear { x0, y0, x1, y1 } => (
+                "linearGradient",
+                format!(
+                    r#"x1="{}" y1="{}" x2="{}" y2="{}""#,
+                    num(x0),
+                    num(y0),
+                    num(x1),
+                    num(y1)
+                ),
+                gradient.stops.clone(),
+            ),
+            // Focal form avoids fr, which SVG 1.1 viewers do not know
+            _ => {
+                let (focal, center, radius, stops) = gradient
+                    .focal_radial()
+                    .expect("Only linear and radial gradients are defined");
+                (
+                    "radialGradient",
+                    format!(
+                        r#"cx="{}" cy="{}" r="{}" fx="{}" fy="{}""#,
+                        num(center.x),
+                        num(center.y),
+                        num(radius),
+                        num(focal.x),
+                        num(focal.y)
+                    ),
+                    stops,
+                )
+            }
+        };
+        self.defs.push_str(&format!(
+            "<{} id=\"{}\" gradientUnits=\"userSpaceOnUse\" {}>\n",
+            element, id, geometry
+        ));
+        for stop in stops {
+            let opacity = if stop.color.alpha() < 1.0 {
+                format!(r#" stop-opacity="{}""#, num(stop.color.alpha()))
+            } else {
+                "".to_string()
+            };
+            self.defs.push_str(&format!(
+                "<stop offset=\"{}\" stop-color=\"{}\"{}/>\n",
+                num(stop.offset),
+                rgb(&stop.color),
+                opacity
+            ));
+        }
+        self.defs.push_str(&format!("</{}>\n", element));
+        return id;
+    }
+
+    /// Write pattern definition if not already written and return its id.
+    fn define_pattern(&mut self, pattern: &Pattern) -> String {
+        // Definition is the same however the pattern repeats
+        let pattern = &Pattern {
+            repetition: Repetition::Repeat,
+            ..pattern.clone()
+        };
+        if let Some((_, id)) = self.patterns.iter().find(|(p, _)| p == pattern) {
+            return id.clone();
+        }
+        let id = self.new_id("p");
+        self.patterns.push((pattern.clone(), id.clone()));
+        self.defs.push_str(&format!(
+            "<pattern id=\"{}\" patternUnits=\"userSpaceOnUse\" width=\"{}\" height=\"{}\"{}>\n",
+            id,
+            num(pattern.width),
+            num(pattern.height),
+            matrix_attribute("patternTransform", &pattern.transform)
+        ));
+        self.defs.push_str(&format!(
+            "<image width=\"{}\" height=\"{}\" preserveAspectRatio=\"none\" href=\"{}\"/>\n</pattern>\n",
+            num(pattern.width),
+            num(pattern.height),
+            png_data_url(&pattern.image)
+        ));
+        return id;
+    }
+
+    /// Write clip path definition if not already written and return its id.
+    fn define_clip(&mut self, clip: &ClipPath) -> String {
+        if let Some((_, id)) = self.clips.iter().find(|(c, _)| c == clip) {
+            return id.clone();
+        }
+        let id = self.new_id("c");
+        self.clips.push((clip.clone(), id.clone()));
+        let rule = match clip.fill_rule {
+            FillRule::Winding => "",
+            FillRule::EvenOdd => r#" clip-rule="evenodd""#,
+        };
+        self.defs.push_str(&format!(
+            "<clipPath id=\"{}\"><path d=\"{}\"{}{}/></clipPath>\n",
+            id,
+            path_data(&clip.path),
+            rule,
+            transform_attribute(&clip.transform)
+        ));
+        return id;
+    }
+
+    /// Write filter definition if not already written and return its id.
+    ///
+    /// The filter region is the whole document, in its coordinates.
+    fn define_filter(&mut self, filter: &[Filter]) -> String {
+        if let Some((_, id)) = self.filters.iter().find(|(f, _)| f == filter) {
+            return id.clone();
+        }
+        let id = self.new_id("f");
+        self.filters.push((filter.to_vec(), id.clone()));
+        self.defs.push_str(&format!(
+            "<filter id=\"{}\" filterUnits=\"userSpaceOnUse\" x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" color-interpolation-filters=\"sRGB\">\n",
+            id, self.width, self.height
+        ));
+        for f in filter {
+            self.defs.push_str(&filter_primitive(f));
+        }
+        self.defs.push_str("</filter>\n");
+        return id;
+    }
+
+    /// Make elements added next clipped to all paths in clip.
+    ///
+    /// Groups that consecutive elements share stay open, so a clip set once
+    /// for many elements gives one group.
+    fn set_clip(&mut self, clip: &[ClipPath]) {
+        let kept = self
+            .clip
+            .iter()
+            .zip(clip)
+            .take_while(|(open, new)| open == new)
+            .count();
+        for _ in kept..self.clip.len() {
+            self.body.push_str("</g>\n");
+        }
+        self.clip.truncate(kept);
+        for path in &clip[kept..] {
+            let id = self.define_clip(path);
+            self.body
+                .push_str(&format!("<g clip-path=\"url(#{})\">\n", id));
+            self.clip.push(path.clone());
+        }
+    }
+
+    /// Add element drawn with paint, made by element from its transform attribute.
+    ///
+    /// SVG patterns always repeat both ways, so patterns that do not are
+    /// clipped to the region they cover. That needs a group to carry the
+    /// transform, with the element inside it.
+    fn push_painted(
+        &mut self,
+        paint: &PaintStyle,
+        bounds: Rect,
+        transform: Transform,
+        element: impl FnOnce(&str) -> String,
+    ) {
+        let coverage = match paint {
+            PaintStyle::Pattern(pattern) => pattern.coverage(bounds, Transform::identity()),
+            _ => None,
+        };
+        let Some(coverage) = coverage else {
+            self.body.push_str(&element(&transform_attribute(&transform)));
+            return;
+        };
+        let id = self.new_id("c");
+        self.defs.push_str(&format!(
+            "<clipPath id=\"{}\"><path d=\"{}\"/></clipPath>\n",
+            id,
+            path_data(&coverage)
+        ));
+        self.body.push_str(&format!(
+            "<g clip-path=\"url(#{})\"{}>\n{}</g>\n",
+            id,
+            transform_attribute(&transform),
+            element("")
+        ));
+    }
+
+    /// Attributes to fill or stroke with paint, adding gradient or pattern definition if needed.
+    ///
+    /// Conic gradients must be drawn with conic_fill() instead.
+    fn paint_attributes(&mut self, paint: &PaintStyle, kind: &str) -> String {
+        return match paint {
+            PaintStyle::Color(color) => color_attributes(color, kind),
+            PaintStyle::Gradient(gradient) if gradient.is_empty() => format!(r#"{}="none""#, kind),
+            PaintStyle::Gradient(gradient) => {
+                format!(r#"{}="url(#{})""#, kind, self.define_gradient(gradient))
+            }
+            PaintStyle::Pattern(pattern) => {
+                format!(r#"{}="url(#{})""#, kind, self.define_pattern(pattern))
+            }
+        };
+    }
+
+    /// Fill path with wedges of conic gradient, clipped to the path.
+    fn conic_fill(
+        &mut self,
+        path: &Path,
+        gradient: &Gradient,
+        fill_rule: FillRule,
+        transform: Transform,
+    ) {
+        if gradient.is_empty() {
+            return;
+        }
+        let id = self.new_id("c");
+        let rule = match fill_rule {
+            FillRule::Winding => "",
+            FillRule::EvenOdd => r#" clip-rule="evenodd""#,
+        };
+        self.defs.push_str(&format!(
+            "<clipPath id=\"{}\"><path d=\"{}\"{}/></clipPath>\n",
+            id,
+            path_data(path),
+            rule
+        ));
+        self.body.push_str(&format!(
+            "<g clip-path=\"url(#{})\"{}>\n",
+            id,
+            transform_attribute(&transform)
+        ));
+        for (wedge, color) in gradient.conic_wedges(path.bounds(), CONIC_WEDGES) {
+            self.body.push_str(&format!(
+                "<path d=\"{}\" {}/>\n",
+                path_data(&wedge),
+                color_attributes(&color, "fill")
+            ));
+        }
+        self.body.push_str("</g>\n");
+    }
+
+    pub fn fill_path(
+        &mut self,
+        path: &Path,
+        paint: &PaintStyle,
+        fill_rule: FillRule,
+        transform: Transform,
+    ) {
+        if let Some(gradient) = paint.conic() {
+            self.conic_fill(path, gradient, fill_rule, transform);
+            return;
+        }
+        let rule = match fill_rule {
+            FillRule::Winding => "",
+            FillRule::EvenOdd => r#" fill-rule="evenodd""#,
+        };
+        let fill = self.paint_attributes(paint, "fill");
+        self.push_painted(paint, path.bounds(), transform, |transform| {
+            format!(
+                "<path d=\"{}\" {}{}{}/>\n",
+                path_data(path),
+                fill,
+                rule,
+                transform
+            )
+        });
+    }
+
+    pub fn stroke_path(
+        &mut self,
+        path: &Path,
+        paint: &PaintStyle,
+        stroke: &StrokeStyle,
+        transform: Transform,
+    ) {
+        if let Some(gradient) = paint.conic() {
+            // Fill outline of stroke instead
+            let resolution = PathStroker::compute_resolution_scale(&transform);
+            if let Some(outline) = path.stroke(&stroke.to_stroke(), resolution) {
+                self.conic_fill(&outline, gradient, FillRule::Winding, transform);
+            }
+            return;
+        }
+        let color = self.paint_attributes(paint, "stroke");
+        // Bounds only matter for patterns, which must cover the whole stroke
+        let bounds = match paint {
+            PaintStyle::Pattern(_) => path
+                .stroke(&stroke.to_stroke(), 1.0)
+                .map_or(path.bounds(), |outline| outline.bounds()),
+            _ => path.bounds(),
+        };
+        self.push_painted(paint, bounds, transform, |transform| {
+            format!(
+                "<path d=\"{}\" fill=\"none\" {} {}{}/>\n",
+                path_data(path),
+                color,
+                stroke_attributes(stroke),
+                transform
+            )
+        });
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, paint: &PaintStyle, transform: Transform) {
+        self.fill_path(
+            &PathBuilder::from_rect(rect),
+            paint,
+            FillRule::Winding,
+            transform,
+        );
+    }
+
+    /// Write run of glyphs as a <text> element.
+    ///
+    /// Each glyph is positioned individually so that spacing matches the
+    /// renderer's own font metrics.
+    pub fn fill_text(
+        &mut self,
+        glyphs: &[TextGlyph],
+        family: &str,
+        size: f32,
+        italic: bool,
+        bold: bool,
+        paint: &PaintStyle,
+        transform: Transform,
+    ) {
+        let Some(bounds) = text_bounds(glyphs, size) else {
+            return;
+        };
+        let fill = self.paint_attributes(paint, "fill");
+        let text: String = glyphs.iter().map(|g| g.ch).collect();
+        let xs: Vec<String> = glyphs.iter().map(|g| num(g.x)).collect();
+        let ys: Vec<String> = glyphs.iter().map(|g| num(g.y)).collect();
+        let style = if italic { r#" font-style="italic""# } else { "" };
+        let weight = if bold { r#" font-weight="bold""# } else { "" };
+        self.push_painted(paint, bounds, transform, |transform| {
+            format!(
+                "<text x=\"{}\" y=\"{}\" font-family=\"{}\" font-size=\"{}\"{}{} {} xml:space=\"preserve\"{}>{}</text>\n",
+                xs.join(" "),
+                ys.join(" "),
+                escape_xml(family),
+                num(size),
+                style,
+                weight,
+                fill,
+                transform,
+                escape_xml(&text)
+            )
+        });
+    }
+
+    /// Complete SVG file contents
+    pub fn finish(&self) -> String {
+        let defs = if self.defs.is_empty() {
+            "".to_string()
+        } else {
+            format!("<defs>\n{}</defs>\n", self.defs)
+        };
+        return format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n{}{}{}</svg>\n",
+            self.width,
+            self.height,
+            self.width,
+            self.height,
+            defs,
+            self.body,
+            "</g>\n".repeat(self.clip.len())
+        );
+    }
+
+    /// Add elements for command, ignoring its clip and compositing
+    fn draw_command(&mut self, command: &DrawCommand, fonts: &FontLibrary) {
+        match command {
+            DrawCommand::Clear { color } => self.clear(*color),
+            // SVG cannot erase, best we can do is paint over with clear color
+            DrawCommand::ClearRect {
+                rect,
+                color,
+                transform,
+                ..
+            } => self.fill_rect(*rect, &PaintStyle::Color(*color), *transform),
+            DrawCommand::FillRect {
+                rect,
+                paint,
+                transform,
+                ..
+            } => self.fill_rect(*rect, paint, *transform),
+            DrawCommand::FillPath {
+                path,
+                paint,
+                fill_rule,
+                transform,
+                ..
+            } => self.fill_path(path, paint, *fill_rule, *transform),
+            DrawCommand::StrokePath {
+                path,
+                paint,
+                stroke,
+                transform,
+                ..
+            } => self.stroke_path(path, paint, stroke, *transform),
+            DrawCommand::FillText {
+                glyphs,
+                size,
+                paint,
+                transform,
+                ..
+            } => {
+                // Conic gradients need outlines to clip wedges with
+                if self.text_mode == SvgTextMode::Text && paint.conic().is_none() {
+                    // Group consecutive glyphs drawn from the same font into one element
+                    for run in glyphs.chunk_by(|a, b| a.font == b.font) {
+                        let (family, italic, bold) = FontLibrary::font_style(&run[0].font);
+                        self.fill_text(run, family, *size, italic, bold, paint, *transform);
+                    }
+                    return;
+                }
+                for glyph in glyphs {
+                    if let Some(path) =
+                        fonts.glyph_path(&glyph.font, glyph.id, *size, glyph.x, glyph.y)
+                    {
+                        self.fill_path(&path, paint, FillRule::Winding, *transform);
+                    }
+                }
+            }
+            // Always outlines, so dashes follow the glyph shapes like in Canvas
+            DrawCommand::StrokeText {
+                glyphs,
+                size,
+                paint,
+                stroke,
+                transform,
+                ..
+            } => {
+                for glyph in glyphs {
+                    if let Some(path) =
+                        fonts.glyph_path(&glyph.font, glyph.id, *size, glyph.x, glyph.y)
+                    {
+                        self.stroke_path(&path, paint, stroke, *transform);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Backend for SvgDocument {
+    fn draw(&mut self, command: &DrawCommand, fonts: &FontLibrary) {
+        self.set_clip(command.clip());
+        let compositing = command.compositing();
+        let mut group = compositing_attributes(&compositing);
+        // Shadow is made from the filtered drawing, like a drop shadow filter at the end
+        let mut filter = compositing.filter.clone();
+        filter.extend(compositing.shadow.map(Filter::DropShadow));
+        if !filter.is_empty() {
+            let id = self.define_filter(&filter);
+            group.push_str(&format!(r#" filter="url(#{})""#, id));
+        }
+        if group.is_empty() {
+            self.draw_command(command, fonts);
+            return;
+        }
+        self.body.push_str(&format!("<g{}>\n", group));
+        self.draw_command(command, fonts);
+        self.body.push_str("</g>\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_list::Shadow;
+    use tiny_skia::LineCap;
+
+    fn glyph(ch: char, x: f32) -> TextGlyph {
+        return TextGlyph {
+            ch,
+            font: "Academico-Italic".to_string(),
+            id: 0,
+            advance: 0.0,
+            x,
+            y: 2.0,
+        };
+    }
+
+    #[test]
+    fn test_path_data() {
+        let mut pb = PathBuilder::new();
+        pb.move_to(0.0, 0.0);
+        pb.line_to(10.5, 0.25);
+        pb.quad_to(1.0, 2.0, 3.0, 4.0);
+        pb.cubic_to(1.0, 2.0, 3.0, 4.0, 5.0, 6.0);
+        pb.close();
+        assert_eq!(
+            path_data(&pb.finish().unwrap()),
+            "M0 0 L10.5 0.25 Q1 2 3 4 C1 2 3 4 5 6 Z"
+        );
+    }
+
+    #[test]
+    fn test_document() {
+        let mut doc = SvgDocument::new(
+            20,
+            10,
+            Color::from_rgba8(255, 255, 255, 255),
+            SvgTextMode::Text,
+        );
+        doc.fill_rect(
+            Rect::from_xywh(1.0, 2.0, 3.0, 4.0).unwrap(),
+            &PaintStyle::Color(Color::from_rgba8(255, 0, 0, 128)),
+            Transform::from_scale(2.0, 2.0),
+        );
+        doc.fill_text(
+            &[glyph('<', 1.0), glyph('a', 3.5)],
+            "Academico",
+            10.0,
+            true,
+            false,
+            &PaintStyle::Color(Color::BLACK),
+            Transform::identity(),
+        );
+        let svg = doc.finish();
+        assert!(svg.contains(r#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10" viewBox="0 0 20 10">"#));
+        assert!(svg.contains(r##"<rect width="20" height="10" fill="#ffffff"/>"##));
+        assert!(svg.contains(r##"<path d="M1 2 L4 2 L4 6 L1 6 Z" fill="#ff0000" fill-opacity="0.502" transform="matrix(2 0 0 2 0 0)"/>"##));
+        assert!(svg.contains(r##"<text x="1 3.5" y="2 2" font-family="Academico" font-size="10" font-style="italic" fill="#000000" xml:space="preserve">&lt;a</text>"##));
+        doc.clear(Color::TRANSPARENT);
+        assert!(!doc.finish().contains("<rect"));
+    }
+
+    #[test]
+    fn test_gradients() {
+        let mut doc = SvgDocument::new(20, 10, Color::TRANSPARENT, SvgTextMode::Text);
+        let mut gradient = Gradient::new(GradientShape::Linear {
+            x0: 0.0,
+            y0: 0.0,
+            x1: 10.0,
+            y1: 0.0,
+        });
+        gradient.add_color_stop(0.0, Color::from_rgba8(255, 0, 0, 128));
+        gradient.add_color_stop(1.0, Color::BLACK);
+        let paint = PaintStyle::Gradient(gradient);
+        let rect = Rect::from_xywh(1.0, 2.0, 3.0, 4.0).unwrap();
+        doc.fill_rect(rect, &paint, Transform::identity());
+        doc.fill_rect(rect, &paint, Transform::identity());
+        let svg = doc.finish();
+        assert!(svg.contains(r##"<defs>
+<linearGradient id="g1" gradientUnits="userSpaceOnUse" x1="0" y1="0" x2="10" y2="0">
+<stop offset="0" stop-color="#ff0000" stop-opacity="0.502"/>
+<stop offset="1" stop-color="#000000"/>
+</linearGradient>
+</defs>"##));
+        assert_eq!(svg.matches(r#"fill="url(#g1)""#).count(), 2);
+
+        let mut conic = Gradient::new(GradientShape::Conic {
+            angle: 0.0,
+            x: 2.0,
+            y: 4.0,
+        });
+        conic.add_color_stop(0.0, Color::BLACK);
+        doc.fill_rect(rect, &PaintStyle::Gradient(conic), Transform::identity());
+        let svg = doc.finish();
+        assert!(svg.contains(r#"<clipPath id="c2"><path d="M1 2 L4 2 L4 6 L1 6 Z"/></clipPath>"#));
+        assert!(svg.contains(r#"<g clip-path="url(#c2)">"#));
+    }
+
+    #[test]
+    fn test_patterns() {
+        let mut doc = SvgDocument::new(20, 10, Color::TRANSPARENT, SvgTextMode::Text);
+        let mut pattern = Pattern {
+            image: tiny_skia::Pixmap::new(2, 2).unwrap(),
+            width: 4.0,
+            height: 4.0,
+            repetition: Repetition::Repeat,
+            transform: Transform::from_translate(1.0, 0.0),
+        };
+        let rect = Rect::from_xywh(0.0, 0.0, 10.0, 10.0).unwrap();
+        doc.fill_rect(rect, &PaintStyle::Pattern(pattern.clone()), Transform::identity());
+        pattern.repetition = Repetition::NoRepeat;
+        doc.fill_rect(rect, &PaintStyle::Pattern(pattern), Transform::from_scale(2.0, 2.0));
+        let svg = doc.finish();
+        assert_eq!(svg.matches("<pattern ").count(), 1);
+        assert!(svg.contains(r#"<pattern id="p1" patternUnits="userSpaceOnUse" width="4" height="4" patternTransform="matrix(1 0 0 1 1 0)">
+<image width="4" height="4" preserveAspectRatio="none" href="data:image/png;base64,"#));
+        assert!(svg.contains(r#"<clipPath id="c2"><path d="M1 0 L5 0 L5 4 L1 4 Z"/></clipPath>"#));
+        assert!(svg.contains(r#"<g clip-path="url(#c2)" transform="matrix(2 0 0 2 0 0)">
+<path d="M0 0 L10 0 L10 10 L0 10 Z" fill="url(#p1)"/>
+</g>"#));
+    }
+
+    #[test]
+    fn test_dash() {
+        let mut doc = SvgDocument::new(20, 10, Color::TRANSPARENT, SvgTextMode::Text);
+        let path = PathBuilder::from_rect(Rect::from_xywh(1.0, 1.0, 5.0, 5.0).unwrap());
+        let mut stroke = StrokeStyle {
+            dash: vec![4.0, 1.0],
+            dash_offset: 2.0,
+            ..StrokeStyle::default()
+        };
+        let black = PaintStyle::Color(Color::BLACK);
+        doc.stroke_path(&path, &black, &stroke, Transform::identity());
+        // All zero lengths are a solid line
+        stroke.dash = vec![0.0, 0.0];
+        doc.stroke_path(&path, &black, &stroke, Transform::identity());
+        let svg = doc.finish();
+        assert!(svg.contains(r#"stroke-dasharray="4 1" stroke-dashoffset="2""#));
+        assert_eq!(svg.matches("stroke-dasharray").count(), 1);
+    }
+
+    #[test]
+    fn test_line_cap_join() {
+        let mut doc = SvgDocument::new(20, 10, Color::TRANSPARENT, SvgTextMode::Text);
+        let path = PathBuilder::from_rect(Rect::from_xywh(1.0, 1.0, 5.0, 5.0).unwrap());
+        let mut stroke = StrokeStyle {
+            line_cap: LineCap::Round,
+            line_join: LineJoin::Bevel,
+            ..StrokeStyle::default()
+        };
+        let black = PaintStyle::Color(Color::BLACK);
+        doc.stroke_path(&path, &black, &stroke, Transform::identity());
+        stroke.line_join = LineJoin::Miter;
+        stroke.miter_limit = 4.0;
+        doc.stroke_path(&path, &black, &stroke, Transform::identity());
+        let svg = doc.finish();
+        assert!(svg.contains(r#"stroke-width="1" stroke-linecap="round" stroke-linejoin="bevel""#));
+        assert!(svg.contains(r#"stroke-width="1" stroke-linecap="round" stroke-miterlimit="4""#));
+    }
+
+    #[test]
+    fn test_clip() {
+        let mut doc = SvgDocument::new(20, 10, Color::TRANSPARENT, SvgTextMode::Text);
+        let rect = Rect::from_xywh(0.0, 0.0, 10.0, 10.0).unwrap();
+        let clip = ClipPath {
+            path: PathBuilder::from_rect(Rect::from_xywh(1.0, 1.0, 2.0, 2.0).unwrap()),
+            fill_rule: FillRule::EvenOdd,
+            transform: Transform::from_scale(2.0, 2.0),
+        };
+        let fill = |clip: Vec<ClipPath>| DrawCommand::FillRect {
+            rect,
+            paint: PaintStyle::Color(Color::BLACK),
+            transform: Transform::identity(),
+            clip,
+            compositing: Compositing::default(),
+        };
+        let fonts = FontLibrary::new();
+        doc.draw(&fill(vec![clip.clone()]), &fonts);
+        doc.draw(&fill(vec![clip.clone()]), &fonts);
+        doc.draw(&fill(vec![clip.clone(), clip.clone()]), &fonts);
+        let svg = doc.finish();
+        assert!(svg.contains(r#"<clipPath id="c1"><path d="M1 1 L3 1 L3 3 L1 3 Z" clip-rule="evenodd" transform="matrix(2 0 0 2 0 0)"/></clipPath>"#));
+        assert_eq!(svg.matches("<clipPath").count(), 1);
+        assert_eq!(svg.matches(r#"<g clip-path="url(#c1)">"#).count(), 2);
+        assert!(svg.ends_with("</g>\n</g>\n</svg>\n"));
+        doc.draw(&fill(vec![]), &fonts);
+        assert_eq!(doc.finish().matches("</g>").count(), 2);
+        assert!(doc.finish().ends_with("/>\n</svg>\n"));
+    }
+
+    #[test]
+    fn test_compositing() {
+        let mut doc = SvgDocument::new(20, 10, Color::TRANSPARENT, SvgTextMode::Text);
+        let fill = |compositing| DrawCommand::FillRect {
+            rect: Rect::from_xywh(0.0, 0.0, 10.0, 10.0).unwrap(),
+            paint: PaintStyle::Color(Color::BLACK),
+            transform: Transform::identity(),
+            clip: vec![],
+            compositing,
+        };
+        let fonts = FontLibrary::new();
+        doc.draw(
+            &fill(Compositing {
+                alpha: 0.5,
+                blend_mode: BlendMode::Multiply,
+                ..Compositing::default()
+            }),
+            &fonts,
+        );
+        doc.draw(
+            &fill(Compositing {
+                alpha: 1.0,
+                blend_mode: BlendMode::DestinationOut,
+                ..Compositing::default()
+            }),
+            &fonts,
+        );
+        let svg = doc.finish();
+        assert!(svg.contains(r##"<g opacity="0.5" style="mix-blend-mode:multiply">
+<path d="M0 0 L10 0 L10 10 L0 10 Z" fill="#000000"/>
+</g>"##));
+        assert_eq!(svg.matches("<g").count(), 1);
+    }
+
+    #[test]
+    fn test_shadow() {
+        let mut doc = SvgDocument::new(20, 10, Color::TRANSPARENT, SvgTextMode::Text);
+        let fill = DrawCommand::FillRect {
+            rect: Rect::from_xywh(0.0, 0.0, 10.0, 10.0).unwrap(),
+            paint: PaintStyle::Color(Color::BLACK),
+            transform: Transform::from_scale(2.0, 2.0),
+            clip: vec![],
+            compositing: Compositing {
+                shadow: Some(Shadow {
+                    color: Color::from_rgba8(255, 0, 0, 128),
+                    blur: 4.0,
+                    offset_x: 2.0,
+                    offset_y: 3.0,
+                }),
+                ..Compositing::default()
+            },
+        };
+        let fonts = FontLibrary::new();
+        doc.draw(&fill, &fonts);
+        doc.draw(&fill, &fonts);
+        let svg = doc.finish();
+        assert!(svg.contains(r##"<filter id="f1" filterUnits="userSpaceOnUse" x="0" y="0" width="20" height="10" color-interpolation-filters="sRGB">
+<feDropShadow dx="2" dy="3" stdDeviation="2" flood-color="#ff0000" flood-opacity="0.502"/>
+</filter>"##));
+        assert_eq!(svg.matches("<filter").count(), 1);
+        assert_eq!(svg.matches(r#"<g filter="url(#f1)">"#).count(), 2);
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut doc = SvgDocument::new(20, 10, Color::TRANSPARENT, SvgTextMode::Text);
+        doc.draw(
+            &DrawCommand::FillRect {
+                rect: Rect::from_xywh(0.0, 0.0, 10.0, 10.0).unwrap(),
+                paint: PaintStyle::Color(Color::BLACK),
+                transform: Transform::identity(),
+                clip: vec![],
+                compositing: Compositing {
+                    alpha: 0.5,
+                    filter: vec![
+                        Filter::Blur(1.5),
+                        Filter::Grayscale(1.0),
+                        Filter::Opacity(0.5),
+                        Filter::Invert(0.25),
+                    ],
+                    ..Compositing::default()
+                },
+            },
+            &FontLibrary::new(),
+        );
+        let svg = doc.finish();
+        assert!(svg.contains(r#"color-interpolation-filters="sRGB">
+<feGaussianBlur stdDeviation="1.5"/>
+<feColorMatrix type="saturate" values="0"/>
+<feComponentTransfer><feFuncA type="linear" slope="0.5"/></feComponentTransfer>
+<feComponentTransfer><feFuncR type="table" tableValues="0.25 0.75"/><feFuncG type="table" tableValues="0.25 0.75"/><feFuncB type="table" tableValues="0.25 0.75"/></feComponentTransfer>
+</filter>"#));
+        assert!(svg.contains(r#"<g opacity="0.5" filter="url(#f1)">"#));
+    }
+}
@@ -0,0 +1,648 @@
+//
+// Traces of calls made on a DrawContext
+//
+// A trace records each drawing call made by JavaScript with its arguments and
+// the drawing state at the time of the call. Replaying a trace makes the same
+// calls on a fresh DrawContext, so a render can be reproduced without QuickJS
+// or VexFlow. Reads (property getters, getTransform, measureText, getImageData,
+// isPointInPath) and savePng are not recorded since they do not change the
+// drawing.
+//
+
+use crate::context::DrawContext;
+use crate::display_list::{path_serde, DisplayList};
+use crate::image::{parse_png_data_url, ImageSource};
+use crate::paint::StyleValue;
+use crate::path::{CornerRadii, PathArg, PathOr};
+use serde::{Deserialize, Serialize};
+use rquickjs::function::{Opt, Rest};
+use serde_json::Value;
+use tiny_skia::{Color, PathBuilder, Pixmap, Rect};
+
+/// Drawing state at the time of a call, in the same form JS sees it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TraceState {
+    pub line_width: f64,
+    #[serde(default)]
+    pub line_dash: Vec<f64>,
+    #[serde(default)]
+    pub line_dash_offset: f64,
+    #[serde(default = "default_line_cap")]
+    pub line_cap: String,
+    #[serde(default = "default_line_join")]
+    pub line_join: String,
+    #[serde(default = "default_miter_limit")]
+    pub miter_limit: f64,
+    pub fill_style: String,
+    pub stroke_style: String,
+    #[serde(default = "default_global_alpha")]
+    pub global_alpha: f64,
+    #[serde(default = "default_global_composite_operation")]
+    pub global_composite_operation: String,
+    #[serde(default = "default_shadow_color")]
+    pub shadow_color: String,
+    #[serde(default)]
+    pub shadow_blur: f64,
+    #[serde(default)]
+    pub shadow_offset_x: f64,
+    #[serde(default)]
+    pub shadow_offset_y: f64,
+    #[serde(default = "default_filter")]
+    pub filter: String,
+    pub font: String,
+    #[serde(default = "default_text_align")]
+    pub text_align: String,
+    #[serde(default = "default_text_baseline")]
+    pub text_baseline: String,
+    #[serde(default = "default_direction")]
+    pub direction: String,
+    /// Transform as [sx, kx, ky, sy, tx, ty], like getTransform()
+    pub transform: Vec<f64>,
+    /// Depth of save/restore stack
+    pub stack_depth: usize,
+}
+
+fn default_line_cap() -> String {
+    return "butt".to_string();
+}
+
+fn default_line_join() -> String {
+    return "miter".to_string();
+}
+
+fn default_miter_limit() -> f64 {
+    return 10.0;
+}
+
+fn default_global_alpha() -> f64 {
+    return 1.0;
+}
+
+fn default_global_composite_operation() -> String {
+    return "source-over".to_string();
+}
+
+fn default_shadow_color() -> String {
+    return "#00000000".to_string();
+}
+
+fn default_filter() -> String {
+    return "none".to_string();
+}
+
+fn default_text_align() -> String {
+    return "start".to_string();
+}
+
+fn default_text_baseline() -> String {
+    return "alphabetic".to_string();
+}
+
+fn default_direction() -> String {
+    return "inherit".to_string();
+}
+
+/// One call made on a DrawContext
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct TraceCall {
+    /// JavaScript name of method or property set
+    pub method: String,
+    pub args: Vec<Value>,
+    /// Drawing state just before the call
+    pub state: TraceState,
+}
+
+/// Every call made on a DrawContext, with what is needed to create it again
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Trace {
+    pub width: u32,
+    pub height: u32,
+    pub zoom: f64,
+    pub background: String,
+    pub foreground: String,
+    pub(crate) calls: Vec<TraceCall>,
+}
+
+/// Argument of call as a number
+///
+/// JSON has no NaN or infinity, they are written as null and read back as NaN.
+fn number(call: &TraceCall, index: usize) -> Result<f64, String> {
+    if let Some(Value::Null) = call.args.get(index) {
+        return Ok(f64::NAN);
+    }
+    return call.args.get(index).and_then(Value::as_f64).ok_or_else(|| {
+        format!("{}: argument {} should be a number", call.method, index + 1)
+    });
+}
+
+/// Argument of call as a string
+fn string(call: &TraceCall, index: usize) -> Result<String, String> {
+    return call
+        .args
+        .get(index)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| format!("{}: argument {} should be a string", call.method, index + 1));
+}
+
+/// Argument of call as a fill or stroke style, a color string or gradient object
+fn style(call: &TraceCall, index: usize) -> Result<StyleValue, String> {
+    let value = call.args.get(index).cloned().unwrap_or(Value::Null);
+    return serde_json::from_value(value).map_err(|_| {
+        format!("{}: argument {} should be a color or gradient", call.method, index + 1)
+    });
+}
+
+/// Argument of call as an image with its size
+fn image(call: &TraceCall, index: usize) -> Result<ImageSource, String> {
+    let value = call.args.get(index).cloned().unwrap_or(Value::Null);
+    return serde_json::from_value(value)
+        .map_err(|_| format!("{}: argument {} should be an image", call.method, index + 1));
+}
+
+/// Argument of call as an image given as PNG data URL
+fn pixmap(call: &TraceCall, index: usize) -> Result<Pixmap, String> {
+    return call
+        .args
+        .get(index)
+        .and_then(Value::as_str)
+        .and_then(parse_png_data_url)
+        .ok_or_else(|| format!("{}: argument {} should be an image", call.method, index + 1));
+}
+
+/// Argument of call as a boolean, missing means false like in JS
+fn boolean(call: &TraceCall, index: usize) -> Result<bool, String> {
+    return match call.args.get(index) {
+        None | Some(Value::Null) => Ok(false),
+        Some(Value::Bool(b)) => Ok(*b),
+        _ => Err(format!("{}: argument {} should be a boolean", call.method, index + 1)),
+    };
+}
+
+/// Argument of call as list of corner radii, each [x, y]
+fn corner_radii(call: &TraceCall, index: usize) -> Result<CornerRadii, String> {
+    let error = || format!("{}: argument {} should be a list of [x, y] radii", call.method, index + 1);
+    let list = call.args.get(index).and_then(Value::as_array).ok_or_else(error)?;
+    let radius = |v: &Value| {
+        let pair = v.as_array().filter(|pair| pair.len() == 2).ok_or_else(error)?;
+        let x = pair[0].as_f64().ok_or_else(error)?;
+        let y = pair[1].as_f64().ok_or_else(error)?;
+        return Ok((x, y));
+    };
+    return Ok(CornerRadii(list.iter().map(radius).collect::<Result<_, String>>()?));
+}
+
+/// Path2D given to a call as trace argument, a list of segments like paths
+/// in display lists, empty for an empty path
+pub(crate) fn path_arg(builder: &PathBuilder) -> Value {
+    return match builder.clone().finish() {
+        Some(path) => path_serde::serialize(&path, serde_json::value::Serializer)
+            .expect("Path can always be serialized"),
+        None => Value::Array(vec![]),
+    };
+}
+
+/// Argument of call as a path written by path_arg()
+fn path(call: &TraceCall, index: usize) -> Result<PathBuilder, String> {
+    let error = || format!("{}: argument {} should be a path", call.method, index + 1);
+    let Some(value) = call.args.get(index).filter(|v| v.is_array()) else {
+        return Err(error());
+    };
+    if value.as_array().is_some_and(Vec::is_empty) {
+        return Ok(PathBuilder::new());
+    }
+    let path = path_serde::deserialize(value.clone()).map_err(|_| error())?;
+    let mut builder = PathBuilder::new();
+    builder.push_path(&path);
+    return Ok(builder);
+}
+
+/// Arguments of fill() or clip(), an optional path then an optional fill rule
+fn path_and_fill_rule(call: &TraceCall) -> Result<(Opt<PathOr<String>>, Opt<String>), String> {
+    return match call.args.first() {
+        None => Ok((Opt(None), Opt(None))),
+        Some(Value::Array(_)) => {
+            let fill_rule = match call.args.len() {
+                1 => None,
+                _ => Some(string(call, 1)?),
+            };
+            Ok((Opt(Some(PathOr::Path(path(call, 0)?))), Opt(fill_rule)))
+        }
+        Some(_) => Ok((Opt(Some(PathOr::Other(string(call, 0)?))), Opt(None))),
+    };
+}
+
+/// Argument of call as a list of numbers
+fn numbers(call: &TraceCall, index: usize) -> Result<Vec<f64>, String> {
+    let error = || format!("{}: argument {} should be a list of numbers", call.method, index + 1);
+    let list = call.args.get(index).and_then(Value::as_array).ok_or_else(error)?;
+    return list.iter().map(|v| v.as_f64().ok_or_else(error)).collect();
+}
+
+impl Trace {
+    pub(crate) fn new(
+        width: u32,
+        height: u32,
+        zoom: f64,
+        background: String,
+        foreground: String,
+    ) -> Self {
+        Trace {
+            width,
+            height,
+            zoom,
+            background,
+            foreground,
+            calls: vec![],
+        }
+    }
+
+    /// Serialize as JSON
+    pub fn to_json(&self) -> String {
+        return serde_json::to_string_pretty(self).expect("Trace can always be serialized");
+    }
+
+    /// Load from JSON written by to_json()
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        return serde_json::from_str(json);
+    }
+
+    /// Make all calls again and return what they draw.
+    ///
+    /// Traces are user input, so bad arguments are errors instead of panics.
+    pub fn replay(&self) -> Result<DisplayList, String> {
+        return Ok(self.replay_context()?.display_list().clone());
+    }
+
+    /// Make all calls on a new DrawContext and return it.
+    pub(crate) fn replay_context(&self) -> Result<DrawContext, String> {
+        let mut context = DrawContext::try_new(
+            self.width,
+            self.height,
+            self.zoom,
+            self.background.clone(),
+            self.foreground.clone(),
+        )?;
+        for (index, call) in self.calls.iter().enumerate() {
+            replay_call(&mut context, call).map_err(|e| format!("Call {}: {}", index, e))?;
+        }
+        return Ok(context);
+    }
+}
+
+/// Argument of call as a transform, 6 numbers like getTransform()
+fn transform(call: &TraceCall, index: usize) -> Result<Vec<f64>, String> {
+    let t = numbers(call, index)?;
+    if t.len() != 6 {
+        return Err(format!("{}: argument {} should be 6 numbers", call.method, index + 1));
+    }
+    return Ok(t);
+}
+
+/// Whether call adds to or draws the current path rather than a Path2D
+fn uses_current_path(call: &TraceCall) -> bool {
+    return match call.method.as_str() {
+        "moveTo" | "lineTo" | "closePath" | "quadraticCurveTo" | "bezierCurveTo" | "arc"
+        | "ellipse" | "arcTo" | "roundRect" | "rect" => true,
+        "stroke" => call.args.is_empty(),
+        "fill" | "clip" => !matches!(call.args.first(), Some(Value::Array(_))),
+        _ => false,
+    };
+}
+
+/// Make one traced call on context.
+fn replay_call(context: &mut DrawContext, call: &TraceCall) -> Result<(), String> {
+    let n = |index| number(call, index);
+    if uses_current_path(call) && !context.has_path() {
+        return Err(format!("{}: no current path, call beginPath() first", call.method));
+    }
+    match call.method.as_str() {
+        "fillStyle" => context.set_fill_style(style(call, 0)?),
+        "strokeStyle" => context.set_stroke_style(style(call, 0)?),
+        "lineWidth" => {
+            let width = n(0)?;
+            if !width.is_finite() || width < 0.0 {
+                return Err("lineWidth: width should be finite and not negative".to_string());
+            }
+            context.set_line_width(width);
+        }
+        "lineCap" => context.set_line_cap(string(call, 0)?),
+        "lineJoin" => context.set_line_join(string(call, 0)?),
+        "miterLimit" => context.set_miter_limit(n(0)?),
+        "globalAlpha" => context.set_global_alpha(n(0)?),
+        "globalCompositeOperation" => context.set_global_composite_operation(string(call, 0)?),
+        "shadowColor" => context.set_shadow_color(string(call, 0)?),
+        "shadowBlur" => context.set_shadow_blur(n(0)?),
+        "shadowOffsetX" => context.set_shadow_offset_x(n(0)?),
+        "shadowOffsetY" => context.set_shadow_offset_y(n(0)?),
+        "filter" => context.set_filter(string(call, 0)?),
+        "font" => context.set_font(string(call, 0)?),
+        "textAlign" => context.set_text_align(string(call, 0)?),
+        "textBaseline" => context.set_text_baseline(string(call, 0)?),
+        "direction" => context.set_direction(string(call, 0)?),
+        "setTransform" => context.set_transform(transform(call, 0)?),
+        "scale" => context.scale(n(0)?, n(1)?),
+        "translate" => context.translate(n(0)?, n(1)?),
+        "rotate" => context.rotate(n(0)?),
+        "fillText" => context.fill_text(string(call, 0)?, n(1)?, n(2)?),
+        "strokeText" => context.stroke_text(string(call, 0)?, n(1)?, n(2)?),
+        "beginPath" => context.begin_path(),
+        "moveTo" => context.move_to(n(0)?, n(1)?),
+        "lineTo" => context.line_to(n(0)?, n(1)?),
+        "closePath" => context.close_path(),
+        "quadraticCurveTo" => context.quadratic_curve_to(n(0)?, n(1)?, n(2)?, n(3)?),
+        "bezierCurveTo" => {
+            context.bezier_curve_to(n(0)?, n(1)?, n(2)?, n(3)?, n(4)?, n(5)?)
+        }
+        "arc" => context.arc(n(0)?, n(1)?, n(2)?, n(3)?, n(4)?, Opt(Some(boolean(call, 5)?))),
+        "ellipse" => context.ellipse(
+            n(0)?,
+            n(1)?,
+            n(2)?,
+            n(3)?,
+            n(4)?,
+            n(5)?,
+            n(6)?,
+            Opt(Some(boolean(call, 7)?)),
+        ),
+        "arcTo" => context.arc_to(n(0)?, n(1)?, n(2)?, n(3)?, n(4)?),
+        "roundRect" => {
+            context.round_rect(n(0)?, n(1)?, n(2)?, n(3)?, Opt(Some(corner_radii(call, 4)?)))
+        }
+        "rect" => context.rect(n(0)?, n(1)?, n(2)?, n(3)?),
+        "stroke" => {
+            let path = if call.args.is_empty() {
+                None
+            } else {
+                Some(PathArg(path(call, 0)?))
+            };
+            context.stroke(Opt(path));
+        }
+        "fill" => {
+            let (path, fill_rule) = path_and_fill_rule(call)?;
+            context.fill(path, fill_rule);
+        }
+        "clip" => {
+            let (path, fill_rule) = path_and_fill_rule(call)?;
+            context.clip(path, fill_rule);
+        }
+        "fillRect" => {
+            let (x, y, width, height) = (n(0)?, n(1)?, n(2)?, n(3)?);
+            // Negative sizes are fine, fillRect() flips the rect
+            let (w, h) = (width.abs() as f32, height.abs() as f32);
+            if Rect::from_xywh(x as f32, y as f32, w, h).is_none() {
+                return Err("fillRect: position and size should be finite".to_string());
+            }
+            context.fill_rect(x, y, width, height);
+        }
+        "clearRect" => {
+            let (x, y, width, height) = (n(0)?, n(1)?, n(2)?, n(3)?);
+            if Rect::from_xywh(x as f32, y as f32, width as f32, height as f32).is_none() {
+                return Err("clearRect: size should be finite and not negative".to_string());
+            }
+            context.clear_rect(x, y, width, height);
+        }
+        "clear" => {
+            let (r, g, b, a) = (n(0)?, n(1)?, n(2)?, n(3)?);
+            if Color::from_rgba(r as f32, g as f32, b as f32, a as f32).is_none() {
+                return Err("clear: color components should be from 0 to 1".to_string());
+            }
+            context.clear(r, g, b, a);
+        }
+        "putImageData" => context.put_pixmap(pixmap(call, 0)?, n(1)?, n(2)?),
+        "drawImage" => {
+            let args = (1..call.args.len()).map(n).collect::<Result<_, _>>()?;
+            context.draw_image(image(call, 0)?, Rest(args));
+        }
+        "setLineDash" => context.set_line_dash(numbers(call, 0)?),
+        "lineDashOffset" => context.set_line_dash_offset(n(0)?),
+        "save" => context.save(),
+        "restore" => context.restore(),
+        other => return Err(format!("unknown method '{}'", other)),
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::parse_path_data;
+    use serde_json::json;
+
+    #[test]
+    fn test_replay() {
+        let mut context = DrawContext::new(20, 10, 1.0, "#fff".to_string(), "#000".to_string());
+        context.start_trace();
+        context.set_fill_style(StyleValue::Css("red".to_string()));
+        context.begin_path();
+        context.rect(1.0, 1.0, 5.0, 5.0);
+        context.fill(Opt(None), Opt(None));
+        let trace = context.trace().unwrap().clone();
+        assert_eq!(trace.calls.len(), 4);
+        assert_eq!(trace.calls[0].method, "fillStyle");
+        assert_eq!(trace.calls[0].args, vec![json!("red")]);
+        assert_eq!(trace.calls[0].state.fill_style, "#000000ff");
+        assert_eq!(trace.calls[1].state.fill_style, "#ff0000ff");
+
+        let loaded = Trace::from_json(&trace.to_json()).unwrap();
+        assert_eq!(loaded, trace);
+        let replayed = loaded.replay_context().unwrap();
+        assert_eq!(replayed.display_list(), context.display_list());
+        assert_eq!(replayed.surface().data(), context.surface().data());
+    }
+
+    /// Drawing calls made on a context to be traced
+    type Scenario = fn(&mut DrawContext);
+
+    /// Each case draws with one feature, which replay must reproduce exactly
+    #[test]
+    fn test_replay_features() {
+        let cases: Vec<(&str, Scenario)> = vec![
+            ("clip", |context| {
+                context.save();
+                context.begin_path();
+                context.rect(0.0, 0.0, 10.0, 10.0);
+                context.rect(2.0, 2.0, 4.0, 4.0);
+                context.clip(Opt(Some(PathOr::Other("evenodd".to_string()))), Opt(None));
+                context.fill_rect(0.0, 0.0, 20.0, 10.0);
+                context.restore();
+                context.fill_rect(15.0, 0.0, 5.0, 10.0);
+            }),
+            ("compositing", |context| {
+                context.set_global_alpha(0.5);
+                context.fill_rect(0.0, 0.0, 10.0, 10.0);
+                context.set_global_alpha(1.0);
+                context.set_global_composite_operation("destination-out".to_string());
+                context.fill_rect(15.0, 0.0, 5.0, 10.0);
+            }),
+            ("shadow", |context| {
+                context.set_shadow_color("#ff0000".to_string());
+                context.set_shadow_offset_x(5.0);
+                context.fill_rect(0.0, 0.0, 5.0, 5.0);
+                context.set_shadow_blur(2.0);
+                context.set_shadow_offset_y(-1.0);
+                context.fill_rect(0.0, 5.0, 5.0, 5.0);
+            }),
+            ("filter", |context| {
+                context.set_filter("grayscale(100%) opacity(0.5)".to_string());
+                context.fill_rect(0.0, 0.0, 5.0, 10.0);
+                context.set_filter("blur(1px) drop-shadow(2px 2px 1px red)".to_string());
+                context.fill_rect(10.0, 0.0, 5.0, 5.0);
+            }),
+            ("strokeText", |context| {
+                context.set_font("10pt Bravura".to_string());
+                context.set_line_width(2.0);
+                context.set_stroke_style(StyleValue::Css("#00f".to_string()));
+                context.stroke_text("\u{e0a4}".to_string(), 2.0, 5.0);
+            }),
+            ("textAlign", |context| {
+                context.set_font("8pt Academico".to_string());
+                context.set_text_align("center".to_string());
+                context.set_text_baseline("middle".to_string());
+                context.fill_text("Hi".to_string(), 10.0, 5.0);
+                context.set_text_align("start".to_string());
+                context.set_direction("rtl".to_string());
+                context.stroke_text("Hi".to_string(), 20.0, 10.0);
+            }),
+            ("drawImage", |context| {
+                let mut image = DrawContext::new(4, 4, 1.0, "#00f".to_string(), "#000".to_string());
+                image.set_fill_style(StyleValue::Css("#f00".to_string()));
+                image.fill_rect(0.0, 0.0, 2.0, 2.0);
+                context.draw_image(image.image_source(), Rest(vec![1.0, 1.0]));
+                context.draw_image(image.image_source(), Rest(vec![10.0, 0.0, 8.0, 8.0]));
+                context.draw_image(
+                    image.image_source(),
+                    Rest(vec![2.0, 0.0, 2.0, 2.0, 6.0, 1.0]),
+                );
+            }),
+            ("putImageData", |context| {
+                context.set_global_alpha(0.5);
+                let mut pixmap = Pixmap::new(4, 2).unwrap();
+                pixmap.fill(Color::from_rgba8(0, 0, 255, 128));
+                context.put_pixmap(pixmap, 5.0, 6.0);
+                context.fill_rect(0.0, 0.0, 5.0, 5.0);
+            }),
+        ];
+        for (name, draw) in cases {
+            let mut context = DrawContext::new(20, 10, 2.0, "#fff".to_string(), "#000".to_string());
+            context.start_trace();
+            draw(&mut context);
+            let trace = Trace::from_json(&context.trace().unwrap().to_json()).unwrap();
+            let replayed = trace
+                .replay_context()
+                .unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert_eq!(replayed.display_list(), context.display_list(), "{}", name);
+            assert_eq!(
+                replayed.surface().data(),
+                context.surface().data(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_replay_path2d() {
+        let mut context = DrawContext::new(20, 10, 1.0, "#fff".to_string(), "#000".to_string());
+        context.start_trace();
+        let square = parse_path_data("M0 0h4v4h-4z");
+        context.fill(Opt(Some(PathOr::Path(square.clone()))), Opt(None));
+        context.translate(10.0, 0.0);
+        context.stroke(Opt(Some(PathArg(square))));
+        context.stroke(Opt(Some(PathArg(PathBuilder::new()))));
+        let trace = context.trace().unwrap().clone();
+        assert_eq!(
+            trace.calls[0].args,
+            vec![
+                json!([{"M": [0.0, 0.0]}, {"L": [4.0, 0.0]}, {"L": [4.0, 4.0]}, {"L": [0.0, 4.0]}, "Z"]),
+                json!("nonzero")
+            ]
+        );
+        assert_eq!(trace.calls[3].args, vec![json!([])]);
+        assert_eq!(context.display_list().commands.len(), 3);
+        let replayed = trace.replay_context().unwrap();
+        assert_eq!(replayed.display_list(), context.display_list());
+        assert_eq!(replayed.surface().data(), context.surface().data());
+
+        // Traces from before Path2D have fill() without arguments.
+        let mut trace = trace;
+        let begin_path = TraceCall {
+            method: "beginPath".to_string(),
+            args: vec![],
+            state: trace.calls[0].state.clone(),
+        };
+        trace.calls.insert(0, begin_path);
+        trace.calls[1].args = vec![];
+        assert!(trace.replay_context().is_ok());
+    }
+
+    #[test]
+    fn test_replay_errors() {
+        let mut trace = Trace::new(2, 2, 1.0, "#fff".to_string(), "#000".to_string());
+        let state = TraceState {
+            line_width: 1.0,
+            line_dash: vec![],
+            line_dash_offset: 0.0,
+            line_cap: default_line_cap(),
+            line_join: default_line_join(),
+            miter_limit: default_miter_limit(),
+            fill_style: "#000000ff".to_string(),
+            stroke_style: "#000000ff".to_string(),
+            global_alpha: default_global_alpha(),
+            global_composite_operation: default_global_composite_operation(),
+            shadow_color: default_shadow_color(),
+            shadow_blur: 0.0,
+            shadow_offset_x: 0.0,
+            shadow_offset_y: 0.0,
+            filter: default_filter(),
+            font: "7pt".to_string(),
+            text_align: default_text_align(),
+            text_baseline: default_text_baseline(),
+            direction: default_direction(),
+            transform: vec![1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+            stack_depth: 0,
+        };
+        trace.calls.push(TraceCall {
+            method: "lineWidth".to_string(),
+            args: vec![json!("wide")],
+            state: state.clone(),
+        });
+        assert_eq!(
+            trace.replay().err().unwrap(),
+            "Call 0: lineWidth: argument 1 should be a number"
+        );
+        let call = |method: &str, args: Value| TraceCall {
+            method: method.to_string(),
+            args: args.as_array().unwrap().clone(),
+            state: state.clone(),
+        };
+        trace.calls[0] = call("drawFish", json!([]));
+        assert_eq!(trace.replay().err().unwrap(), "Call 0: unknown method 'drawFish'");
+        let bad_calls = [
+            (call("setTransform", json!([[1, 0, 0]])), "setTransform: argument 1 should be 6 numbers"),
+            (call("lineTo", json!([1, 2])), "lineTo: no current path, call beginPath() first"),
+            (call("fill", json!(["evenodd"])), "fill: no current path, call beginPath() first"),
+            (call("stroke", json!([])), "stroke: no current path, call beginPath() first"),
+            (call("lineWidth", json!([-1])), "lineWidth: width should be finite and not negative"),
+            (call("fillRect", json!([0, null, 1, 1])), "fillRect: position and size should be finite"),
+            (call("clear", json!([2, 0, 0, 1])), "clear: color components should be from 0 to 1"),
+            (call("clearRect", json!([0, 0, -1, 1])), "clearRect: size should be finite and not negative"),
+            (call("clearRect", json!([0, 0, null, 1])), "clearRect: size should be finite and not negative"),
+        ];
+        for (bad, error) in bad_calls {
+            trace.calls = vec![bad];
+            assert_eq!(trace.replay().err().unwrap(), format!("Call 0: {}", error));
+        }
+        // Path2D arguments do not need a current path
+        trace.calls = vec![call("fill", json!([[{"M": [0, 0]}, {"L": [1, 1]}, {"L": [0, 1]}]]))];
+        assert!(trace.replay().is_ok());
+
+        trace.calls = vec![];
+        trace.background = "#xyz".to_string();
+        assert_eq!(trace.replay().err().unwrap(), "Invalid background color '#xyz'");
+        trace.background = "#fff".to_string();
+        trace.width = 0;
+        assert_eq!(trace.replay().err().unwrap(), "Could not create surface of size 0x2");
+    }
+}
@@ -5,9 +5,9 @@ try {
     let { VexFlow } = await import('@vexflow-debug-with-tests');
 
     async function main() {
-        console.log(`Running tests`);
-        VexFlow.Test.run();
-        console.log(`test result: \x1b[1m${QUnit.passed}\x1b[0m tests passed.`);
+        const { jobs, job } = testOptions;
+        VexFlow.Test.run({ jobs, job });
+        report_test_result(QUnit.passed, QUnit.filtered);
     }
     await main();
 } catch(err) {
@@ -48,6 +48,7 @@ if (!globalThis.QUnit) {
         moduleName: '',
         testName: '',
         passed: 0,
+        filtered: 0,
 
         assertions: {
             ok: () => true,
@@ -69,10 +70,20 @@ if (!globalThis.QUnit) {
 
         // See: https://api.qunitjs.com/QUnit/test/
         test(testName, callback) {
+            const fullName = `${QUMock.moduleName}::${testName}`;
+            if (!is_test_selected(fullName)) {
+                QUMock.filtered++;
+                return;
+            }
+            if (testOptions.list) {
+                print(`${fullName}: test\n`);
+                QUMock.passed++;
+                return;
+            }
             QUMock.testName = testName;
             QUMock.assertions.test.module.name = QUMock.moduleName;
             // Print out the progress and keep it on a single line.
-            print(`test ${QUMock.moduleName}::${testName} ... `);
+            print(`test ${fullName} ... `);
             callback(QUMock.assertions);
             print('\x1b[92mok\x1b[39m\n');
             QUMock.passed++;
@@ -113,6 +124,11 @@ globalThis.document = {
             const canvas = new Canvas(1, 1, 1.0, '#fff', '#000', false);
             return canvas;
         }
+        if (t === 'img') {
+            console.debug(`createElement('img')`);
+            // Images load a PNG file as soon as src is set
+            return new Image();
+        }
         throw new Error(`Cannot create element '${t}', not supported`);
     }
 };
@@ -128,6 +144,9 @@ export class Canvas {
     getContext() {
         return this.drawContext;
     }
+    saveFile(filename) {
+        this.drawContext.savePng(filename);
+    }
     // Need to have toDataURL for type detection to pass
     toDataURL() {
         return "<URL>";